@@ -0,0 +1,106 @@
+/*!
+A tiny command line parser shared by the tools in this crate.
+
+It knows about positional arguments, boolean switches (like `--outputs`) and
+options that take a value (like `--distance 2` or `--distance=2`). Everything
+after a lone `--` is positional.
+*/
+
+use std::str::FromStr;
+
+use Result;
+
+/// Parsed command line arguments.
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+    positional: Vec<String>,
+    switches: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+impl Args {
+    /// Parse the given arguments, not including the program name.
+    ///
+    /// `switches` and `options` list the names (without the leading `--`) of
+    /// the flags that are accepted. Any other flag is an error.
+    pub fn parse<I>(
+        argv: I,
+        switches: &[&str],
+        options: &[&str],
+    ) -> Result<Args>
+    where I: IntoIterator<Item=String> {
+        let mut args = Args::default();
+        let mut it = argv.into_iter();
+        while let Some(arg) = it.next() {
+            if arg == "--" {
+                args.positional.extend(it);
+                break;
+            }
+            if !arg.starts_with("--") || arg.len() == 2 {
+                args.positional.push(arg);
+                continue;
+            }
+            let (name, value) = match arg[2..].find('=') {
+                None => (arg[2..].to_string(), None),
+                Some(i) => {
+                    (arg[2..2 + i].to_string(), Some(arg[3 + i..].to_string()))
+                }
+            };
+            if switches.contains(&&*name) {
+                if value.is_some() {
+                    return Err(From::from(format!(
+                        "flag --{} does not take a value", name)));
+                }
+                args.switches.push(name);
+            } else if options.contains(&&*name) {
+                let value = match value.or_else(|| it.next()) {
+                    Some(value) => value,
+                    None => return Err(From::from(format!(
+                        "flag --{} requires a value", name))),
+                };
+                args.options.push((name, value));
+            } else {
+                return Err(From::from(format!("unrecognized flag --{}", name)));
+            }
+        }
+        Ok(args)
+    }
+
+    /// Returns true if and only if the given switch was given.
+    pub fn switch(&self, name: &str) -> bool {
+        self.switches.iter().any(|s| s == name)
+    }
+
+    /// Returns the value of the given option, if it was given.
+    ///
+    /// If the option was given more than once, the last value wins.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.options.iter().rev()
+            .find(|&&(ref n, _)| n == name)
+            .map(|&(_, ref v)| &**v)
+    }
+
+    /// Returns the value of the given option parsed as a `T`, or `default`
+    /// if the option wasn't given.
+    pub fn parsed_or<T: FromStr>(&self, name: &str, default: T) -> Result<T> {
+        match self.value(name) {
+            None => Ok(default),
+            Some(v) => v.parse().map_err(|_| From::from(format!(
+                "invalid value for --{}: {:?}", name, v))),
+        }
+    }
+
+    /// Returns all positional arguments.
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    /// Returns the positional argument at index `i`, or an error mentioning
+    /// `what` if there isn't one.
+    pub fn arg(&self, i: usize, what: &str) -> Result<&str> {
+        match self.positional.get(i) {
+            Some(arg) => Ok(arg),
+            None => Err(From::from(format!("missing argument: <{}>", what))),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+/*!
+Ranked autocompletion over a map from keys to popularity scores.
+
+Given what the user has typed so far, `Autocomplete` finds every key that
+starts with it (optionally allowing a few typos, and optionally at the start
+of any word in the key rather than just the start of the key) and returns the
+`k` matching keys with the highest scores.
+
+Exact prefix queries are answered with a range query, which visits only the
+keys that share the prefix. Fuzzy queries use a Levenshtein automaton that
+accepts any key with a *prefix* within the given edit distance of the query.
+In both cases, only the best `k` results are ever kept in memory.
+*/
+
+use fst::{Automaton, IntoStreamer, Map};
+use fst_levenshtein::Levenshtein;
+
+use {Result, prefix_end};
+use topk::TopK;
+use utf8::{Decoded, Decoder};
+
+/// A builder for an autocomplete query.
+#[derive(Clone, Debug)]
+pub struct Autocomplete<'a> {
+    query: &'a str,
+    distance: u32,
+    limit: usize,
+    word_starts: bool,
+}
+
+impl<'a> Autocomplete<'a> {
+    /// Create a new query for keys starting with `query`. By default, typos
+    /// aren't allowed and the best 10 results are returned.
+    pub fn new(query: &'a str) -> Autocomplete<'a> {
+        Autocomplete {
+            query: query,
+            distance: 0,
+            limit: 10,
+            word_starts: false,
+        }
+    }
+
+    /// Allow the prefix of a key to be up to `distance` edits away from the
+    /// query.
+    pub fn distance(mut self, distance: u32) -> Autocomplete<'a> {
+        self.distance = distance;
+        self
+    }
+
+    /// Return at most `limit` results.
+    pub fn limit(mut self, limit: usize) -> Autocomplete<'a> {
+        self.limit = limit;
+        self
+    }
+
+    /// When enabled, the query may match at the start of any word in a key
+    /// instead of only at the start of the key. This makes `spri` find
+    /// `bruce springsteen`. A word starts after any character that isn't
+    /// alphanumeric, as with `Subsequence::word_starts`.
+    ///
+    /// Note that this has to look at every key in the map.
+    pub fn word_starts(mut self, yes: bool) -> Autocomplete<'a> {
+        self.word_starts = yes;
+        self
+    }
+
+    /// Run this query against `map`, whose values are treated as scores.
+    ///
+    /// Results are returned in descending order of score. Ties are broken by
+    /// the lexicographic order of keys.
+    pub fn search(&self, map: &Map) -> Result<Vec<(Vec<u8>, u64)>> {
+        let mut top = TopK::new(self.limit);
+        if self.distance == 0 && !self.word_starts {
+            let mut range = map.range().ge(self.query);
+            if let Some(end) = prefix_end(self.query.as_bytes()) {
+                range = range.lt(end);
+            }
+            top.extend(range.into_stream());
+        } else {
+            let lev = Levenshtein::new(self.query, self.distance)?;
+            if self.word_starts {
+                top.extend(map.search(WordStarts(lev)).into_stream());
+            } else {
+                top.extend(map.search(lev.starts_with()).into_stream());
+            }
+        }
+        Ok(top.into_sorted_vec())
+    }
+}
+
+/// An automaton that matches any key where `A` matches a prefix of the key
+/// or a prefix of the key after any word boundary. A word boundary is any
+/// character that isn't alphanumeric, so words containing, e.g., accented
+/// letters aren't split.
+///
+/// This runs a separate copy of `A` from the start of every word seen so far
+/// (dropping copies that can no longer match), so it's only suitable for
+/// small automata like short Levenshtein queries.
+struct WordStarts<A>(A);
+
+/// `None` means a match was found. Otherwise, the states of every copy of
+/// the inner automaton that might still match, whether the next byte starts
+/// a word, and any partially decoded codepoint.
+struct WordStartsState<S>(Option<(Vec<S>, bool, Decoder)>);
+
+impl<A: Automaton> Automaton for WordStarts<A> {
+    type State = WordStartsState<A::State>;
+
+    fn start(&self) -> WordStartsState<A::State> {
+        let start = self.0.start();
+        if self.0.is_match(&start) {
+            WordStartsState(None)
+        } else {
+            WordStartsState(Some((vec![], true, Decoder::default())))
+        }
+    }
+
+    fn is_match(&self, state: &WordStartsState<A::State>) -> bool {
+        state.0.is_none()
+    }
+
+    fn will_always_match(&self, state: &WordStartsState<A::State>) -> bool {
+        state.0.is_none()
+    }
+
+    fn accept(
+        &self,
+        state: &WordStartsState<A::State>,
+        byte: u8,
+    ) -> WordStartsState<A::State> {
+        let (running, at_boundary, dec) = match state.0 {
+            None => return WordStartsState(None),
+            Some((ref running, at_boundary, dec)) => {
+                (running, at_boundary, dec)
+            }
+        };
+        let mut next = vec![];
+        let fresh = if at_boundary { Some(self.0.start()) } else { None };
+        for s in running.iter().chain(fresh.as_ref()) {
+            let s = self.0.accept(s, byte);
+            if self.0.is_match(&s) {
+                return WordStartsState(None);
+            }
+            if self.0.can_match(&s) {
+                next.push(s);
+            }
+        }
+        // A word can only start after a complete codepoint. Bytes that
+        // aren't valid UTF-8 don't start one either.
+        let (boundary, dec) = match dec.push(byte) {
+            Decoded::Char(c) => (!c.is_alphanumeric(), Decoder::default()),
+            Decoded::Incomplete(dec) => (false, dec),
+            Decoded::Invalid => (false, Decoder::default()),
+        };
+        WordStartsState(Some((next, boundary, dec)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fst::Map;
+
+    use super::Autocomplete;
+
+    fn complete(query: &str, distance: u32) -> Vec<String> {
+        let keys = [
+            "bruce springsteen", "café-olé", "naïve-ölfarbe", "x\u{a0}yz",
+            "zürich", "über alles",
+        ];
+        let map = Map::from_iter(keys.iter().map(|k| (k, 1))).unwrap();
+        let results = Autocomplete::new(query)
+            .distance(distance)
+            .word_starts(true)
+            .search(&map)
+            .unwrap();
+        let mut keys: Vec<String> = results.into_iter()
+            .map(|(key, _)| String::from_utf8(key).unwrap())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn word_starts() {
+        assert_eq!(complete("spri", 0), ["bruce springsteen"]);
+        assert_eq!(complete("spr", 1), ["bruce springsteen"]);
+        // Non-ASCII punctuation and spaces separate words, but letters
+        // don't.
+        assert_eq!(complete("olé", 0), ["café-olé"]);
+        assert_eq!(complete("ölf", 0), ["naïve-ölfarbe"]);
+        assert_eq!(complete("yz", 0), ["x\u{a0}yz"]);
+        assert!(complete("ve", 0).is_empty());
+        assert!(complete("rich", 0).is_empty());
+        assert_eq!(complete("alles", 0), ["über alles"]);
+        assert_eq!(complete("über", 0), ["über alles"]);
+    }
+}
@@ -0,0 +1,416 @@
+/*!
+`fst-bench` re-runs the comparisons from the "Query performance" section of
+the blog post: an FST set against a `BTreeSet`, a `HashSet` and a sorted
+`Vec`, on generated corpora of a few sizes.
+
+    cargo run --release --bin fst-bench -- --keys 10000,1000000 --csv out.csv
+
+Numbers from a debug build are meaningless.
+*/
+
+extern crate fst;
+extern crate fst_levenshtein;
+extern crate fst_regex;
+extern crate transducers;
+
+use std::collections::{BTreeSet, HashSet};
+use std::ops::Bound::{Included, Unbounded};
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::process;
+use std::time::{Duration, Instant};
+
+use fst::{Automaton, IntoStreamer, Set, Streamer};
+use fst_levenshtein::Levenshtein;
+use fst_regex::Regex;
+
+use transducers::{Result, matches, prefix_end};
+use transducers::args::Args;
+use transducers::corpus::{self, Rng};
+
+const USAGE: &'static str = "\
+Usage:
+    fst-bench [--keys N,...] [--queries N] [--slow-queries N] [--seed S]
+              [--csv FILE]
+
+For each corpus size, generate that many distinct dictionary-like words and
+load them into an FST set, a BTreeSet, a HashSet and a sorted Vec. Then time
+these queries against each:
+
+    build        Build the structure from the sorted words.
+    contains     Look up a key. Half of the keys looked up are in the set.
+    range        Find the first 100 keys greater than or equal to a key.
+    prefix       Find every key starting with the first three letters of a
+                 key.
+    regex        Find every key matching a regex like `ab.*c`.
+    levenshtein  Find every key within one edit of a key.
+
+Structures that can't answer a query directly answer it by looking at every
+key. Results are printed as a table, with the number of keys found by each
+kind of query, which should be the same for every structure.
+
+Options:
+    --keys N,...      Corpus sizes (default: 10000,100000,1000000).
+    --queries N       Queries of each kind to run (default: 10000).
+    --slow-queries N  Regex and Levenshtein queries to run, which are slow
+                      on everything but the FST (default: 10).
+    --seed S          Seed for the corpus and queries (default: 0).
+    --csv FILE        Also write the results to FILE as CSV.
+";
+
+fn main() {
+    if let Err(err) = run() {
+        let _ = writeln!(io::stderr(), "{}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let args = Args::parse(
+        env::args().skip(1),
+        &["help"],
+        &["keys", "queries", "slow-queries", "seed", "csv"],
+    )?;
+    if args.switch("help") {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+    let mut sizes = vec![];
+    let keys = args.value("keys").unwrap_or("10000,100000,1000000");
+    for size in keys.split(',') {
+        sizes.push(size.parse::<usize>()
+            .map_err(|_| format!("invalid corpus size {:?}", size))?);
+    }
+    let config = Config {
+        queries: args.parsed_or("queries", 10000)?,
+        slow_queries: args.parsed_or("slow-queries", 10)?,
+        seed: args.parsed_or("seed", 0)?,
+    };
+
+    let mut results = vec![];
+    for &size in &sizes {
+        results.extend(bench_size(&config, size)?);
+    }
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    writeln!(wtr, "{:>9}  {:<9}  {:<11}  {:>7}  {:>12}  {:>12}  {:>9}",
+             "keys", "structure", "query", "queries", "total (ms)",
+             "ns/query", "results")?;
+    for r in &results {
+        writeln!(wtr, "{:>9}  {:<9}  {:<11}  {:>7}  {:>12.3}  {:>12.0}  {:>9}",
+                 r.keys, r.structure, r.query, r.queries,
+                 nanos(r.total) as f64 / 1e6, r.per_query(), r.found)?;
+    }
+    wtr.flush()?;
+
+    if let Some(path) = args.value("csv") {
+        let mut wtr = io::BufWriter::new(File::create(path)?);
+        writeln!(wtr, "keys,structure,query,queries,total_ns,ns_per_query,\
+                       results")?;
+        for r in &results {
+            writeln!(wtr, "{},{},{},{},{},{:.1},{}",
+                     r.keys, r.structure, r.query, r.queries, nanos(r.total),
+                     r.per_query(), r.found)?;
+        }
+        wtr.flush()?;
+    }
+    Ok(())
+}
+
+struct Config {
+    queries: usize,
+    slow_queries: usize,
+    seed: u64,
+}
+
+/// The time taken by one kind of query against one structure.
+struct Measurement {
+    keys: usize,
+    structure: &'static str,
+    query: &'static str,
+    queries: usize,
+    total: Duration,
+    /// The total number of keys found, which is also used to keep the
+    /// queries from being optimized away.
+    found: usize,
+}
+
+impl Measurement {
+    fn per_query(&self) -> f64 {
+        nanos(self.total) as f64 / self.queries.max(1) as f64
+    }
+}
+
+/// The queries run against every structure for one corpus.
+struct Queries {
+    contains: Vec<String>,
+    range: Vec<String>,
+    prefix: Vec<String>,
+    regex: Vec<Regex>,
+    levenshtein: Vec<Levenshtein>,
+}
+
+fn bench_size(config: &Config, size: usize) -> Result<Vec<Measurement>> {
+    let mut rng = Rng::new(config.seed);
+    let words = corpus::words(&mut rng, size)?;
+    let queries = queries(config, &mut rng, &words)?;
+
+    let mut results = vec![];
+    let (set, build) = time(|| Set::from_iter(&words));
+    results.push(measure_build(size, "fst", build));
+    results.extend(bench(size, "fst", &set?, &queries));
+
+    let (btree, build) = time(|| {
+        words.iter().cloned().collect::<BTreeSet<_>>()
+    });
+    results.push(measure_build(size, "btreeset", build));
+    results.extend(bench(size, "btreeset", &btree, &queries));
+    drop(btree);
+
+    let (hash, build) = time(|| {
+        words.iter().cloned().collect::<HashSet<_>>()
+    });
+    results.push(measure_build(size, "hashset", build));
+    results.extend(bench(size, "hashset", &hash, &queries));
+    drop(hash);
+
+    let (vec, build) = time(|| SortedVec(words.clone()));
+    results.push(measure_build(size, "vec", build));
+    results.extend(bench(size, "vec", &vec, &queries));
+
+    check(&results);
+    Ok(results)
+}
+
+/// Generate the queries for a corpus.
+fn queries(
+    config: &Config,
+    rng: &mut Rng,
+    words: &[String],
+) -> Result<Queries> {
+    let mut queries = Queries {
+        contains: vec![],
+        range: vec![],
+        prefix: vec![],
+        regex: vec![],
+        levenshtein: vec![],
+    };
+    if words.is_empty() {
+        return Ok(queries);
+    }
+    for i in 0..config.queries {
+        if i % 2 == 0 {
+            queries.contains.push(rng.choose(words).clone());
+        } else {
+            queries.contains.push(corpus::word(rng));
+        }
+        queries.range.push(corpus::word(rng));
+        let word = rng.choose(words);
+        queries.prefix.push(word.chars().take(3).collect());
+    }
+    for _ in 0..config.slow_queries {
+        let word = rng.choose(words);
+        let first: String = word.chars().take(2).collect();
+        let last = word.chars().last().unwrap_or('a');
+        queries.regex.push(Regex::new(&format!("{}.*{}", first, last))?);
+        let word = rng.choose(words);
+        queries.levenshtein.push(Levenshtein::new(word, 1)?);
+    }
+    Ok(queries)
+}
+
+/// Run every query against `keys`.
+fn bench<K: Keys>(
+    size: usize,
+    structure: &'static str,
+    keys: &K,
+    queries: &Queries,
+) -> Vec<Measurement> {
+    let measure = |query, n, (found, total)| Measurement {
+        keys: size,
+        structure: structure,
+        query: query,
+        queries: n,
+        total: total,
+        found: found,
+    };
+    vec![
+        measure("contains", queries.contains.len(), time(|| {
+            queries.contains.iter().filter(|q| keys.contains(q)).count()
+        })),
+        measure("range", queries.range.len(), time(|| {
+            queries.range.iter().map(|q| keys.range(q, 100)).sum()
+        })),
+        measure("prefix", queries.prefix.len(), time(|| {
+            queries.prefix.iter().map(|q| keys.prefix(q)).sum()
+        })),
+        measure("regex", queries.regex.len(), time(|| {
+            queries.regex.iter().map(|q| keys.search(q)).sum()
+        })),
+        measure("levenshtein", queries.levenshtein.len(), time(|| {
+            queries.levenshtein.iter().map(|q| keys.search(q)).sum()
+        })),
+    ]
+}
+
+fn measure_build(
+    size: usize,
+    structure: &'static str,
+    total: Duration,
+) -> Measurement {
+    Measurement {
+        keys: size,
+        structure: structure,
+        query: "build",
+        queries: 1,
+        total: total,
+        found: size,
+    }
+}
+
+/// Complain about any query that found a different number of keys in
+/// different structures, which means one of them is wrong.
+fn check(results: &[Measurement]) {
+    for r in results {
+        let first = results.iter().find(|o| o.query == r.query).unwrap();
+        if r.found != first.found {
+            let _ = writeln!(
+                io::stderr(),
+                "warning: {} {} found {} keys but {} found {}",
+                r.structure, r.query, r.found, first.structure, first.found);
+        }
+    }
+}
+
+fn time<T, F: FnOnce() -> T>(f: F) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+fn nanos(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64
+}
+
+/// The queries being benchmarked, as answered by each structure.
+trait Keys {
+    /// Returns true if `key` is in the set.
+    fn contains(&self, key: &str) -> bool;
+
+    /// Returns the number of keys greater than or equal to `start`, up to
+    /// `limit`.
+    fn range(&self, start: &str, limit: usize) -> usize;
+
+    /// Returns the number of keys starting with `prefix`.
+    fn prefix(&self, prefix: &str) -> usize;
+
+    /// Returns the number of keys matched by `aut`.
+    fn search<A: Automaton>(&self, aut: &A) -> usize;
+}
+
+impl Keys for Set {
+    fn contains(&self, key: &str) -> bool {
+        Set::contains(self, key)
+    }
+
+    fn range(&self, start: &str, limit: usize) -> usize {
+        count(self.range().ge(start).into_stream(), limit)
+    }
+
+    fn prefix(&self, prefix: &str) -> usize {
+        let mut range = self.range().ge(prefix);
+        if let Some(end) = prefix_end(prefix.as_bytes()) {
+            range = range.lt(end);
+        }
+        count(range.into_stream(), usize::max_value())
+    }
+
+    fn search<A: Automaton>(&self, aut: &A) -> usize {
+        count(Set::search(self, aut).into_stream(), usize::max_value())
+    }
+}
+
+impl Keys for BTreeSet<String> {
+    fn contains(&self, key: &str) -> bool {
+        BTreeSet::contains(self, key)
+    }
+
+    fn range(&self, start: &str, limit: usize) -> usize {
+        BTreeSet::range::<str, _>(self, (Included(start), Unbounded))
+            .take(limit)
+            .count()
+    }
+
+    fn prefix(&self, prefix: &str) -> usize {
+        BTreeSet::range::<str, _>(self, (Included(prefix), Unbounded))
+            .take_while(|k| k.starts_with(prefix))
+            .count()
+    }
+
+    fn search<A: Automaton>(&self, aut: &A) -> usize {
+        self.iter().filter(|k| matches(aut, k.as_bytes())).count()
+    }
+}
+
+impl Keys for HashSet<String> {
+    fn contains(&self, key: &str) -> bool {
+        HashSet::contains(self, key)
+    }
+
+    fn range(&self, start: &str, limit: usize) -> usize {
+        // There's no order, so every key has to be looked at.
+        self.iter().filter(|k| &***k >= start).count().min(limit)
+    }
+
+    fn prefix(&self, prefix: &str) -> usize {
+        self.iter().filter(|k| k.starts_with(prefix)).count()
+    }
+
+    fn search<A: Automaton>(&self, aut: &A) -> usize {
+        self.iter().filter(|k| matches(aut, k.as_bytes())).count()
+    }
+}
+
+/// A sorted `Vec`, searched with binary search.
+struct SortedVec(Vec<String>);
+
+impl SortedVec {
+    /// Returns the index of the first key greater than or equal to `key`.
+    fn lower_bound(&self, key: &str) -> usize {
+        match self.0.binary_search_by(|k| (**k).cmp(key)) {
+            Ok(i) | Err(i) => i,
+        }
+    }
+}
+
+impl Keys for SortedVec {
+    fn contains(&self, key: &str) -> bool {
+        self.0.binary_search_by(|k| (**k).cmp(key)).is_ok()
+    }
+
+    fn range(&self, start: &str, limit: usize) -> usize {
+        self.0[self.lower_bound(start)..].iter().take(limit).count()
+    }
+
+    fn prefix(&self, prefix: &str) -> usize {
+        self.0[self.lower_bound(prefix)..].iter()
+            .take_while(|k| k.starts_with(prefix))
+            .count()
+    }
+
+    fn search<A: Automaton>(&self, aut: &A) -> usize {
+        self.0.iter().filter(|k| matches(aut, k.as_bytes())).count()
+    }
+}
+
+/// Count the keys in `stream`, up to `limit`.
+fn count<S>(mut stream: S, limit: usize) -> usize
+where S: for<'a> Streamer<'a, Item=&'a [u8]> {
+    let mut n = 0;
+    while n < limit && stream.next().is_some() {
+        n += 1;
+    }
+    n
+}
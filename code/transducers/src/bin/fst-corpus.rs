@@ -0,0 +1,104 @@
+/*!
+`fst-corpus` writes a synthetic corpus, one key per line, for reproducing the
+experiments in the blog post without downloading its data sets.
+
+    fst-corpus --sorted urls 1000000 | fst-index build - urls.fst
+
+The same seed always produces the same corpus.
+*/
+
+extern crate transducers;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::process;
+
+use transducers::Result;
+use transducers::args::Args;
+use transducers::corpus::{Corpus, Kind};
+
+const USAGE: &'static str = "\
+Usage:
+    fst-corpus [--seed S] [--sorted] [--output FILE] <kind> <count>
+
+Write <count> generated keys of the given kind, one per line. <kind> is one
+of:
+
+    words   Dictionary-like words, e.g., `brantle`.
+    titles  Title cased, multiword titles like Wikipedia's, e.g.,
+            `The Quorvel of Stanmire (film)`.
+    urls    URLs with a few popular hosts and paths a few levels deep, like
+            Common Crawl's, e.g., `http://www.plendor.com/ascot/merith.html`.
+    dois    DOI URLs, e.g., `http://dx.doi.org/10.1016/j.cell.2015.03.012`.
+
+Keys may repeat and are written in the order they're generated, unless
+--sorted is given. Sorting keeps every key in memory.
+
+Options:
+    --seed S       Seed for the generator (default: 0).
+    --sorted       Write <count> distinct keys in lexicographic order, which
+                   is what FST builders want.
+    --output FILE  Write to FILE instead of stdout.
+";
+
+fn main() {
+    if let Err(err) = run() {
+        // Don't complain when the output is piped into something like `head`.
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            if err.kind() == io::ErrorKind::BrokenPipe {
+                return;
+            }
+        }
+        let _ = writeln!(io::stderr(), "{}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let args = Args::parse(
+        env::args().skip(1),
+        &["help", "sorted"],
+        &["seed", "output"],
+    )?;
+    if args.switch("help") {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+    let kind: Kind = args.arg(0, "kind")?.parse()?;
+    let count = args.arg(1, "count")?;
+    let count: usize = count.parse()
+        .map_err(|_| format!("invalid count {:?}", count))?;
+    let mut corpus = Corpus::new(kind, args.parsed_or("seed", 0)?);
+
+    match args.value("output") {
+        None => {
+            let stdout = io::stdout();
+            let wtr = io::BufWriter::new(stdout.lock());
+            write_corpus(wtr, &mut corpus, count, args.switch("sorted"))
+        }
+        Some(path) => {
+            let wtr = io::BufWriter::new(File::create(path)?);
+            write_corpus(wtr, &mut corpus, count, args.switch("sorted"))
+        }
+    }
+}
+
+fn write_corpus<W: Write>(
+    mut wtr: W,
+    corpus: &mut Corpus,
+    count: usize,
+    sorted: bool,
+) -> Result<()> {
+    if sorted {
+        for key in corpus.distinct(count)? {
+            writeln!(wtr, "{}", key)?;
+        }
+    } else {
+        for key in corpus.take(count) {
+            writeln!(wtr, "{}", key)?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -0,0 +1,164 @@
+/*!
+`fst-diff` reports the keys that differ between two FSTs.
+
+    fst-diff yesterday.fst today.fst
+    fst-diff --outputs --json yesterday.fst today.fst
+*/
+
+extern crate fst;
+extern crate transducers;
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
+
+use fst::Streamer;
+
+use transducers::{Result, open_map};
+use transducers::args::Args;
+use transducers::diff::{Change, Diff};
+
+const USAGE: &'static str = "\
+Usage:
+    fst-diff [--outputs] [--json] [--count] <left> <right>
+
+Print every key that was removed (only in <left>), added (only in <right>) or
+changed (in both with a different value), in lexicographic order, as one of
+
+    - key
+    + key
+    ~ key
+
+Both FSTs are streamed, so they can be arbitrarily large. Sets can be compared
+too, but since every key in a set has the value 0, no key is ever changed. The
+exit status is 0 if there are no differences and 1 if there are some.
+
+Options:
+    --outputs  Also print values as `- key,value`, `+ key,value` and
+               `~ key,left,right`.
+    --json     Print one JSON object per line instead, like
+               {\"key\":\"foo\",\"change\":\"changed\",\"left\":1,\"right\":2}.
+               Keys that aren't valid UTF-8 are converted lossily.
+    --count    Only print the number of removed, added and changed keys.
+";
+
+fn main() {
+    match run() {
+        Ok(true) => process::exit(1),
+        Ok(false) => {}
+        Err(err) => {
+            if let Some(err) = err.downcast_ref::<io::Error>() {
+                if err.kind() == io::ErrorKind::BrokenPipe {
+                    return;
+                }
+            }
+            let _ = writeln!(io::stderr(), "{}", err);
+            process::exit(2);
+        }
+    }
+}
+
+/// Returns whether there were any differences.
+fn run() -> Result<bool> {
+    let args = Args::parse(
+        env::args().skip(1),
+        &["help", "outputs", "json", "count"],
+        &[],
+    )?;
+    if args.switch("help") {
+        print!("{}", USAGE);
+        return Ok(false);
+    }
+    let left = open_map(args.arg(0, "left")?)?;
+    let right = open_map(args.arg(1, "right")?)?;
+    let mut diff = Diff::new(&left, &right);
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    let (mut removed, mut added, mut changed) = (0u64, 0u64, 0u64);
+    while let Some((key, change)) = diff.next() {
+        match change {
+            Change::Removed(_) => removed += 1,
+            Change::Added(_) => added += 1,
+            Change::Changed(_, _) => changed += 1,
+        }
+        if args.switch("count") {
+            continue;
+        } else if args.switch("json") {
+            write_json(&mut wtr, key, change)?;
+        } else {
+            write_text(&mut wtr, key, change, args.switch("outputs"))?;
+        }
+    }
+    if args.switch("count") {
+        if args.switch("json") {
+            writeln!(wtr, "{{\"removed\":{},\"added\":{},\"changed\":{}}}",
+                     removed, added, changed)?;
+        } else {
+            writeln!(wtr, "removed: {}", removed)?;
+            writeln!(wtr, "added:   {}", added)?;
+            writeln!(wtr, "changed: {}", changed)?;
+        }
+    }
+    wtr.flush()?;
+    Ok(removed + added + changed > 0)
+}
+
+fn write_text<W: Write>(
+    mut wtr: W,
+    key: &[u8],
+    change: Change,
+    outputs: bool,
+) -> io::Result<()> {
+    let sign = match change {
+        Change::Removed(_) => b"- ",
+        Change::Added(_) => b"+ ",
+        Change::Changed(_, _) => b"~ ",
+    };
+    wtr.write_all(sign)?;
+    wtr.write_all(key)?;
+    if outputs {
+        match change {
+            Change::Removed(v) | Change::Added(v) => write!(wtr, ",{}", v)?,
+            Change::Changed(l, r) => write!(wtr, ",{},{}", l, r)?,
+        }
+    }
+    wtr.write_all(b"\n")
+}
+
+fn write_json<W: Write>(
+    mut wtr: W,
+    key: &[u8],
+    change: Change,
+) -> io::Result<()> {
+    wtr.write_all(b"{\"key\":")?;
+    write_json_string(&mut wtr, &String::from_utf8_lossy(key))?;
+    match change {
+        Change::Removed(v) => {
+            write!(wtr, ",\"change\":\"removed\",\"left\":{}", v)?
+        }
+        Change::Added(v) => {
+            write!(wtr, ",\"change\":\"added\",\"right\":{}", v)?
+        }
+        Change::Changed(l, r) => write!(
+            wtr, ",\"change\":\"changed\",\"left\":{},\"right\":{}", l, r)?,
+    }
+    wtr.write_all(b"}\n")
+}
+
+/// Write `s` as a quoted JSON string.
+fn write_json_string<W: Write>(mut wtr: W, s: &str) -> io::Result<()> {
+    wtr.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => wtr.write_all(b"\\\"")?,
+            '\\' => wtr.write_all(b"\\\\")?,
+            '\n' => wtr.write_all(b"\\n")?,
+            '\r' => wtr.write_all(b"\\r")?,
+            '\t' => wtr.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(wtr, "\\u{:04x}", c as u32)?,
+            c => write!(wtr, "{}", c)?,
+        }
+    }
+    wtr.write_all(b"\"")
+}
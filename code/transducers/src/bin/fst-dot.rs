@@ -0,0 +1,47 @@
+/*!
+`fst-dot` prints an FST in the Graphviz "dot" format.
+
+    fst-dot --state-names months.fst | dot -Tpng > months.png
+*/
+
+extern crate fst;
+extern crate transducers;
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
+
+use transducers::Result;
+use transducers::args::Args;
+use transducers::dot::{self, Options};
+
+const USAGE: &'static str = "\
+Usage:
+    fst-dot [--state-names] <fst>
+
+Options:
+    --state-names  Label each state with a number.
+";
+
+fn main() {
+    if let Err(err) = run() {
+        let _ = writeln!(io::stderr(), "{}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let args = Args::parse(env::args().skip(1), &["state-names", "help"], &[])?;
+    if args.switch("help") {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+    let map = transducers::open_map(args.arg(0, "fst")?)?;
+    let opts = Options { state_names: args.switch("state-names") };
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    dot::write_dot(map.as_fst(), &opts, &mut wtr)?;
+    wtr.flush()?;
+    Ok(())
+}
@@ -0,0 +1,900 @@
+/*!
+`fst-index` builds sets and maps from plain text files and runs queries
+against them.
+
+This collects the snippets from the blog post (building with `SetBuilder` and
+`MapBuilder`, range queries, Levenshtein and regex searches, and set union)
+into one tool that works on arbitrary files.
+*/
+
+extern crate fst;
+extern crate fst_levenshtein;
+extern crate fst_regex;
+extern crate transducers;
+
+use std::cmp;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fst::{Automaton, IntoStreamer, Map, Streamer, MapBuilder, SetBuilder};
+use fst::{map, set};
+use fst_levenshtein::Levenshtein;
+use fst_regex::Regex;
+
+use transducers::{Result, open_map, open_set};
+use transducers::args::Args;
+use transducers::autocomplete::Autocomplete;
+use transducers::cached::CachedFst;
+use transducers::combine::{self, Reducer};
+use transducers::damerau::DamerauLevenshtein;
+use transducers::extsort::ExternalSorter;
+use transducers::glob::Glob;
+use transducers::integrity;
+use transducers::normalize::Normalizer;
+use transducers::ordinal::OrdinalMap;
+use transducers::page::{Cursor, Page, Paginator};
+use transducers::query::Query;
+use transducers::rank::{RankSet, RankSetBuilder};
+use transducers::reverse::{PairedSet, PairedSetBuilder, reversed_path};
+use transducers::shard::{Progress, SearchBuilder, ShardedBuilder};
+use transducers::shard::{ShardedMap, shard_paths};
+use transducers::subsequence::{DefaultScorer, Subsequence};
+
+const USAGE: &'static str = "\
+Usage:
+    fst-index build [--map] [--reverse] [--ranked] [--sorted] [--memory MB]
+                    [--tmp-dir DIR] [--threads N [--concat] [--progress]]
+                    <input> <output>
+    fst-index contains [--cache KB] <fst> <key>...
+    fst-index range [--start KEY] [--end KEY] [--outputs] <fst>
+    fst-index page [--start KEY] [--end KEY] [--regex RE] [--limit N]
+                   [--after CURSOR] [--outputs] <fst>
+    fst-index fuzzy [--distance N] [--transpositions] [--ignore-case] [--nfkc]
+                    [--outputs] <fst> <query>
+    fst-index regex [--ignore-case] [--nfkc] [--outputs] <fst> <regex>
+    fst-index glob [--ignore-case] [--nfkc] [--outputs] <fst> <glob>
+    fst-index complete [--distance N] [--limit K] [--words] <fst> <query>
+    fst-index subseq [--limit K] [--words] [--ignore-case] <fst> <query>
+    fst-index rank <fst> <key>...
+    fst-index select <fst> <n>...
+    fst-index key <fst> <value>...
+    fst-index union [--output FILE] [--reduce R] <fst>...
+    fst-index shards [--threads N] [--start KEY] [--end KEY] [--regex RE]
+                     [--limit K] [--reduce R] [--outputs] <fst>...
+    fst-index query [--output FILE] <query>
+    fst-index seal <fst> <output>
+    fst-index verify <fst>...
+
+Commands:
+    build     Build an FST from <input>, which has one key per line, or from
+              stdin if <input> is `-`. With --map, each line is `key,value`
+              where value is an unsigned integer. Input is sorted and
+              deduplicated first (the first value of a duplicate key wins),
+              buffering at most --memory megabytes (default: 128) and spilling
+              sorted runs to --tmp-dir. If the input is already sorted and has
+              no duplicates, pass --sorted to skip this step. With --reverse,
+              also write a set of every key with its bytes reversed to
+              <output>.rev, which lets glob answer patterns like `*.org`
+              without a full scan. The reversed keys need sorting too, so
+              without --sorted, --memory is split evenly between sorting the
+              input and sorting the reversed keys. With --ranked, write a set
+              that supports rank and select. With --threads, build the set or
+              map in N shards at once, writing them to the directory <output>
+              for the shards command to search. Without --sorted, --memory is
+              then split evenly between sorting the input and buffering keys
+              for the shards. With --concat, concatenate the shards into the
+              single FST <output> instead, which means inserting every key a
+              second time on one thread: that's only faster than building
+              without --threads when reading the input is the slow part. With
+              --progress, also report the keys built per second and bytes
+              written while the shards are built.
+    contains  Report whether each key is in the FST. With --cache, read the
+              FST through a cache of at most KB kilobytes instead of memory
+              mapping it, and print the cache's hits and misses to stderr.
+    range     Print all keys greater than or equal to --start and less than or
+              equal to --end.
+    page      Print a page of at most --limit (default: 100) of the keys
+              between --start and --end that match --regex. If there are
+              more, print a cursor to stderr, which gets the next page when
+              passed to --after along with the same query.
+    fuzzy     Print all keys within a Levenshtein distance of --distance
+              (default: 1) from <query>. With --transpositions, swapping two
+              adjacent characters counts as one edit instead of two.
+    regex     Print all keys matching the regular expression <regex>.
+    glob      Print all keys matching the shell style pattern <glob>, which
+              may use `*`, `?`, `[a-c]`, `[!a-c]` and `\\` escapes. If
+              <fst>.rev exists (see build --reverse), patterns like `*.org`
+              are answered with it, in the order of their reversed keys.
+    complete  Print the --limit (default: 10) keys with the highest values
+              that start with <query>, allowing up to --distance (default: 0)
+              edits. With --words, <query> may match the start of any word in
+              a key. Results are printed as `key,value`.
+    subseq    Print the --limit (default: 10) best keys containing the
+              characters of <query> in order, e.g., `brsp` finds `bruce
+              springsteen`. With --words, each run of matched characters must
+              start a word. Results are printed as `key,score`.
+    rank      Print the number of keys less than each key, in an FST built
+              with --ranked.
+    select    Print the key with each rank (counting from 0), in an FST
+              built with --ranked.
+    key       Print the key with each value, in a map whose values increase
+              with its keys (like a set built with --ranked, or a term
+              dictionary).
+    union     Print the union of the keys in all of the given FSTs. With
+              --output, write the union as a new set instead. With --reduce,
+              the FSTs are treated as maps and the values of keys appearing in
+              more than one map are combined with one of sum, max, min, first
+              or last. The result is printed as `key,value` or, with
+              --output, written as a new map.
+    shards    Print the keys in all of the given FSTs, treated as shards of one
+              FST, that are between --start and --end and match --regex. An
+              <fst> can also be a directory written by build --threads.
+              Every shard is searched at once by --threads (default: 4)
+              threads, and the search stops after --limit keys. With
+              --outputs, the values of keys in more than one shard are
+              combined with --reduce (default: first).
+    query     Print the keys selected by <query>, which combines FSTs with
+              `|` (union), `&` (intersection), `^` (symmetric difference),
+              `!` (complement) and parentheses, e.g., `(a.fst | b.fst) &
+              !c.fst`. Sets can also be intersected with filters written as
+              `re:\"<regex>\"`, `fuzzy:<word>~<distance>`, `prefix:<text>`
+              or `glob:\"<glob>\"`. With --output, write the keys as a new
+              set instead.
+    seal      Copy <fst> to <output> with a header that records its number
+              of keys, checksum and when and from what it was sealed. Every
+              command accepts sealed FSTs, and refuses to open one whose
+              header, length or number of keys is wrong.
+    verify    Check that each sealed FST is intact, including its checksum,
+              and print its header. If any fail, the exit status is 4 if one
+              is corrupt, or else 3 if one was written by an unsupported
+              version, or else 2 if one couldn't be read.
+
+Options:
+    --outputs      Print the value associated with each key as `key,value`.
+    --ignore-case  Search keys as if they were case folded. For regex, write
+                   the pattern's literals in lowercase.
+    --nfkc         Search keys under Unicode compatibility equivalence, so
+                   that, e.g., `ﬁ` matches `fi`. Keys are always compared
+                   under canonical equivalence when either this or
+                   --ignore-case is given.
+";
+
+fn main() {
+    if let Err(err) = run() {
+        // Don't complain when the output is piped into something like `head`.
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            if err.kind() == io::ErrorKind::BrokenPipe {
+                return;
+            }
+        }
+        let _ = writeln!(io::stderr(), "{}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let mut argv = env::args().skip(1);
+    let cmd = match argv.next() {
+        None => return Err(From::from(USAGE)),
+        Some(cmd) => cmd,
+    };
+    let args = Args::parse(
+        argv,
+        &[
+            "map", "reverse", "ranked", "sorted", "outputs", "words",
+            "transpositions", "ignore-case", "nfkc", "progress", "concat",
+        ],
+        &[
+            "start", "end", "distance", "output", "memory", "tmp-dir",
+            "reduce", "limit", "threads", "regex", "cache", "after",
+        ],
+    )?;
+    match &*cmd {
+        "build" => cmd_build(&args),
+        "contains" => cmd_contains(&args),
+        "range" => cmd_range(&args),
+        "page" => cmd_page(&args),
+        "fuzzy" => cmd_fuzzy(&args),
+        "regex" => cmd_regex(&args),
+        "glob" => cmd_glob(&args),
+        "complete" => cmd_complete(&args),
+        "subseq" => cmd_subseq(&args),
+        "rank" => cmd_rank(&args),
+        "select" => cmd_select(&args),
+        "key" => cmd_key(&args),
+        "union" => cmd_union(&args),
+        "shards" => cmd_shards(&args),
+        "query" => cmd_query(&args),
+        "seal" => cmd_seal(&args),
+        "verify" => cmd_verify(&args),
+        "-h" | "--help" | "help" => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(From::from(format!("unknown command: {}\n\n{}", cmd, USAGE))),
+    }
+}
+
+fn cmd_build(args: &Args) -> Result<()> {
+    let input = args.arg(0, "input")?;
+    let output = args.arg(1, "output")?;
+    let is_map = args.switch("map");
+    let exclusive = ["map", "reverse", "ranked"];
+    if exclusive.iter().filter(|&&s| args.switch(s)).count() > 1 {
+        return Err(From::from(
+            "only one of --map, --reverse and --ranked can be used"));
+    }
+    let reverse = args.switch("reverse");
+    let threads = args.parsed_or::<usize>("threads", 0)?;
+    if threads > 0 && (reverse || args.switch("ranked")) {
+        return Err(From::from(
+            "--threads can't be used with --reverse or --ranked"));
+    }
+    let concat = args.switch("concat");
+    if threads == 0 && (concat || args.switch("progress")) {
+        return Err(From::from("--concat and --progress require --threads"));
+    }
+    let sorted = args.switch("sorted");
+    let memory = args.parsed_or::<usize>("memory", 128)? * (1 << 20);
+    // Without --sorted, the input's sorter buffers keys, and so do the
+    // shards or the sorter for the reversed keys, so they split the budget.
+    let (sort_memory, build_memory) = if (threads > 0 || reverse) && !sorted {
+        (memory / 2, memory - memory / 2)
+    } else {
+        (memory, memory)
+    };
+    let stdin = io::stdin();
+    let (rdr, input): (Box<BufRead>, &str) = if input == "-" {
+        (Box::new(stdin.lock()), "<stdin>")
+    } else {
+        (Box::new(io::BufReader::new(File::open(input)?)), input)
+    };
+    // With --concat, the shards are built in a temporary directory. This is
+    // declared before the builder so that it's dropped after it, i.e., once
+    // the builder has removed the shards, however the build ends.
+    let tmp_shards = if concat {
+        let dir = args.value("tmp-dir").map_or_else(env::temp_dir, From::from)
+            .join(format!("transducers-shards-{}", process::id()));
+        Some(RemoveDir(dir))
+    } else {
+        None
+    };
+    let mut reporter = None;
+    let mut builder = if threads > 0 {
+        let dir = match tmp_shards {
+            None => Path::new(output),
+            Some(RemoveDir(ref dir)) => dir,
+        };
+        let mut sharded = if is_map {
+            ShardedBuilder::map(dir)
+        } else {
+            ShardedBuilder::set(dir)
+        };
+        sharded.threads(threads).max_memory(build_memory);
+        if args.switch("progress") {
+            reporter = Some(Reporter::start(sharded.progress()));
+        }
+        let wtr = if concat {
+            Some(io::BufWriter::new(File::create(output)?))
+        } else {
+            None
+        };
+        Builder::Sharded(sharded, wtr)
+    } else {
+        let wtr = io::BufWriter::new(File::create(output)?);
+        if is_map {
+            Builder::Map(MapBuilder::new(wtr)?)
+        } else if args.switch("ranked") {
+            Builder::Ranked(RankSetBuilder::new(wtr)?)
+        } else if reverse {
+            let rev = io::BufWriter::new(File::create(reversed_path(output))?);
+            let mut paired = PairedSetBuilder::new(wtr, rev)?;
+            paired.sorter().max_memory(build_memory);
+            if let Some(dir) = args.value("tmp-dir") {
+                paired.sorter().tmp_dir(dir);
+            }
+            Builder::Paired(paired)
+        } else {
+            Builder::Set(SetBuilder::new(wtr)?)
+        }
+    };
+
+    if sorted {
+        for (i, line) in rdr.lines().enumerate() {
+            let line = line?;
+            parse_line(&line, is_map)
+                .and_then(|(key, value)| builder.insert(key.as_bytes(), value))
+                .map_err(|err| format!("{}:{}: {}", input, i + 1, err))?;
+        }
+    } else {
+        let mut sorter = ExternalSorter::new();
+        sorter.max_memory(sort_memory);
+        if let Some(dir) = args.value("tmp-dir") {
+            sorter.tmp_dir(dir);
+        }
+        for (i, line) in rdr.lines().enumerate() {
+            let line = line?;
+            let (key, value) = parse_line(&line, is_map)
+                .map_err(|err| format!("{}:{}: {}", input, i + 1, err))?;
+            sorter.push(key, value)?;
+        }
+        for result in sorter.finish()? {
+            let (key, value) = result?;
+            builder.insert(&key, value)?;
+        }
+    }
+    let result = builder.finish();
+    if let Some(reporter) = reporter {
+        reporter.stop();
+    }
+    result
+}
+
+fn cmd_contains(args: &Args) -> Result<()> {
+    if args.value("cache").is_some() {
+        return contains_cached(args);
+    }
+    let map = open_map(args.arg(0, "fst")?)?;
+    args.arg(1, "key")?;
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    for key in &args.positional()[1..] {
+        let found = if map.contains_key(key) { "yes" } else { "no" };
+        writeln!(wtr, "{}\t{}", key, found)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Implements `contains --cache`, which reads the FST in 4 KB blocks.
+fn contains_cached(args: &Args) -> Result<()> {
+    let blocks = args.parsed_or::<usize>("cache", 0)? / 4;
+    let fst = CachedFst::open(args.arg(0, "fst")?, 4096, blocks)?;
+    args.arg(1, "key")?;
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    for key in &args.positional()[1..] {
+        let found = if fst.contains_key(key)? { "yes" } else { "no" };
+        writeln!(wtr, "{}\t{}", key, found)?;
+    }
+    wtr.flush()?;
+    let stats = fst.stats();
+    writeln!(io::stderr(), "cache hits: {}, misses: {}",
+             stats.hits, stats.misses)?;
+    Ok(())
+}
+
+fn cmd_range(args: &Args) -> Result<()> {
+    let map = open_map(args.arg(0, "fst")?)?;
+    let mut range = map.range();
+    if let Some(start) = args.value("start") {
+        range = range.ge(start);
+    }
+    if let Some(end) = args.value("end") {
+        range = range.le(end);
+    }
+    print_stream(range.into_stream(), args.switch("outputs"))
+}
+
+fn cmd_page(args: &Args) -> Result<()> {
+    let map = open_map(args.arg(0, "fst")?)?;
+    let cursor = match args.value("after") {
+        None => None,
+        Some(cursor) => Some(cursor.parse()?),
+    };
+    let page = match args.value("regex") {
+        None => paginate(Paginator::range(), &map, cursor, args)?,
+        Some(re) => {
+            paginate(Paginator::search(Regex::new(re)?), &map, cursor, args)?
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    for (key, value) in page.entries {
+        wtr.write_all(&key)?;
+        if args.switch("outputs") {
+            write!(wtr, ",{}", value)?;
+        }
+        wtr.write_all(b"\n")?;
+    }
+    wtr.flush()?;
+    if let Some(next) = page.next {
+        writeln!(io::stderr(), "next page: --after {}", next)?;
+    }
+    Ok(())
+}
+
+/// Returns the page of `paginator`'s results in `map` that starts at
+/// `cursor`, restricted to the range and page size given by `args`.
+fn paginate<A: Automaton>(
+    mut paginator: Paginator<A>,
+    map: &Map,
+    cursor: Option<Cursor>,
+    args: &Args,
+) -> Result<Page> {
+    if let Some(start) = args.value("start") {
+        paginator = paginator.ge(start);
+    }
+    if let Some(end) = args.value("end") {
+        paginator = paginator.le(end);
+    }
+    paginator = paginator.page_size(args.parsed_or("limit", 100)?);
+    Ok(paginator.page(map.as_fst(), cursor.as_ref()))
+}
+
+fn cmd_fuzzy(args: &Args) -> Result<()> {
+    let map = open_map(args.arg(0, "fst")?)?;
+    let mut query = args.arg(1, "query")?.to_string();
+    if let Some(norm) = normalizer(args) {
+        query = norm.normalize(&query);
+    }
+    let distance = args.parsed_or("distance", 1)?;
+    if args.switch("transpositions") {
+        let dl = DamerauLevenshtein::new(&query, distance)?;
+        print_search(&map, dl, args)
+    } else {
+        let lev = Levenshtein::new(&query, distance)?;
+        print_search(&map, lev, args)
+    }
+}
+
+fn cmd_regex(args: &Args) -> Result<()> {
+    let map = open_map(args.arg(0, "fst")?)?;
+    let re = Regex::new(args.arg(1, "regex")?)?;
+    print_search(&map, re, args)
+}
+
+fn cmd_glob(args: &Args) -> Result<()> {
+    let path = args.arg(0, "fst")?;
+    let mut pattern = args.arg(1, "glob")?.to_string();
+    if let Some(norm) = normalizer(args) {
+        pattern = norm.normalize(&pattern);
+    }
+    let glob = Glob::new(&pattern)?;
+    if normalizer(args).is_some() || !reversed_path(path).exists() {
+        return print_search(&open_map(path)?, glob, args);
+    }
+    // Let the paired set decide whether the reversed set can help.
+    let paired = PairedSet::open(path)?;
+    let mut stream = paired.glob(&pattern)?;
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    while let Some(key) = stream.next() {
+        wtr.write_all(key)?;
+        if args.switch("outputs") {
+            // The reversed set has no values, so look each key up in the
+            // forward FST.
+            let out = paired.forward().as_fst().get(key);
+            write!(wtr, ",{}", out.map_or(0, |out| out.value()))?;
+        }
+        wtr.write_all(b"\n")?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Returns the normalizer asked for by --ignore-case and --nfkc, if any.
+fn normalizer(args: &Args) -> Option<Normalizer> {
+    let ignore_case = args.switch("ignore-case");
+    let nfkc = args.switch("nfkc");
+    if !ignore_case && !nfkc {
+        return None;
+    }
+    Some(Normalizer::new().case_fold(ignore_case).compatibility(nfkc))
+}
+
+/// Search `map` with `aut`, normalizing keys first if asked to.
+fn print_search<A>(map: &Map, aut: A, args: &Args) -> Result<()>
+where A: Automaton, A::State: Clone {
+    let outputs = args.switch("outputs");
+    match normalizer(args) {
+        None => print_stream(map.search(aut).into_stream(), outputs),
+        Some(norm) => {
+            let aut = norm.automaton(aut);
+            print_stream(map.search(aut).into_stream(), outputs)
+        }
+    }
+}
+
+fn cmd_complete(args: &Args) -> Result<()> {
+    let map = open_map(args.arg(0, "fst")?)?;
+    let query = args.arg(1, "query")?;
+    let results = Autocomplete::new(query)
+        .distance(args.parsed_or("distance", 0)?)
+        .limit(args.parsed_or("limit", 10)?)
+        .word_starts(args.switch("words"))
+        .search(&map)?;
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    for (key, score) in results {
+        wtr.write_all(&key)?;
+        writeln!(wtr, ",{}", score)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn cmd_subseq(args: &Args) -> Result<()> {
+    let map = open_map(args.arg(0, "fst")?)?;
+    let subseq = Subsequence::new(args.arg(1, "query")?)
+        .word_starts(args.switch("words"))
+        .ignore_case(args.switch("ignore-case"));
+    let stream = map.search(subseq.clone()).into_stream();
+    let limit = args.parsed_or("limit", 10)?;
+    let results = subseq.rank(stream, &DefaultScorer, limit);
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    for (key, score) in results {
+        wtr.write_all(&key)?;
+        writeln!(wtr, ",{}", score)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn cmd_rank(args: &Args) -> Result<()> {
+    let set = RankSet::open(args.arg(0, "fst")?)?;
+    args.arg(1, "key")?;
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    for key in &args.positional()[1..] {
+        writeln!(wtr, "{}\t{}", key, set.rank(key))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn cmd_select(args: &Args) -> Result<()> {
+    let set = RankSet::open(args.arg(0, "fst")?)?;
+    args.arg(1, "n")?;
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    for n in &args.positional()[1..] {
+        let n: u64 = n.parse()
+            .map_err(|_| format!("invalid rank {:?}", n))?;
+        let key = set.select(n).ok_or_else(|| {
+            format!("rank {} is out of range (the set has {} keys)",
+                    n, set.len())
+        })?;
+        write!(wtr, "{}\t", n)?;
+        wtr.write_all(&key)?;
+        wtr.write_all(b"\n")?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn cmd_key(args: &Args) -> Result<()> {
+    let map = OrdinalMap::open(args.arg(0, "fst")?)?;
+    args.arg(1, "value")?;
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    for value in &args.positional()[1..] {
+        let value: u64 = value.parse()
+            .map_err(|_| format!("invalid value {:?}", value))?;
+        let key = map.key(value).ok_or_else(|| {
+            format!("no key has the value {}", value)
+        })?;
+        write!(wtr, "{}\t", value)?;
+        wtr.write_all(&key)?;
+        wtr.write_all(b"\n")?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn cmd_union(args: &Args) -> Result<()> {
+    args.arg(0, "fst")?;
+    if let Some(reducer) = args.value("reduce") {
+        return union_maps(args, reducer.parse()?);
+    }
+    let mut sets = vec![];
+    for path in args.positional() {
+        sets.push(open_set(path)?);
+    }
+    let mut op = set::OpBuilder::new();
+    for set in &sets {
+        op = op.add(set);
+    }
+    let mut union = op.union();
+
+    if let Some(output) = args.value("output") {
+        let wtr = io::BufWriter::new(File::create(output)?);
+        let mut builder = SetBuilder::new(wtr)?;
+        builder.extend_stream(union)?;
+        builder.finish()?;
+    } else {
+        let stdout = io::stdout();
+        let mut wtr = io::BufWriter::new(stdout.lock());
+        while let Some(key) = union.next() {
+            wtr.write_all(key)?;
+            wtr.write_all(b"\n")?;
+        }
+        wtr.flush()?;
+    }
+    Ok(())
+}
+
+fn cmd_shards(args: &Args) -> Result<()> {
+    if args.positional().is_empty() {
+        args.arg(0, "fst")?;
+    }
+    let mut paths = vec![];
+    for path in args.positional() {
+        if Path::new(path).is_dir() {
+            paths.extend(shard_paths(path)?);
+        } else {
+            paths.push(PathBuf::from(path));
+        }
+    }
+    let mut map = ShardedMap::open(&paths, args.parsed_or("threads", 0)?)?;
+    if let Some(reduce) = args.value("reduce") {
+        map.reducer(reduce.parse()?);
+    }
+    match args.value("regex") {
+        None => print_sharded(map.range(), args),
+        Some(re) => print_sharded(map.search(Regex::new(re)?), args),
+    }
+}
+
+/// Prints the results of a search of every shard, restricted to the range
+/// and number of keys given by `args`.
+fn print_sharded<A>(mut search: SearchBuilder<A>, args: &Args) -> Result<()>
+where A: Automaton + Send + Sync + 'static {
+    if let Some(start) = args.value("start") {
+        search = search.ge(start);
+    }
+    if let Some(end) = args.value("end") {
+        search = search.le(end);
+    }
+    if args.value("limit").is_some() {
+        search = search.limit(args.parsed_or("limit", 0)?);
+    }
+    let outputs = args.switch("outputs");
+    let mut stream = search.into_stream();
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    while let Some((key, value)) = stream.next()? {
+        wtr.write_all(key)?;
+        if outputs {
+            write!(wtr, ",{}", value)?;
+        }
+        wtr.write_all(b"\n")?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn cmd_query(args: &Args) -> Result<()> {
+    let query = Query::parse(args.arg(0, "query")?)?;
+    let sets = query.open_sets()?;
+    let mut keys = query.search(&sets)?;
+
+    if let Some(output) = args.value("output") {
+        let wtr = io::BufWriter::new(File::create(output)?);
+        let mut builder = SetBuilder::new(wtr)?;
+        builder.extend_stream(keys)?;
+        builder.finish()?;
+    } else {
+        let stdout = io::stdout();
+        let mut wtr = io::BufWriter::new(stdout.lock());
+        while let Some(key) = keys.next() {
+            wtr.write_all(key)?;
+            wtr.write_all(b"\n")?;
+        }
+        wtr.flush()?;
+    }
+    Ok(())
+}
+
+fn cmd_seal(args: &Args) -> Result<()> {
+    let input = args.arg(0, "fst")?;
+    let output = args.arg(1, "output")?;
+    let sealed = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let metadata = vec![
+        ("source".to_string(), input.to_string()),
+        ("sealed".to_string(), sealed.to_string()),
+        ("tool".to_string(),
+         format!("fst-index {}", env!("CARGO_PKG_VERSION"))),
+    ];
+    let wtr = io::BufWriter::new(File::create(output)?);
+    integrity::seal(input, wtr, &metadata)?;
+    Ok(())
+}
+
+fn cmd_verify(args: &Args) -> Result<()> {
+    args.arg(0, "fst")?;
+    let (mut failed, mut status) = (0, 0);
+    for path in args.positional() {
+        match integrity::verify(path) {
+            Ok(header) => {
+                print!("{}: ok (version {}, {} keys, {} bytes, crc {:08x}",
+                       path, header.version, header.keys, header.fst_len,
+                       header.checksum);
+                for &(ref name, ref value) in &header.metadata {
+                    print!(", {}={}", name, value);
+                }
+                println!(")");
+            }
+            Err(err) => {
+                failed += 1;
+                status = cmp::max(status, match err {
+                    integrity::Error::Io(_) => 2,
+                    integrity::Error::Version { .. } => 3,
+                    integrity::Error::Corrupt(_) => 4,
+                });
+                println!("{}: {}", path, err);
+            }
+        }
+    }
+    if failed > 0 {
+        io::stdout().flush()?;
+        writeln!(io::stderr(), "{} FST(s) failed to verify", failed)?;
+        process::exit(status);
+    }
+    Ok(())
+}
+
+/// Implements `union --reduce`, where the inputs are maps and the values of
+/// duplicate keys are combined with `reducer`.
+fn union_maps(args: &Args, reducer: Reducer) -> Result<()> {
+    let mut maps = vec![];
+    for path in args.positional() {
+        maps.push(open_map(path)?);
+    }
+    let mut op = map::OpBuilder::new();
+    for map in &maps {
+        op = op.add(map);
+    }
+    let union = combine::Union::new(op, reducer);
+
+    if let Some(output) = args.value("output") {
+        let wtr = io::BufWriter::new(File::create(output)?);
+        let mut builder = MapBuilder::new(wtr)?;
+        builder.extend_stream(union)?;
+        builder.finish()?;
+        Ok(())
+    } else {
+        print_stream(union, true)
+    }
+}
+
+/// Parses a line of input to `build`. For sets, the entire line is the key
+/// and the value is always `0`.
+fn parse_line(line: &str, is_map: bool) -> Result<(&str, u64)> {
+    if is_map {
+        parse_map_line(line)
+    } else {
+        Ok((line, 0))
+    }
+}
+
+/// Parses a line of the form `key,value`. The key may itself contain commas,
+/// so we split on the last one.
+fn parse_map_line(line: &str) -> Result<(&str, u64)> {
+    let i = match line.rfind(',') {
+        None => return Err(From::from("expected a line of the form key,value")),
+        Some(i) => i,
+    };
+    let value = line[i + 1..].trim().parse().map_err(|_| {
+        format!("invalid value: {:?}", &line[i + 1..])
+    })?;
+    Ok((&line[..i], value))
+}
+
+/// A builder for either a set or a map, as chosen by `build --map`, a set
+/// with its reversed companion, as chosen by `build --reverse`, a set that
+/// supports rank and select, as chosen by `build --ranked`, or a set or map
+/// built in shards, as chosen by `build --threads`.
+enum Builder<W> {
+    Set(SetBuilder<W>),
+    Map(MapBuilder<W>),
+    Paired(PairedSetBuilder<W>),
+    Ranked(RankSetBuilder<W>),
+    /// If there's a writer, the shards are concatenated into it once they're
+    /// built.
+    Sharded(ShardedBuilder, Option<W>),
+}
+
+impl<W: Write> Builder<W> {
+    fn insert(&mut self, key: &[u8], value: u64) -> Result<()> {
+        match *self {
+            Builder::Set(ref mut b) => b.insert(key)?,
+            Builder::Map(ref mut b) => b.insert(key, value)?,
+            Builder::Paired(ref mut b) => b.insert(key)?,
+            Builder::Ranked(ref mut b) => b.insert(key)?,
+            Builder::Sharded(ref mut b, _) => b.push(key, value)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Builder::Set(b) => b.finish()?,
+            Builder::Map(b) => b.finish()?,
+            Builder::Paired(b) => b.finish()?,
+            Builder::Ranked(b) => b.finish()?,
+            Builder::Sharded(b, None) => {
+                b.finish()?;
+            }
+            Builder::Sharded(b, Some(wtr)) => b.finish_into(wtr)?,
+        }
+        Ok(())
+    }
+}
+
+/// Removes a directory when dropped, if it's empty.
+struct RemoveDir(PathBuf);
+
+impl Drop for RemoveDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir(&self.0);
+    }
+}
+
+/// Prints the progress of a sharded build to stderr about once a second,
+/// until every shard is built.
+struct Reporter {
+    done: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl Reporter {
+    fn start(progress: Arc<Progress>) -> Reporter {
+        let done = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let done = done.clone();
+            thread::spawn(move || {
+                let mut ticks = 0;
+                while !done.load(Ordering::SeqCst) && !progress.is_finished() {
+                    thread::sleep(Duration::from_millis(100));
+                    ticks += 1;
+                    if ticks % 10 == 0 {
+                        let _ = write!(io::stderr(), "\r{}", progress);
+                    }
+                }
+                let _ = writeln!(io::stderr(), "\r{}", progress);
+            })
+        };
+        Reporter { done: done, handle: handle }
+    }
+
+    /// Stop reporting, if every shard hasn't been built already (e.g.,
+    /// because the build failed).
+    fn stop(self) {
+        self.done.store(true, Ordering::SeqCst);
+        let _ = self.handle.join();
+    }
+}
+
+/// Writes every key in the stream to stdout, one per line. If `outputs` is
+/// true, then each line also includes the key's value.
+fn print_stream<S>(mut stream: S, outputs: bool) -> Result<()>
+where S: for<'a> Streamer<'a, Item=(&'a [u8], u64)> {
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    while let Some((key, value)) = stream.next() {
+        wtr.write_all(key)?;
+        if outputs {
+            write!(wtr, ",{}", value)?;
+        }
+        wtr.write_all(b"\n")?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
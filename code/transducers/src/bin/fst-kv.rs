@@ -0,0 +1,149 @@
+/*!
+`fst-kv` builds a key-value store whose values are arbitrary bytes and reads
+values back out of it.
+
+    fst-kv build --compress urls.tsv urls.kv
+    fst-kv get urls.kv https://example.com/
+    fst-kv range --start https://a --end https://b urls.kv
+*/
+
+extern crate fst;
+extern crate fst_regex;
+extern crate transducers;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use fst::Automaton;
+use fst_regex::Regex;
+
+use transducers::Result;
+use transducers::args::Args;
+use transducers::kv::{FstKv, FstKvBuilder, StreamBuilder};
+
+const USAGE: &'static str = "\
+Usage:
+    fst-kv build [--compress] <input> <store>
+    fst-kv get <store> <key>...
+    fst-kv range [--start KEY] [--end KEY] [--regex RE] <store>
+
+Commands:
+    build  Write a store to the directory <store> from <input>, which has one
+           `key<TAB>value` line per key, sorted by key with no duplicates.
+           With --compress, compress the values that get smaller for it.
+    get    Print the value of each key. Missing keys are reported on stderr,
+           and make the exit status 1.
+    range  Print `key<TAB>value` for every key greater than or equal to
+           --start and less than or equal to --end that matches --regex.
+";
+
+fn main() {
+    if let Err(err) = run() {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            if err.kind() == io::ErrorKind::BrokenPipe {
+                return;
+            }
+        }
+        let _ = writeln!(io::stderr(), "{}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let mut argv = env::args().skip(1);
+    let cmd = match argv.next() {
+        None => return Err(From::from(USAGE)),
+        Some(cmd) => cmd,
+    };
+    let args = Args::parse(argv, &["compress"], &["start", "end", "regex"])?;
+    match &*cmd {
+        "build" => cmd_build(&args),
+        "get" => cmd_get(&args),
+        "range" => cmd_range(&args),
+        "-h" | "--help" | "help" => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(From::from(format!("unknown command: {}\n\n{}", cmd, USAGE))),
+    }
+}
+
+fn cmd_build(args: &Args) -> Result<()> {
+    let rdr = io::BufReader::new(File::open(args.arg(0, "input")?)?);
+    let mut builder = FstKvBuilder::new(args.arg(1, "store")?)?;
+    builder.compress(args.switch("compress"));
+    for (i, line) in rdr.split(b'\n').enumerate() {
+        let line = line?;
+        let tab = match line.iter().position(|&b| b == b'\t') {
+            None => {
+                return Err(From::from(format!(
+                    "line {}: expected `key<TAB>value`", i + 1)));
+            }
+            Some(tab) => tab,
+        };
+        builder.insert(&line[..tab], &line[tab + 1..])
+            .map_err(|err| format!("line {}: {}", i + 1, err))?;
+    }
+    builder.finish()
+}
+
+fn cmd_get(args: &Args) -> Result<()> {
+    let kv = FstKv::open(args.arg(0, "store")?)?;
+    args.arg(1, "key")?;
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    let mut missing = 0;
+    for key in &args.positional()[1..] {
+        match kv.get(key)? {
+            None => {
+                missing += 1;
+                wtr.flush()?;
+                writeln!(io::stderr(), "{}: not found", key)?;
+            }
+            Some(value) => {
+                wtr.write_all(&value)?;
+                wtr.write_all(b"\n")?;
+            }
+        }
+    }
+    wtr.flush()?;
+    if missing > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn cmd_range(args: &Args) -> Result<()> {
+    let kv = FstKv::open(args.arg(0, "store")?)?;
+    match args.value("regex") {
+        None => print_range(kv.range(), args),
+        Some(re) => print_range(kv.search(Regex::new(re)?), args),
+    }
+}
+
+fn print_range<A: Automaton>(
+    mut builder: StreamBuilder<A>,
+    args: &Args,
+) -> Result<()> {
+    if let Some(start) = args.value("start") {
+        builder = builder.ge(start);
+    }
+    if let Some(end) = args.value("end") {
+        builder = builder.le(end);
+    }
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    let mut stream = builder.into_stream();
+    while let Some((key, value)) = stream.next()? {
+        wtr.write_all(key)?;
+        wtr.write_all(b"\t")?;
+        wtr.write_all(value)?;
+        wtr.write_all(b"\n")?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
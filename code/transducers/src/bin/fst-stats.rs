@@ -0,0 +1,89 @@
+/*!
+`fst-stats` prints statistics about the structure of an FST.
+
+    fst-stats --input /usr/share/dict/words words.fst
+*/
+
+extern crate fst;
+extern crate transducers;
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+use transducers::Result;
+use transducers::args::Args;
+use transducers::stats::Stats;
+
+const USAGE: &'static str = "\
+Usage:
+    fst-stats [--input FILE] <fst>
+
+Options:
+    --input FILE  The file the FST was built from. When given, the size of
+                  the FST is compared with the size of this file.
+";
+
+fn main() {
+    if let Err(err) = run() {
+        let _ = writeln!(io::stderr(), "{}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let args = Args::parse(env::args().skip(1), &["help"], &["input"])?;
+    if args.switch("help") {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+    let map = transducers::open_map(args.arg(0, "fst")?)?;
+    let stats = Stats::new(map.as_fst());
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    writeln!(wtr, "keys:              {}", stats.keys)?;
+    writeln!(wtr, "states:            {}", stats.states)?;
+    writeln!(wtr, "final states:      {} ({:.1}%)",
+             stats.final_states, 100.0 * stats.final_ratio())?;
+    writeln!(wtr, "transitions:       {} ({:.2} per state)",
+             stats.transitions, stats.avg_out_degree())?;
+    writeln!(wtr, "key length:        max {}, avg {:.1}",
+             stats.max_key_len, stats.avg_key_len())?;
+    writeln!(wtr, "size:              {} ({:.2} bytes/key)",
+             human_size(stats.size), stats.bytes_per_key())?;
+    if let Some(input) = args.value("input") {
+        let input_size = fs::metadata(input)?.len();
+        writeln!(wtr, "input size:        {}", human_size(input_size))?;
+        writeln!(wtr, "compression ratio: {:.1}%",
+                 100.0 * stats.compression_ratio(input_size))?;
+    }
+    writeln!(wtr, "out-degree:")?;
+    for (degree, &count) in stats.out_degrees.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        writeln!(wtr, "  {:>3}  {:>12}  ({:.1}%)",
+                 degree, count, 100.0 * count as f64 / stats.states as f64)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Format a number of bytes the way the blog post does, e.g., `324 KB`.
+fn human_size(bytes: u64) -> String {
+    const UNITS: &'static [&'static str] = &["B", "KB", "MB", "GB", "TB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
@@ -0,0 +1,149 @@
+/*!
+`fst-text` builds a full text index of plain text files and searches it.
+
+    fst-text build gutenberg.idx ~/data/gutenberg
+    fst-text search --phrase gutenberg.idx white whale
+    fst-text search gutenberg.idx ahab~1 '/harpoon(s|ed)?/'
+*/
+
+extern crate transducers;
+
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+use transducers::Result;
+use transducers::args::Args;
+use transducers::inverted::{Index, IndexBuilder, Query};
+
+const USAGE: &'static str = "\
+Usage:
+    fst-text build [--memory MB] [--tmp-dir DIR] <index> <path>...
+    fst-text search [--any | --phrase] [--positions] <index> <term>...
+
+Commands:
+    build   Write an index of every file in the given paths (directories are
+            searched recursively) to the directory <index>. Terms are sorted
+            buffering at most --memory megabytes (default: 128) and spilling
+            sorted runs to --tmp-dir.
+    search  Print the name of every document containing all of the terms.
+            With --any, print documents containing any of them instead, and
+            with --phrase, documents containing all of them in order. A term
+            written as `word~N` matches every term within N edits of `word`,
+            and one written as `/regex/` matches every term matching the
+            regex.
+
+Options:
+    --positions  Also print the word positions of the matches in each
+                 document.
+";
+
+fn main() {
+    if let Err(err) = run() {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            if err.kind() == io::ErrorKind::BrokenPipe {
+                return;
+            }
+        }
+        let _ = writeln!(io::stderr(), "{}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let mut argv = env::args().skip(1);
+    let cmd = match argv.next() {
+        None => return Err(From::from(USAGE)),
+        Some(cmd) => cmd,
+    };
+    let args = Args::parse(
+        argv,
+        &["any", "phrase", "positions"],
+        &["memory", "tmp-dir"],
+    )?;
+    match &*cmd {
+        "build" => cmd_build(&args),
+        "search" => cmd_search(&args),
+        "-h" | "--help" | "help" => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(From::from(format!("unknown command: {}\n\n{}", cmd, USAGE))),
+    }
+}
+
+fn cmd_build(args: &Args) -> Result<()> {
+    let mut builder = IndexBuilder::new(args.arg(0, "index")?)?;
+    builder.sorter()
+        .max_memory(args.parsed_or::<usize>("memory", 128)? * (1 << 20));
+    if let Some(dir) = args.value("tmp-dir") {
+        builder.sorter().tmp_dir(dir);
+    }
+    args.arg(1, "path")?;
+    for path in &args.positional()[1..] {
+        if Path::new(path).is_dir() {
+            builder.add_dir(path)?;
+        } else {
+            builder.add_file(path)?;
+        }
+    }
+    builder.finish()
+}
+
+fn cmd_search(args: &Args) -> Result<()> {
+    if args.switch("any") && args.switch("phrase") {
+        return Err(From::from("--any and --phrase can't be used together"));
+    }
+    let index = Index::open(args.arg(0, "index")?)?;
+    args.arg(1, "term")?;
+    let mut terms = vec![];
+    for term in &args.positional()[1..] {
+        terms.push(parse_term(term)?);
+    }
+    let query = if args.switch("phrase") {
+        Query::Phrase(terms)
+    } else if args.switch("any") {
+        Query::Or(terms)
+    } else {
+        Query::And(terms)
+    };
+
+    let stdout = io::stdout();
+    let mut wtr = io::BufWriter::new(stdout.lock());
+    for posting in index.search(&query)? {
+        let doc = match index.docs().get(posting.doc as usize) {
+            None => {
+                return Err(From::from(format!(
+                    "corrupt index: document {} is out of bounds",
+                    posting.doc)));
+            }
+            Some(doc) => doc,
+        };
+        write!(wtr, "{}", doc)?;
+        if args.switch("positions") {
+            write!(wtr, ":")?;
+            for pos in &posting.positions {
+                write!(wtr, " {}", pos)?;
+            }
+        }
+        writeln!(wtr)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Parse a term given on the command line, which is either a regex between
+/// slashes, a word followed by `~` and an edit distance, or a word.
+fn parse_term(term: &str) -> Result<Query> {
+    if term.len() >= 2 && term.starts_with('/') && term.ends_with('/') {
+        return Ok(Query::Regex(term[1..term.len() - 1].to_string()));
+    }
+    if let Some(i) = term.rfind('~') {
+        let distance = term[i + 1..].parse().map_err(|_| {
+            format!("invalid edit distance in {:?}", term)
+        })?;
+        return Ok(Query::Fuzzy(term[..i].to_string(), distance));
+    }
+    Ok(Query::Term(term.to_string()))
+}
@@ -0,0 +1,122 @@
+/*!
+The bounds of a range query, shared by the searches that can be restricted to
+a range of keys and resumed after the last key they returned.
+
+Both `page::Paginator` and `shard::SearchBuilder` keep their bounds around
+and run the same search many times: once per page, or once per batch of
+results from a shard. Each run starts just after the last key of the run
+before it, which has to be combined with the query's own lower bound. That
+logic lives here, so the two can't disagree about it.
+*/
+
+use std::ops::Bound;
+
+use fst::{Automaton, IntoStreamer};
+use fst::raw::{Fst, Stream};
+
+/// A lower and an upper bound on keys, either of which may be missing.
+#[derive(Clone, Debug)]
+pub struct Bounds {
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+}
+
+impl Bounds {
+    /// Bounds that include every key.
+    pub fn new() -> Bounds {
+        Bounds { lower: Bound::Unbounded, upper: Bound::Unbounded }
+    }
+
+    /// Only include keys greater than or equal to `bound`.
+    pub fn ge(&mut self, bound: &[u8]) {
+        self.lower = Bound::Included(bound.to_vec());
+    }
+
+    /// Only include keys greater than `bound`.
+    pub fn gt(&mut self, bound: &[u8]) {
+        self.lower = Bound::Excluded(bound.to_vec());
+    }
+
+    /// Only include keys less than or equal to `bound`.
+    pub fn le(&mut self, bound: &[u8]) {
+        self.upper = Bound::Included(bound.to_vec());
+    }
+
+    /// Only include keys less than `bound`.
+    pub fn lt(&mut self, bound: &[u8]) {
+        self.upper = Bound::Excluded(bound.to_vec());
+    }
+
+    /// Returns a stream of the keys in `fst` that match `aut` and are within
+    /// the bounds. If `after` is given, the stream starts after it instead
+    /// of at the lower bound, unless the lower bound comes later.
+    pub fn search<'f, A: Automaton>(
+        &self,
+        fst: &'f Fst,
+        aut: A,
+        after: Option<&[u8]>,
+    ) -> Stream<'f, A> {
+        let mut builder = fst.search(aut);
+        builder = match self.lower(after) {
+            Bound::Included(key) => builder.ge(key),
+            Bound::Excluded(key) => builder.gt(key),
+            Bound::Unbounded => builder,
+        };
+        builder = match self.upper {
+            Bound::Included(ref key) => builder.le(key),
+            Bound::Excluded(ref key) => builder.lt(key),
+            Bound::Unbounded => builder,
+        };
+        builder.into_stream()
+    }
+
+    /// The lower bound of a search that starts after `after`, which is the
+    /// greater of `after` and the query's own lower bound.
+    fn lower<'a>(&'a self, after: Option<&'a [u8]>) -> Bound<&'a [u8]> {
+        let after = match after {
+            None => {
+                return match self.lower {
+                    Bound::Included(ref key) => Bound::Included(key),
+                    Bound::Excluded(ref key) => Bound::Excluded(key),
+                    Bound::Unbounded => Bound::Unbounded,
+                };
+            }
+            Some(after) => after,
+        };
+        match self.lower {
+            Bound::Included(ref key) if **key > *after => {
+                Bound::Included(key)
+            }
+            Bound::Excluded(ref key) if **key >= *after => {
+                Bound::Excluded(key)
+            }
+            _ => Bound::Excluded(after),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use super::Bounds;
+
+    #[test]
+    fn lower() {
+        let mut bounds = Bounds::new();
+        assert_eq!(bounds.lower(None), Bound::Unbounded);
+        assert_eq!(bounds.lower(Some(b"b")), Bound::Excluded(&b"b"[..]));
+
+        bounds.ge(b"m");
+        assert_eq!(bounds.lower(None), Bound::Included(&b"m"[..]));
+        assert_eq!(bounds.lower(Some(b"a")), Bound::Included(&b"m"[..]));
+        assert_eq!(bounds.lower(Some(b"m")), Bound::Excluded(&b"m"[..]));
+        assert_eq!(bounds.lower(Some(b"z")), Bound::Excluded(&b"z"[..]));
+
+        bounds.gt(b"m");
+        assert_eq!(bounds.lower(None), Bound::Excluded(&b"m"[..]));
+        assert_eq!(bounds.lower(Some(b"a")), Bound::Excluded(&b"m"[..]));
+        assert_eq!(bounds.lower(Some(b"m")), Bound::Excluded(&b"m"[..]));
+        assert_eq!(bounds.lower(Some(b"m\0")), Bound::Excluded(&b"m\0"[..]));
+    }
+}
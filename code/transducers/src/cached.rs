@@ -0,0 +1,867 @@
+/*!
+Reading FSTs through a fixed-size block cache instead of a memory map.
+
+`fst::raw::Fst` needs every byte of the FST in one slice, which in practice
+means a memory map (or reading the whole file into memory). A memory map is
+the right choice almost everywhere, since the kernel's page cache does the
+caching, but it also means the process's memory use is whatever the kernel
+decides, which doesn't go over well in containers with a hard memory limit.
+
+`CachedFst` reads the FST straight from a file instead. The file is read in
+blocks of a fixed size, and the blocks most recently used are kept in an LRU
+cache with room for a fixed number of them, so memory use is bounded by
+`block_size * blocks` no matter how big the FST is. The cache counts its hits
+and misses, which is what to look at when choosing its size: lookups start
+at the root, which is at the end of the file, so the blocks near the end are
+hot and a cache that holds them turns most reads into hits.
+
+Since `fst` can't read nodes from anything but a slice, this module decodes
+the nodes itself, following the format written by `fst::raw::Builder`. Only
+what lookups and streaming need is supported: `get`, `contains_key` and a
+stream of the keys matching an automaton, in order. Reads can fail, so unlike
+their `fst` counterparts, these return a `Result`, and `Stream` has its own
+`next` method instead of implementing `Streamer`.
+
+Sealed files (see the `integrity` module) are read by skipping their header.
+Their checksum isn't checked, since that would mean reading the whole file;
+use `integrity::verify` for that. A corrupt FST can still return wrong
+results, but a node or transition that points outside the file is an error
+rather than a panic.
+*/
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Mutex;
+
+use fst::Automaton;
+use fst::automaton::AlwaysMatch;
+
+use Result;
+use integrity;
+
+/// The address of the empty final state, which takes no space.
+const EMPTY_ADDRESS: usize = 0;
+
+/// Nodes with more transitions than this have an index from input byte to
+/// transition (in FSTs of version 2 or newer).
+const TRANS_INDEX_THRESHOLD: usize = 32;
+
+/// The bytes that the format can encode in a node's state byte, indexed by
+/// one less than their code.
+const COMMON_INPUTS: &'static [u8; 63] =
+    b"te/oasripcnw.hlm-du012g=:bf3y5&_4v9678k%?xCDASFIBEjPTzRNM+LOqHG";
+
+/// An FST read from a file through a block cache.
+pub struct CachedFst {
+    cache: BlockCache,
+    version: u64,
+    len: u64,
+    root: Node,
+}
+
+impl CachedFst {
+    /// Open the FST in the file at `path`, caching at most `blocks` blocks
+    /// of `block_size` bytes each.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        block_size: usize,
+        blocks: usize,
+    ) -> Result<CachedFst> {
+        let mut file = File::open(&path)?;
+        let (offset, size) = if integrity::is_sealed(&path)? {
+            let header = integrity::read_header(&mut file)?;
+            (header.len, header.fst_len)
+        } else {
+            (0, file.metadata()?.len())
+        };
+        if size < 32 {
+            return Err(From::from(format!(
+                "{} bytes is too small to be an FST", size)));
+        }
+        let cache = BlockCache::new(file, offset, size, block_size, blocks);
+        let version = read_u64(&cache.read(0, 8)?);
+        // Version 3 (written by fst 0.4) appends a checksum to the footer.
+        let end = match version {
+            1 | 2 => size,
+            3 => size - 4,
+            _ => {
+                return Err(From::from(format!(
+                    "unsupported FST version {}", version)));
+            }
+        };
+        let footer = cache.read(end - 16, 16)?;
+        let len = read_u64(&footer[..8]);
+        let root_addr = read_u64(&footer[8..]);
+        if root_addr >= end {
+            return Err(From::from("FST root address is out of bounds"));
+        }
+        let root = Node::read(&cache, version, root_addr as usize)?;
+        Ok(CachedFst {
+            cache: cache,
+            version: version,
+            len: len,
+            root: root,
+        })
+    }
+
+    /// The number of keys in the FST.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the FST has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the value of `key`, if it's in the FST. For sets, the value
+    /// is always `0`.
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<u64>> {
+        let mut out = 0;
+        let mut node = None;
+        for &b in key.as_ref() {
+            let t = {
+                let cur = node.as_ref().unwrap_or(&self.root);
+                match cur.find_input(b) {
+                    None => return Ok(None),
+                    Some(i) => cur.transition(i)?,
+                }
+            };
+            out = add_output(out, t.out)?;
+            node = Some(self.node(t.addr)?);
+        }
+        let node = node.as_ref().unwrap_or(&self.root);
+        if node.is_final {
+            Ok(Some(add_output(out, node.final_output)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns true if `key` is in the FST.
+    pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> Result<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Returns a stream of every key in the FST, with its value.
+    pub fn stream(&self) -> Stream<AlwaysMatch> {
+        self.search(AlwaysMatch)
+    }
+
+    /// Returns a stream of the keys matching `aut`, with their values.
+    pub fn search<A: Automaton>(&self, aut: A) -> Stream<A> {
+        Stream {
+            fst: self,
+            aut: aut,
+            stack: vec![],
+            key: vec![],
+            started: false,
+        }
+    }
+
+    /// Returns how often reads were served by the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    fn node(&self, addr: usize) -> Result<Node> {
+        Node::read(&self.cache, self.version, addr)
+    }
+}
+
+/// The keys of a `CachedFst` matching an automaton, in lexicographic order.
+pub struct Stream<'f, A: Automaton> {
+    fst: &'f CachedFst,
+    aut: A,
+    /// The nodes on the path to the current key, each with the index of the
+    /// next transition to follow, the output so far and the automaton's
+    /// state.
+    stack: Vec<(Node, usize, u64, A::State)>,
+    key: Vec<u8>,
+    started: bool,
+}
+
+impl<'f, A: Automaton> Stream<'f, A> {
+    /// Returns the next key and its value, or `None` once every key has been
+    /// returned.
+    pub fn next(&mut self) -> Result<Option<(&[u8], u64)>> {
+        if !self.started {
+            self.started = true;
+            let state = self.aut.start();
+            let root = self.fst.root.clone();
+            let matched = root.is_final && self.aut.is_match(&state);
+            let out = root.final_output;
+            self.stack.push((root, 0, 0, state));
+            if matched {
+                return Ok(Some((&[], out)));
+            }
+        }
+        loop {
+            let next = match self.stack.last_mut() {
+                None => return Ok(None),
+                Some(&mut (ref node, ref mut i, out, ref state)) => {
+                    if *i < node.ntrans {
+                        let t = node.transition(*i)?;
+                        *i += 1;
+                        let out = add_output(out, t.out)?;
+                        Some((t, self.aut.accept(state, t.inp), out))
+                    } else {
+                        None
+                    }
+                }
+            };
+            let (t, state, out) = match next {
+                Some(next) => next,
+                None => {
+                    self.stack.pop();
+                    self.key.pop();
+                    continue;
+                }
+            };
+            if !self.aut.can_match(&state) {
+                continue;
+            }
+            let node = self.fst.node(t.addr)?;
+            let matched = node.is_final && self.aut.is_match(&state);
+            let final_out = add_output(out, node.final_output)?;
+            self.key.push(t.inp);
+            self.stack.push((node, 0, out, state));
+            if matched {
+                return Ok(Some((&self.key, final_out)));
+            }
+        }
+    }
+}
+
+/// How often the cache had the block a read needed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    /// Block reads served from the cache.
+    pub hits: u64,
+    /// Block reads that went to the file.
+    pub misses: u64,
+}
+
+/// A file read in fixed-size blocks, the most recently used of which are
+/// cached.
+struct BlockCache {
+    block_size: usize,
+    /// Where the FST starts in the file.
+    offset: u64,
+    /// The length of the FST in bytes.
+    size: u64,
+    inner: Mutex<Lru>,
+}
+
+impl BlockCache {
+    fn new(
+        file: File,
+        offset: u64,
+        size: u64,
+        block_size: usize,
+        blocks: usize,
+    ) -> BlockCache {
+        BlockCache {
+            block_size: if block_size == 0 { 1 } else { block_size },
+            offset: offset,
+            size: size,
+            inner: Mutex::new(Lru {
+                file: file,
+                capacity: if blocks == 0 { 1 } else { blocks },
+                index: HashMap::new(),
+                entries: vec![],
+                head: None,
+                tail: None,
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    /// Read `len` bytes of the FST starting at `start`.
+    fn read(&self, start: u64, len: usize) -> io::Result<Vec<u8>> {
+        let end = start + len as u64;
+        if end > self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof, "read past the end of the FST"));
+        }
+        let mut lru = self.inner.lock().expect("block cache lock poisoned");
+        let mut buf = Vec::with_capacity(len);
+        let bs = self.block_size as u64;
+        let mut pos = start;
+        while pos < end {
+            let block = pos / bs;
+            let block_start = block * bs;
+            let block_len = ::std::cmp::min(bs, self.size - block_start);
+            let data = lru.get(block, self.offset + block_start, block_len)?;
+            let from = (pos - block_start) as usize;
+            let to = (::std::cmp::min(end, block_start + bs) - block_start)
+                as usize;
+            buf.extend_from_slice(&data[from..to]);
+            pos = block_start + bs;
+        }
+        Ok(buf)
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.inner.lock().expect("block cache lock poisoned").stats
+    }
+}
+
+/// The blocks in the cache, as a doubly linked list from most to least
+/// recently used, stored in a `Vec` and indexed by block number.
+struct Lru {
+    file: File,
+    capacity: usize,
+    index: HashMap<u64, usize>,
+    entries: Vec<Entry>,
+    head: Option<usize>,
+    tail: Option<usize>,
+    stats: CacheStats,
+}
+
+struct Entry {
+    block: u64,
+    data: Vec<u8>,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+impl Lru {
+    /// Returns the block with the given number, which is `len` bytes at
+    /// `pos` in the file, reading it if it isn't cached.
+    fn get(&mut self, block: u64, pos: u64, len: u64) -> io::Result<&[u8]> {
+        if let Some(&i) = self.index.get(&block) {
+            self.stats.hits += 1;
+            self.unlink(i);
+            self.push_front(i);
+            return Ok(&self.entries[i].data);
+        }
+        self.stats.misses += 1;
+        let mut data = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(pos))?;
+        self.file.read_exact(&mut data)?;
+        let i = if self.entries.len() < self.capacity {
+            self.entries.push(Entry {
+                block: block,
+                data: data,
+                prev: None,
+                next: None,
+            });
+            self.entries.len() - 1
+        } else {
+            // Reuse the least recently used entry.
+            let i = self.tail.expect("a full cache has a tail");
+            self.unlink(i);
+            self.index.remove(&self.entries[i].block);
+            self.entries[i].block = block;
+            self.entries[i].data = data;
+            i
+        };
+        self.index.insert(block, i);
+        self.push_front(i);
+        Ok(&self.entries[i].data)
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.entries[i].prev, self.entries[i].next);
+        match prev {
+            Some(p) => self.entries[p].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(n) => self.entries[n].prev = prev,
+            None => self.tail = prev,
+        }
+        self.entries[i].prev = None;
+        self.entries[i].next = None;
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.entries[i].next = self.head;
+        if let Some(h) = self.head {
+            self.entries[h].prev = Some(i);
+        }
+        self.head = Some(i);
+        if self.tail.is_none() {
+            self.tail = Some(i);
+        }
+    }
+}
+
+/// A transition from one node to another.
+#[derive(Clone, Copy, Debug)]
+struct Transition {
+    inp: u8,
+    out: u64,
+    addr: usize,
+}
+
+/// The kinds of node in the format, named after the encodings used by
+/// `fst::raw`.
+#[derive(Clone, Copy, Debug)]
+enum State {
+    /// One transition to the node written just before this one, with no
+    /// output.
+    OneTransNext,
+    /// One transition, with an output.
+    OneTrans,
+    /// Any number of transitions, possibly final.
+    AnyTrans,
+    /// A final node with no transitions and no output, which isn't written.
+    EmptyFinal,
+}
+
+/// A node, along with a copy of the bytes it's encoded in.
+#[derive(Clone, Debug)]
+struct Node {
+    version: u64,
+    state: State,
+    /// The state byte, which is the last byte of the node.
+    byte: u8,
+    /// The address of the node, i.e., the address of its state byte.
+    start: usize,
+    /// The address of the first byte of the node.
+    end: usize,
+    /// The bytes at `end..start + 1`.
+    data: Vec<u8>,
+    is_final: bool,
+    ntrans: usize,
+    /// The number of bytes in each transition address.
+    tsize: usize,
+    /// The number of bytes in each output.
+    osize: usize,
+    final_output: u64,
+}
+
+impl Node {
+    fn read(cache: &BlockCache, version: u64, addr: usize) -> Result<Node> {
+        let mut node = Node {
+            version: version,
+            state: State::EmptyFinal,
+            byte: 0,
+            start: addr,
+            end: addr,
+            data: vec![],
+            is_final: true,
+            ntrans: 0,
+            tsize: 0,
+            osize: 0,
+            final_output: 0,
+        };
+        if addr == EMPTY_ADDRESS {
+            return Ok(node);
+        }
+        // The state byte and the two bytes before it are enough to find
+        // where the node begins. Every offset below the state byte is
+        // checked, since a corrupt file can put any of them before the
+        // start of the FST.
+        let head_start = addr.saturating_sub(2);
+        let head = cache.read(head_start as u64, addr - head_start + 1)?;
+        let below = |n: usize| {
+            addr.checked_sub(n).ok_or_else(|| bad_node(addr))
+        };
+        let at = |i: usize| head[i - head_start];
+        node.byte = at(addr);
+        node.is_final = false;
+        match node.byte >> 6 {
+            0b11 => {
+                node.state = State::OneTransNext;
+                node.ntrans = 1;
+                node.end = below(node.input_len())?;
+            }
+            0b10 => {
+                node.state = State::OneTrans;
+                node.ntrans = 1;
+                let sizes = at(below(node.input_len() + 1)?);
+                node.tsize = (sizes >> 4) as usize;
+                node.osize = (sizes & 0b1111) as usize;
+                node.end = below(node.input_len() + 1 + node.tsize
+                                 + node.osize)?;
+            }
+            _ => {
+                node.state = State::AnyTrans;
+                node.is_final = node.byte & 0b01_000000 != 0;
+                node.ntrans = match node.byte & 0b00_111111 {
+                    // 1 can't be the number of transitions here, since it
+                    // always fits in the state byte, so it means 256.
+                    0 => match at(below(1)?) { 1 => 256, n => n as usize },
+                    n => n as usize,
+                };
+                let sizes = at(below(node.ntrans_len() + 1)?);
+                node.tsize = (sizes >> 4) as usize;
+                node.osize = (sizes & 0b1111) as usize;
+                let final_osize = if node.is_final { node.osize } else { 0 };
+                node.end = below(node.ntrans_len() + 1
+                                 + node.total_trans_size()
+                                 + node.ntrans * node.osize
+                                 + final_osize)?;
+            }
+        }
+        // Outputs and addresses are decoded into a u64.
+        if node.tsize > 8 || node.osize > 8 {
+            return Err(bad_node(addr));
+        }
+        node.data = cache.read(node.end as u64, addr - node.end + 1)?;
+        if let State::AnyTrans = node.state {
+            if node.is_final && node.osize > 0 {
+                let at = node.end;
+                node.final_output = node.uint(at, node.osize);
+            }
+        }
+        Ok(node)
+    }
+
+    /// Returns the `i`th transition.
+    ///
+    /// `read` checked that the node's bytes are all in the FST, so offsets
+    /// within the node can't go out of range, but the address a transition
+    /// points to can.
+    fn transition(&self, i: usize) -> Result<Transition> {
+        Ok(match self.state {
+            State::OneTransNext => Transition {
+                inp: self.one_input(),
+                out: 0,
+                addr: self.end.checked_sub(1)
+                    .ok_or_else(|| bad_node(self.start))?,
+            },
+            State::OneTrans => {
+                let at = self.start - self.input_len() - 1 - self.tsize;
+                let out = if self.osize == 0 {
+                    0
+                } else {
+                    self.uint(at - self.osize, self.osize)
+                };
+                Transition {
+                    inp: self.one_input(),
+                    out: out,
+                    addr: self.delta(at)?,
+                }
+            }
+            State::AnyTrans => {
+                let inputs = self.start - self.ntrans_len() - 1
+                    - self.trans_index_size();
+                let addrs = inputs - self.ntrans - (i + 1) * self.tsize;
+                let out = if self.osize == 0 {
+                    0
+                } else {
+                    let at = self.start - self.ntrans_len() - 1
+                        - self.total_trans_size()
+                        - (i + 1) * self.osize;
+                    self.uint(at, self.osize)
+                };
+                Transition {
+                    inp: self.at(inputs - i - 1),
+                    out: out,
+                    addr: self.delta(addrs)?,
+                }
+            }
+            State::EmptyFinal => panic!("the empty final state has no \
+                                         transitions"),
+        })
+    }
+
+    /// Returns the index of the transition on `b`, if there is one.
+    fn find_input(&self, b: u8) -> Option<usize> {
+        match self.state {
+            State::OneTransNext | State::OneTrans => {
+                if self.one_input() == b { Some(0) } else { None }
+            }
+            State::AnyTrans => {
+                let start = self.start - self.ntrans_len() - 1;
+                if self.trans_index_size() > 0 {
+                    let i = self.at(start - 256 + b as usize) as usize;
+                    if i < self.ntrans { Some(i) } else { None }
+                } else {
+                    // Inputs are stored in reverse order.
+                    (0..self.ntrans).find(|&i| self.at(start - i - 1) == b)
+                }
+            }
+            State::EmptyFinal => None,
+        }
+    }
+
+    /// The input of a node with one transition.
+    fn one_input(&self) -> u8 {
+        match common_input(self.byte) {
+            Some(b) => b,
+            None => self.at(self.start - 1),
+        }
+    }
+
+    /// The number of bytes used by the input of a node with one transition.
+    fn input_len(&self) -> usize {
+        if common_input(self.byte).is_some() { 0 } else { 1 }
+    }
+
+    /// The number of bytes used by the number of transitions, when it
+    /// doesn't fit in the state byte.
+    fn ntrans_len(&self) -> usize {
+        if self.byte & 0b00_111111 == 0 { 1 } else { 0 }
+    }
+
+    fn trans_index_size(&self) -> usize {
+        if self.version >= 2 && self.ntrans > TRANS_INDEX_THRESHOLD {
+            256
+        } else {
+            0
+        }
+    }
+
+    /// The number of bytes used by the inputs, addresses and index of the
+    /// transitions of an `AnyTrans` node.
+    fn total_trans_size(&self) -> usize {
+        self.ntrans + self.ntrans * self.tsize + self.trans_index_size()
+    }
+
+    fn at(&self, addr: usize) -> u8 {
+        self.data[addr - self.end]
+    }
+
+    /// Decode the little endian integer of `n` bytes at `addr`.
+    fn uint(&self, addr: usize, n: usize) -> u64 {
+        let mut v = 0;
+        for i in 0..n {
+            v |= (self.at(addr + i) as u64) << (8 * i);
+        }
+        v
+    }
+
+    /// Decode the transition address at `addr`, which is stored as its
+    /// distance from the first byte of this node.
+    fn delta(&self, addr: usize) -> Result<usize> {
+        if self.tsize == 0 {
+            return Ok(EMPTY_ADDRESS);
+        }
+        match self.uint(addr, self.tsize) as usize {
+            EMPTY_ADDRESS => Ok(EMPTY_ADDRESS),
+            delta => {
+                self.end.checked_sub(delta).ok_or_else(|| bad_node(self.start))
+            }
+        }
+    }
+}
+
+/// The error for a node at `addr` whose bytes or transitions aren't all in
+/// the FST.
+fn bad_node(addr: usize) -> Box<::std::error::Error + Send + Sync> {
+    From::from(format!("corrupt FST: node at address {} is out of bounds",
+                       addr))
+}
+
+/// Add two outputs, which only overflows in a corrupt FST.
+fn add_output(a: u64, b: u64) -> Result<u64> {
+    a.checked_add(b).ok_or_else(|| {
+        From::from("corrupt FST: an output doesn't fit in 64 bits")
+    })
+}
+
+/// Returns the input encoded in the low six bits of a state byte, if any.
+fn common_input(byte: u8) -> Option<u8> {
+    match byte & 0b00_111111 {
+        0 => None,
+        i => Some(COMMON_INPUTS[i as usize - 1]),
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use fst::{IntoStreamer, Map, MapBuilder};
+    use fst::automaton::Subsequence;
+
+    use super::{CachedFst, Stream};
+
+    /// Cache sizes to test with, as `(block_size, blocks)`, from a cache
+    /// with one small block, so nearly every read misses, to one that holds
+    /// every block.
+    const CACHES: &'static [(usize, usize)] =
+        &[(7, 1), (16, 3), (64, 4), (4096, 64)];
+
+    static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+    /// A file in the temporary directory that's removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(bytes: &[u8]) -> TempFile {
+            let name = format!("transducers-cached-{}-{}", process::id(),
+                               NEXT_FILE.fetch_add(1, Ordering::SeqCst));
+            let path = env::temp_dir().join(name);
+            File::create(&path).unwrap().write_all(bytes).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Returns the bytes of the map of `keys`, which are sorted and
+    /// deduplicated first.
+    fn build(mut keys: Vec<(Vec<u8>, u64)>) -> Vec<u8> {
+        keys.sort();
+        keys.dedup_by(|a, b| a.0 == b.0);
+        let mut builder = MapBuilder::memory();
+        for (k, v) in keys {
+            builder.insert(k, v).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn collect<A: ::fst::Automaton>(
+        mut stream: Stream<A>,
+    ) -> Vec<(Vec<u8>, u64)> {
+        let mut out = vec![];
+        while let Some((k, v)) = stream.next().unwrap() {
+            out.push((k.to_vec(), v));
+        }
+        out
+    }
+
+    /// Check that every lookup and search agrees with `fst::Map`, with every
+    /// size of cache.
+    fn check(keys: Vec<(Vec<u8>, u64)>) {
+        let bytes = build(keys);
+        let file = TempFile::new(&bytes);
+        let map = Map::from_bytes(bytes).unwrap();
+        let all = map.stream().into_byte_vec();
+
+        // Lookups of every key, and of keys on either side of each one.
+        let mut lookups = vec![vec![], vec![0], vec![255]];
+        for &(ref k, _) in &all {
+            lookups.push(k.clone());
+            lookups.push(k[..k.len().saturating_sub(1)].to_vec());
+            let mut after = k.clone();
+            after.push(0);
+            lookups.push(after.clone());
+            *after.last_mut().unwrap() = 255;
+            lookups.push(after);
+        }
+        for &(block_size, blocks) in CACHES {
+            let fst = CachedFst::open(&file.0, block_size, blocks).unwrap();
+            assert_eq!(fst.len(), map.len() as u64);
+            for key in &lookups {
+                assert_eq!(fst.get(key).unwrap(), map.get(key), "{:?}", key);
+            }
+            assert_eq!(collect(fst.stream()), all);
+            for sub in &["", "a", "ba", "zz"] {
+                assert_eq!(
+                    collect(fst.search(Subsequence::new(sub))),
+                    map.search(Subsequence::new(sub))
+                        .into_stream()
+                        .into_byte_vec());
+            }
+        }
+    }
+
+    /// A xorshift generator, so the random tests are the same every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    fn random_keys(
+        n: usize,
+        alphabet: &[u8],
+        max_len: u64,
+    ) -> Vec<(Vec<u8>, u64)> {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        (0..n).map(|_| {
+            let len = rng.next_u64() % (max_len + 1);
+            let key = (0..len)
+                .map(|_| alphabet[rng.next_u64() as usize % alphabet.len()])
+                .collect();
+            // Mix small outputs with ones that need all eight bytes.
+            let value = match rng.next_u64() & 1 {
+                0 => rng.next_u64() % 100,
+                _ => rng.next_u64(),
+            };
+            (key, value)
+        }).collect()
+    }
+
+    #[test]
+    fn random() {
+        check(random_keys(1000, b"abz", 8));
+        check(random_keys(1000, b"abcdefghijklmnopqrstuvwxyz./:", 12));
+    }
+
+    #[test]
+    fn every_byte() {
+        check(random_keys(1000, &(0..256).map(|b| b as u8)
+                                         .collect::<Vec<u8>>(), 3));
+    }
+
+    #[test]
+    fn many_transitions() {
+        let mut keys = vec![];
+        // The root and the node after `x` have a transition on every byte,
+        // the node after `y` has more than 32 and the one after `z` fewer.
+        for b in 0..256u64 {
+            keys.push((vec![b as u8], b));
+            keys.push((vec![b'x', b as u8], 1000 + b));
+        }
+        for b in 0..40u64 {
+            keys.push((vec![b'y', (b * 5) as u8], b));
+        }
+        for b in 0..20u64 {
+            keys.push((vec![b'z', (b * 11) as u8, b'!'], 0));
+        }
+        check(keys);
+    }
+
+    #[test]
+    fn empty_key() {
+        check(vec![(vec![], 0)]);
+        check(vec![(vec![], 42)]);
+        check(vec![(vec![], 7), (b"a".to_vec(), 3), (b"ab".to_vec(), 0)]);
+        check(vec![]);
+    }
+
+    #[test]
+    fn corrupt() {
+        let keys = random_keys(50, b"abcdef", 4);
+        let bytes = build(keys.clone());
+        let file = TempFile::new(&[]);
+        // Every corruption of a single byte either opens and reads without
+        // panicking or fails to open.
+        for i in 0..bytes.len() {
+            for &b in &[0x00, 0xff, bytes[i] ^ 0x55] {
+                let mut corrupt = bytes.clone();
+                corrupt[i] = b;
+                File::create(&file.0).unwrap().write_all(&corrupt).unwrap();
+                let fst = match CachedFst::open(&file.0, 8, 2) {
+                    Err(_) => continue,
+                    Ok(fst) => fst,
+                };
+                for &(ref k, _) in &keys {
+                    let _ = fst.get(k);
+                }
+                let mut stream = fst.stream();
+                while let Ok(Some(_)) = stream.next() {}
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+/*!
+Combining automata.
+
+`setop-regex` intersects the results of a single regex with a whole set.
+Combinators instead build one automaton out of several, so a query like
+"within one edit of `springsten` and contains a space" runs in a single
+traversal of the FST, and whole subtrees are skipped as soon as either side
+can no longer match. `fst::Automaton` already provides most of them:
+
+* `a.intersection(b)` matches keys matched by both `a` and `b`.
+* `a.union(b)` matches keys matched by either `a` or `b`.
+* `a.complement()` matches keys not matched by `a`.
+* `a.starts_with()` matches keys that have a prefix matched by `a`.
+
+This module adds the one it's missing, `a.prefix_of()`, which matches keys
+that are a prefix of some key matched by `a`. It's available on every
+automaton by importing `AutomatonExt`.
+*/
+
+use fst::Automaton;
+
+/// Combinators available on every automaton, in addition to those provided
+/// by `fst::Automaton`.
+pub trait AutomatonExt: Automaton + Sized {
+    /// Match every key that is a prefix of (or equal to) a key that `self`
+    /// matches.
+    ///
+    /// This relies on `can_match` being exact, i.e., returning false only
+    /// when no extension of the key can match. That's true of `Levenshtein`
+    /// and of the automata in this crate, so, e.g., the prefixes of every
+    /// string within one edit of `springsteen` include `sprin` and `sprn`.
+    /// An automaton whose `can_match` always returns true would make this
+    /// match everything.
+    fn prefix_of(self) -> PrefixOf<Self> {
+        PrefixOf(self)
+    }
+}
+
+impl<A: Automaton> AutomatonExt for A {}
+
+/// An automaton that matches any key that is a prefix of a key matched by
+/// `A`.
+#[derive(Clone, Debug)]
+pub struct PrefixOf<A>(A);
+
+impl<A: Automaton> Automaton for PrefixOf<A> {
+    type State = A::State;
+
+    fn start(&self) -> A::State {
+        self.0.start()
+    }
+
+    fn is_match(&self, state: &A::State) -> bool {
+        self.0.can_match(state)
+    }
+
+    fn can_match(&self, state: &A::State) -> bool {
+        self.0.can_match(state)
+    }
+
+    fn accept(&self, state: &A::State, byte: u8) -> A::State {
+        self.0.accept(state, byte)
+    }
+}
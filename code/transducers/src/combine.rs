@@ -0,0 +1,119 @@
+/*!
+Union of maps where the values of duplicate keys are combined.
+
+`fst::map::OpBuilder::union` yields every key once along with *all* of the
+values associated with it, one for each map that contains it. `Union` reduces
+those values to a single `u64` with a `Reducer`, which makes it possible to,
+say, merge word frequency maps built from separate shards of a corpus by
+summing the counts.
+
+Since `Union` is itself a stream of `(key, value)` pairs, it can be passed
+directly to `MapBuilder::extend_stream`. Like every other set operation, only
+one key from each input is in memory at a time.
+*/
+
+use std::fmt;
+use std::str::FromStr;
+
+use fst::Streamer;
+use fst::map::{self, IndexedValue};
+
+use Result;
+
+/// How to combine the values of a key that appears in more than one map.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Reducer {
+    /// Add the values together. The sum saturates at `u64::MAX`.
+    Sum,
+    /// Take the largest value.
+    Max,
+    /// Take the smallest value.
+    Min,
+    /// Take the value from the first map (in the order they were added) that
+    /// contains the key.
+    First,
+    /// Take the value from the last map (in the order they were added) that
+    /// contains the key.
+    Last,
+}
+
+impl Reducer {
+    /// Combine the values associated with a single key into one value.
+    ///
+    /// `values` is never empty when it comes from a union.
+    pub fn reduce(&self, values: &[IndexedValue]) -> u64 {
+        let it = values.iter();
+        match *self {
+            Reducer::Sum => it.fold(0, |sum, iv| sum.saturating_add(iv.value)),
+            Reducer::Max => it.map(|iv| iv.value).max().unwrap_or(0),
+            Reducer::Min => it.map(|iv| iv.value).min().unwrap_or(0),
+            // The values of a union aren't necessarily sorted by stream
+            // index, so look for the smallest/largest one explicitly.
+            Reducer::First => {
+                it.min_by_key(|iv| iv.index).map_or(0, |iv| iv.value)
+            }
+            Reducer::Last => {
+                it.max_by_key(|iv| iv.index).map_or(0, |iv| iv.value)
+            }
+        }
+    }
+}
+
+impl FromStr for Reducer {
+    type Err = Box<::std::error::Error + Send + Sync>;
+
+    fn from_str(s: &str) -> Result<Reducer> {
+        match s {
+            "sum" => Ok(Reducer::Sum),
+            "max" => Ok(Reducer::Max),
+            "min" => Ok(Reducer::Min),
+            "first" => Ok(Reducer::First),
+            "last" => Ok(Reducer::Last),
+            _ => Err(From::from(format!(
+                "unknown reducer {:?} (expected one of sum, max, min, first, \
+                 last)", s))),
+        }
+    }
+}
+
+impl fmt::Display for Reducer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Reducer::Sum => "sum",
+            Reducer::Max => "max",
+            Reducer::Min => "min",
+            Reducer::First => "first",
+            Reducer::Last => "last",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A stream of the union of the keys in several maps, where each key's
+/// values are combined into one.
+///
+/// The `'m` lifetime parameter refers to the lifetime of the underlying maps.
+pub struct Union<'m> {
+    union: map::Union<'m>,
+    reducer: Reducer,
+}
+
+impl<'m> Union<'m> {
+    /// Create a union of all streams added to `op`, combining values with
+    /// `reducer`.
+    ///
+    /// Since `op` can contain any map stream, this works equally well on
+    /// whole maps, ranges or searches.
+    pub fn new(op: map::OpBuilder<'m>, reducer: Reducer) -> Union<'m> {
+        Union { union: op.union(), reducer: reducer }
+    }
+}
+
+impl<'a, 'm> Streamer<'a> for Union<'m> {
+    type Item = (&'a [u8], u64);
+
+    fn next(&'a mut self) -> Option<(&'a [u8], u64)> {
+        let reducer = self.reducer;
+        self.union.next().map(|(key, values)| (key, reducer.reduce(values)))
+    }
+}
@@ -0,0 +1,385 @@
+/*!
+Reproducible synthetic corpora.
+
+The experiments in the blog post use data sets that are too big (or too
+encumbered) to ship with this repository. This module generates stand-ins
+from a seed, so that benchmarks can be re-run anywhere and produce the same
+keys every time.
+
+Each `Kind` of corpus imitates the shape of one of the post's data sets:
+
+* `words`: a dictionary-like list of English-looking words.
+* `titles`: title cased, multiword titles like Wikipedia's, with common
+  words shared between many titles and the odd disambiguation like
+  `(film)`.
+* `urls`: URLs like Common Crawl's, where a few popular hosts account for
+  many URLs, so long shared prefixes are common, followed by paths a few
+  segments deep.
+* `dois`: DOI URLs like `http://dx.doi.org/10.1016/j.cell.2015.03.012`,
+  with a few hundred registrants using a handful of numbering schemes.
+
+Words in titles, host names and path segments are drawn from a fixed
+vocabulary with a Zipf-like distribution, which is what gives real data its
+shared prefixes and suffixes, and what FSTs compress so well.
+
+`Rng` is a small xorshift generator, which is plenty for making up keys and
+avoids depending on a particular version of `rand` for reproducibility.
+*/
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use Result;
+
+/// A seeded pseudo-random number generator (xorshift64*).
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a generator from a seed. Equal seeds produce equal sequences.
+    pub fn new(seed: u64) -> Rng {
+        // Scramble the seed with splitmix64, so that small seeds like 1 and 2
+        // don't start out looking alike, and so the state is never 0.
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+        Rng { state: if z == 0 { 1 } else { z } }
+    }
+
+    /// Returns the next number in the sequence.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// Returns a number in `0..n`. `n` must not be 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Returns true with probability `p`.
+    pub fn chance(&mut self, p: f64) -> bool {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64 <= p
+    }
+
+    /// Returns a random element of `items`, which must not be empty.
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+
+    /// Returns a number in `0..n`, where smaller numbers are much more
+    /// likely: `k` is picked with probability roughly proportional to
+    /// `1/(k+1)`, as in Zipf's law. `n` must not be 0.
+    pub fn zipf(&mut self, n: u64) -> u64 {
+        let u = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        let k = (n as f64).powf(u) as u64;
+        k.saturating_sub(1).min(n - 1)
+    }
+}
+
+const ONSETS: &'static [&'static str] = &[
+    "b", "bl", "br", "c", "ch", "cl", "cr", "d", "dr", "f", "fl", "fr", "g",
+    "gl", "gr", "h", "j", "k", "l", "m", "n", "p", "pl", "pr", "qu", "r", "s",
+    "sc", "sh", "sl", "sp", "st", "str", "t", "th", "tr", "v", "w", "wh", "y",
+    "z", "",
+];
+const NUCLEI: &'static [&'static str] = &[
+    "a", "e", "i", "o", "u", "a", "e", "i", "o", "ai", "ea", "ee", "ie", "oo",
+    "ou", "y",
+];
+const CODAS: &'static [&'static str] = &[
+    "", "", "", "", "b", "ck", "d", "ft", "g", "l", "ll", "m", "n", "nd", "ng",
+    "nt", "p", "r", "rd", "rk", "rn", "s", "ss", "st", "t", "x",
+];
+const SUFFIXES: &'static [&'static str] = &[
+    "s", "ed", "er", "ers", "es", "ing", "ings", "ly", "ness", "able", "ful",
+    "less", "ment", "ism", "ist", "ity",
+];
+
+/// Generate a random English-looking word, made of one to four syllables
+/// and sometimes a suffix.
+pub fn word(rng: &mut Rng) -> String {
+    let syllables = match rng.below(10) {
+        0 | 1 => 1,
+        2 | 3 | 4 | 5 => 2,
+        6 | 7 | 8 => 3,
+        _ => 4,
+    };
+    let mut word = String::new();
+    for _ in 0..syllables {
+        word.push_str(*rng.choose(ONSETS));
+        word.push_str(*rng.choose(NUCLEI));
+        word.push_str(*rng.choose(CODAS));
+    }
+    if rng.chance(0.3) {
+        word.push_str(*rng.choose(SUFFIXES));
+    }
+    word
+}
+
+/// Generate `n` distinct words, in sorted order, like a dictionary word
+/// list.
+pub fn words(rng: &mut Rng, n: usize) -> Result<Vec<String>> {
+    distinct(rng, n, word)
+}
+
+/// Call `gen` until it has produced `n` distinct strings, and return them in
+/// sorted order.
+///
+/// If `gen` produces nothing new `MAX_REPEATS` times in a row, it probably
+/// can't produce `n` distinct strings at all, so this gives up and returns
+/// an error.
+pub fn distinct<F>(rng: &mut Rng, n: usize, mut gen: F) -> Result<Vec<String>>
+where F: FnMut(&mut Rng) -> String {
+    collect_distinct(n, || gen(rng))
+}
+
+/// The most keys in a row that `distinct` lets a generator repeat.
+const MAX_REPEATS: usize = 100_000;
+
+fn collect_distinct<F>(n: usize, mut gen: F) -> Result<Vec<String>>
+where F: FnMut() -> String {
+    let mut set = BTreeSet::new();
+    let mut repeats = 0;
+    while set.len() < n {
+        if set.insert(gen()) {
+            repeats = 0;
+        } else if repeats == MAX_REPEATS {
+            return Err(From::from(format!(
+                "gave up after {} distinct keys: the last {} were all \
+                 repeats", set.len(), MAX_REPEATS)));
+        } else {
+            repeats += 1;
+        }
+    }
+    Ok(set.into_iter().collect())
+}
+
+/// The kinds of corpus that can be generated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    Words,
+    Titles,
+    Urls,
+    Dois,
+}
+
+impl FromStr for Kind {
+    type Err = Box<::std::error::Error + Send + Sync>;
+
+    fn from_str(s: &str) -> Result<Kind> {
+        match s {
+            "words" => Ok(Kind::Words),
+            "titles" => Ok(Kind::Titles),
+            "urls" => Ok(Kind::Urls),
+            "dois" => Ok(Kind::Dois),
+            _ => Err(From::from(format!(
+                "unknown corpus {:?} (expected one of words, titles, urls, \
+                 dois)", s))),
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Kind::Words => "words",
+            Kind::Titles => "titles",
+            Kind::Urls => "urls",
+            Kind::Dois => "dois",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The number of distinct words that titles, hosts and paths are made of.
+const VOCABULARY: usize = 50000;
+
+const SMALL_WORDS: &'static [&'static str] = &[
+    "of", "the", "and", "in", "on", "for", "to", "a", "at", "with", "from",
+];
+const DISAMBIGUATIONS: &'static [&'static str] = &[
+    "film", "album", "song", "band", "novel", "river", "village", "footballer",
+    "politician", "TV series", "disambiguation",
+];
+const TLDS: &'static [&'static str] = &[
+    "com", "com", "com", "com", "com", "org", "org", "net", "net", "de", "uk",
+    "co.uk", "ru", "jp", "fr", "info", "edu", "gov", "io", "nl",
+];
+const EXTENSIONS: &'static [&'static str] = &[
+    "", "", "", "/", ".html", ".html", ".htm", ".php", ".aspx", ".jpg", ".pdf",
+];
+
+/// An endless stream of keys of one kind.
+///
+/// Keys may repeat, and they come out in no particular order, so use
+/// `distinct` to get a sorted set of them, or sort them externally (e.g.,
+/// with `fst-index build`) when there are too many to fit in memory.
+pub struct Corpus {
+    kind: Kind,
+    rng: Rng,
+    vocabulary: Vec<String>,
+}
+
+impl Corpus {
+    /// Create a generator of the given kind. Equal seeds produce equal
+    /// corpora.
+    pub fn new(kind: Kind, seed: u64) -> Corpus {
+        let mut rng = Rng::new(seed);
+        let vocabulary = match kind {
+            Kind::Words => vec![],
+            _ => {
+                // Shuffle the vocabulary so that the most common words
+                // aren't all near the start of the alphabet.
+                // The word generator can produce millions of distinct words,
+                // so this can't fail.
+                let mut vocab = words(&mut rng, VOCABULARY).unwrap();
+                for i in (1..vocab.len()).rev() {
+                    let j = rng.below(i as u64 + 1) as usize;
+                    vocab.swap(i, j);
+                }
+                vocab
+            }
+        };
+        Corpus { kind: kind, rng: rng, vocabulary: vocabulary }
+    }
+
+    /// Generate the next key.
+    pub fn next_key(&mut self) -> String {
+        match self.kind {
+            Kind::Words => word(&mut self.rng),
+            Kind::Titles => self.title(),
+            Kind::Urls => self.url(),
+            Kind::Dois => self.doi(),
+        }
+    }
+
+    /// Generate keys until there are `n` distinct ones, and return them in
+    /// sorted order, ready to be inserted into a builder. As with the
+    /// `distinct` function, this fails if the generator stops producing new
+    /// keys.
+    pub fn distinct(&mut self, n: usize) -> Result<Vec<String>> {
+        collect_distinct(n, || self.next_key())
+    }
+
+    /// Pick a word from the vocabulary, favoring common ones.
+    fn common_word(&mut self) -> &str {
+        let i = self.rng.zipf(self.vocabulary.len() as u64) as usize;
+        &self.vocabulary[i]
+    }
+
+    fn title(&mut self) -> String {
+        let len = 1 + self.rng.zipf(6);
+        let mut title = String::new();
+        for i in 0..len {
+            if i > 0 {
+                title.push(' ');
+                if i + 1 < len && self.rng.chance(0.2) {
+                    title.push_str(*self.rng.choose(SMALL_WORDS));
+                    title.push(' ');
+                }
+            }
+            let word = self.common_word().to_string();
+            title.push_str(&capitalize(&word));
+        }
+        if self.rng.chance(0.05) {
+            let what = *self.rng.choose(DISAMBIGUATIONS);
+            if self.rng.chance(0.3) {
+                let year = 1900 + self.rng.below(120);
+                title.push_str(&format!(" ({} {})", year, what));
+            } else {
+                title.push_str(&format!(" ({})", what));
+            }
+        }
+        title
+    }
+
+    fn url(&mut self) -> String {
+        let scheme = if self.rng.chance(0.3) { "https" } else { "http" };
+        let mut url = format!("{}://", scheme);
+        if self.rng.chance(0.6) {
+            url.push_str("www.");
+        } else if self.rng.chance(0.2) {
+            let sub = self.common_word().to_string();
+            url.push_str(&sub);
+            url.push('.');
+        }
+        // Hosts are drawn from the most popular part of the vocabulary, so
+        // a few of them have most of the URLs.
+        let host = self.rng.zipf(5000) as usize;
+        url.push_str(&self.vocabulary[host].clone());
+        url.push('.');
+        url.push_str(*self.rng.choose(TLDS));
+        let depth = self.rng.zipf(6);
+        for _ in 0..depth {
+            url.push('/');
+            let segment = self.common_word().to_string();
+            url.push_str(&segment);
+            if self.rng.chance(0.1) {
+                url.push_str(&format!("-{}", self.rng.below(1000)));
+            }
+        }
+        if depth > 0 {
+            url.push_str(*self.rng.choose(EXTENSIONS));
+        }
+        if self.rng.chance(0.1) {
+            if depth == 0 {
+                url.push('/');
+            }
+            url.push_str(&format!("?id={}", self.rng.below(100000)));
+        }
+        url
+    }
+
+    fn doi(&mut self) -> String {
+        // Registrants are numbered from 1000, and the big ones (like
+        // Elsevier's 1016) publish most of the DOIs.
+        let registrant = 1000 + self.rng.zipf(500) * 7 % 9000;
+        let year = 1990 + self.rng.below(30);
+        let suffix = match registrant % 4 {
+            0 => {
+                let journal = self.common_word().to_string();
+                format!("j.{}.{}.{:02}.{:03}",
+                        &journal[..journal.len().min(5)], year,
+                        1 + self.rng.below(12), self.rng.below(1000))
+            }
+            1 => format!("s{:05}-{:03}-{:04}-{}",
+                         self.rng.zipf(50000), year % 1000,
+                         self.rng.below(10000), self.rng.below(10)),
+            2 => format!("978-{}-{:03}-{:05}-{}_{}",
+                         self.rng.below(10), self.rng.below(1000),
+                         self.rng.below(100000), self.rng.below(10),
+                         1 + self.rng.below(30)),
+            _ => {
+                let journal = self.common_word().to_string();
+                format!("{}.{}.{:04}",
+                        &journal[..journal.len().min(4)], year,
+                        self.rng.below(10000))
+            }
+        };
+        format!("http://dx.doi.org/10.{}/{}", registrant, suffix)
+    }
+}
+
+impl Iterator for Corpus {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        Some(self.next_key())
+    }
+}
+
+/// Uppercase the first letter of `word`.
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        None => String::new(),
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+    }
+}
@@ -0,0 +1,300 @@
+/*!
+A Damerau-Levenshtein automaton, where swapping two adjacent characters counts
+as a single edit.
+
+`fst_levenshtein::Levenshtein` treats `ofo` as two edits away from `foo`,
+since a transposition is a deletion plus an insertion. Most real typos are
+exactly that kind of swap, so `DamerauLevenshtein` instead uses the *optimal
+string alignment* distance: the usual insertions, deletions and substitutions
+plus transpositions of adjacent characters, where no substring is edited more
+than once.
+
+Like `Levenshtein`, distances are measured in Unicode codepoints, and the
+automaton is compiled to a DFA up front. If the DFA would need more than
+10,000 states, construction fails with `Error::TooManyStates`.
+*/
+
+use std::cmp;
+use std::error;
+use std::fmt;
+
+use fst::Automaton;
+
+use dfa::{self, Dfa, STATE_LIMIT};
+
+/// An error that occurred while building a Damerau-Levenshtein automaton.
+#[derive(Debug)]
+pub enum Error {
+    /// The DFA would have more states than the given limit.
+    TooManyStates(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::TooManyStates(size_limit) => write!(
+                f,
+                "Damerau-Levenshtein automaton exceeds size limit of {} states",
+                size_limit),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::TooManyStates(_) => "too many states",
+        }
+    }
+}
+
+/// A Damerau-Levenshtein automaton.
+///
+/// It matches every key within the given optimal string alignment distance
+/// of the query, and can be used anywhere a `Levenshtein` automaton can. For
+/// example, searching a set containing `fa`, `fo`, `foo`, `ofo` and `oof`
+/// with `DamerauLevenshtein::new("foo", 1)` finds `fo`, `foo` and `ofo`.
+pub struct DamerauLevenshtein {
+    query: String,
+    distance: u32,
+    /// The distinct characters in the query, sorted. Every character in the
+    /// query is identified by its index in this list, and every other
+    /// character by `chars.len()`.
+    chars: Vec<char>,
+    dfa: Dfa,
+}
+
+impl DamerauLevenshtein {
+    /// Create a new automaton that matches every key within `distance` edits
+    /// of `query`.
+    pub fn new(
+        query: &str,
+        distance: u32,
+    ) -> Result<DamerauLevenshtein, Error> {
+        let mut chars: Vec<char> = query.chars().collect();
+        chars.sort();
+        chars.dedup();
+        let classes: Vec<usize> = query.chars()
+            .map(|c| chars.binary_search(&c).unwrap())
+            .collect();
+        let dfa = Dfa::new(
+            Row::start(classes.len(), distance),
+            chars.len() + 1,
+            |row, class| {
+                let next = row.next(&classes, class, distance);
+                if next.is_dead(distance) { None } else { Some(next) }
+            },
+            |row| row.is_match(distance),
+        ).map_err(|_| Error::TooManyStates(STATE_LIMIT))?;
+        Ok(DamerauLevenshtein {
+            query: query.to_string(),
+            distance: distance,
+            chars: chars,
+            dfa: dfa,
+        })
+    }
+
+    fn class(&self, c: char) -> usize {
+        self.chars.binary_search(&c).unwrap_or(self.chars.len())
+    }
+}
+
+impl fmt::Debug for DamerauLevenshtein {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DamerauLevenshtein(query: {:?}, distance: {:?})",
+               self.query, self.distance)
+    }
+}
+
+/// The state of a `DamerauLevenshtein` automaton: the DFA state reached after
+/// the last complete codepoint, along with any partially decoded codepoint.
+/// `None` means the key can't match.
+pub type State = dfa::State;
+
+impl Automaton for DamerauLevenshtein {
+    type State = State;
+
+    fn start(&self) -> State {
+        self.dfa.start()
+    }
+
+    fn is_match(&self, state: &State) -> bool {
+        self.dfa.is_match(state)
+    }
+
+    fn can_match(&self, state: &State) -> bool {
+        self.dfa.can_match(state)
+    }
+
+    fn accept(&self, state: &State, byte: u8) -> State {
+        self.dfa.accept(state, byte, |c| self.class(c))
+    }
+}
+
+/// The last two rows of the dynamic programming table along with the class
+/// of the last character consumed, which is everything needed to compute the
+/// next row.
+///
+/// `cur[j]` is the distance between the key consumed so far and the first
+/// `j` characters of the query. All distances are capped at `k + 1`, which
+/// keeps the number of distinct rows finite.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct Row {
+    prev: Vec<u32>,
+    cur: Vec<u32>,
+    last: Option<usize>,
+}
+
+impl Row {
+    fn start(len: usize, k: u32) -> Row {
+        Row {
+            prev: vec![k + 1; len + 1],
+            cur: (0..len as u32 + 1).map(|j| cmp::min(j, k + 1)).collect(),
+            last: None,
+        }
+    }
+
+    fn next(&self, query: &[usize], class: usize, k: u32) -> Row {
+        let mut next = Vec::with_capacity(self.cur.len());
+        next.push(cmp::min(self.cur[0] + 1, k + 1));
+        for j in 1..self.cur.len() {
+            let cost = if query[j - 1] == class { 0 } else { 1 };
+            let mut d = cmp::min(
+                cmp::min(self.cur[j] + 1, next[j - 1] + 1),
+                self.cur[j - 1] + cost,
+            );
+            if j >= 2
+                && query[j - 2] == class
+                && self.last == Some(query[j - 1])
+            {
+                d = cmp::min(d, self.prev[j - 2] + 1);
+            }
+            next.push(cmp::min(d, k + 1));
+        }
+        Row { prev: self.cur.clone(), cur: next, last: Some(class) }
+    }
+
+    fn is_match(&self, k: u32) -> bool {
+        self.cur[self.cur.len() - 1] <= k
+    }
+
+    /// Returns true if no extension of the key can ever be within distance
+    /// `k`. Every future row is computed from this one, plus one, or from the
+    /// previous row plus one (a transposition).
+    fn is_dead(&self, k: u32) -> bool {
+        self.cur.iter().all(|&d| d > k)
+            && self.prev.iter().all(|&d| d + 1 > k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp;
+
+    use matches;
+    use super::{DamerauLevenshtein, Error};
+
+    /// A xorshift generator, so the tests are random but repeatable.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn string(&mut self, max_len: u64) -> String {
+            let len = self.next_u64() % (max_len + 1);
+            let alphabet = ['a', 'b', 'c', 'é'];
+            (0..len)
+                .map(|_| alphabet[self.next_u64() as usize % alphabet.len()])
+                .collect()
+        }
+    }
+
+    /// The optimal string alignment distance, straight from the
+    /// definition.
+    fn osa(a: &str, b: &str) -> u32 {
+        let (a, b): (Vec<char>, Vec<char>) =
+            (a.chars().collect(), b.chars().collect());
+        let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in 0..a.len() + 1 {
+            for j in 0..b.len() + 1 {
+                d[i][j] = if i == 0 || j == 0 {
+                    (i + j) as u32
+                } else {
+                    let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+                    let mut min = cmp::min(
+                        cmp::min(d[i - 1][j] + 1, d[i][j - 1] + 1),
+                        d[i - 1][j - 1] + cost,
+                    );
+                    if i > 1 && j > 1
+                        && a[i - 1] == b[j - 2]
+                        && a[i - 2] == b[j - 1]
+                    {
+                        min = cmp::min(min, d[i - 2][j - 2] + 1);
+                    }
+                    min
+                };
+            }
+        }
+        d[a.len()][b.len()]
+    }
+
+    fn check(query: &str, key: &str, distance: u32) {
+        let aut = DamerauLevenshtein::new(query, distance).unwrap();
+        assert_eq!(matches(&aut, key.as_bytes()),
+                   osa(query, key) <= distance,
+                   "{:?} vs {:?} within {}", query, key, distance);
+    }
+
+    #[test]
+    fn random() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..300 {
+            let query = rng.string(6);
+            for distance in 0..3 {
+                let aut = DamerauLevenshtein::new(&query, distance).unwrap();
+                for _ in 0..20 {
+                    let key = rng.string(8);
+                    assert_eq!(matches(&aut, key.as_bytes()),
+                               osa(&query, &key) <= distance,
+                               "{:?} vs {:?} within {}",
+                               query, key, distance);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transpositions() {
+        // At the start, in the middle and at the end of the string, and
+        // with multibyte characters.
+        let swaps = [
+            ("foo", "ofo"), ("abcd", "bacd"), ("abcd", "acbd"),
+            ("abcd", "abdc"), ("aébc", "éabc"), ("abcé", "abéc"),
+            ("ab", "ba"),
+        ];
+        for &(query, key) in &swaps {
+            check(query, key, 0);
+            check(query, key, 1);
+        }
+        // Optimal string alignment doesn't edit a substring twice, so this
+        // is three edits rather than two.
+        check("ca", "abc", 2);
+        check("ca", "abc", 3);
+        check("", "ab", 1);
+        check("", "ab", 2);
+    }
+
+    #[test]
+    fn too_many_states() {
+        let query: String = (0..40u8).map(|i| (b'A' + i) as char).collect();
+        match DamerauLevenshtein::new(&query, 6) {
+            Err(Error::TooManyStates(_)) => {}
+            Ok(_) => panic!("expected too many states"),
+        }
+    }
+}
@@ -0,0 +1,138 @@
+/*!
+Dense DFAs over character classes, for automata that are compiled up front.
+
+Both `DamerauLevenshtein` and `Glob` compile to the same kind of DFA: the
+codepoints are split into a small number of classes that the pattern can't
+tell apart, and every state has one transition per class. They differ only
+in what a state of the DFA stands for (a row of the edit distance table, or a
+set of positions in the glob), so `Dfa::new` explores those from a start
+state, and the automata supply how to step from one to the next.
+
+The FST feeds an automaton bytes, not codepoints, so the automaton's state is
+a DFA state plus a `Decoder` holding any partially decoded codepoint.
+*/
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+use utf8::{Decoded, Decoder};
+
+/// The most states a DFA may have.
+pub const STATE_LIMIT: usize = 10_000;
+
+/// The state that can never lead to a match.
+const DEAD: usize = 0;
+
+/// The state every search starts in.
+const START: usize = 1;
+
+/// The state of an automaton backed by a `Dfa`: the DFA state reached after
+/// the last complete codepoint, along with any partially decoded codepoint.
+/// `None` means the key can't match.
+pub type State = Option<(usize, Decoder)>;
+
+/// The error returned when a DFA would have more than `STATE_LIMIT` states.
+#[derive(Debug)]
+pub struct TooManyStates;
+
+/// A DFA with a transition for every state and character class.
+pub struct Dfa {
+    classes: usize,
+    trans: Vec<usize>,
+    is_match: Vec<bool>,
+}
+
+impl Dfa {
+    /// Build the DFA whose states are every value reachable from `start`.
+    ///
+    /// `next` returns the value reached from a value on a character in the
+    /// given class, or `None` if no key can match from there, and
+    /// `is_match` returns whether a value is a match.
+    pub fn new<S, N, M>(
+        start: S,
+        classes: usize,
+        mut next: N,
+        is_match: M,
+    ) -> Result<Dfa, TooManyStates>
+    where S: Clone + Eq + Hash,
+          N: FnMut(&S, usize) -> Option<S>,
+          M: Fn(&S) -> bool {
+        let mut dfa = Dfa { classes: classes, trans: vec![], is_match: vec![] };
+        let mut ids: HashMap<S, usize> = HashMap::new();
+        let mut queue = VecDeque::new();
+
+        // The dead state loops back to itself on every input.
+        dfa.add_state(false);
+        ids.insert(start.clone(), dfa.add_state(is_match(&start)));
+        queue.push_back(start);
+
+        while let Some(value) = queue.pop_front() {
+            let id = ids[&value];
+            for class in 0..classes {
+                let next_id = match next(&value, class) {
+                    None => DEAD,
+                    Some(next) => {
+                        if let Some(&next_id) = ids.get(&next) {
+                            next_id
+                        } else {
+                            if dfa.is_match.len() >= STATE_LIMIT {
+                                return Err(TooManyStates);
+                            }
+                            let next_id = dfa.add_state(is_match(&next));
+                            ids.insert(next.clone(), next_id);
+                            queue.push_back(next);
+                            next_id
+                        }
+                    }
+                };
+                dfa.trans[id * classes + class] = next_id;
+            }
+        }
+        Ok(dfa)
+    }
+
+    /// The state to start a search in.
+    pub fn start(&self) -> State {
+        Some((START, Decoder::default()))
+    }
+
+    /// Returns true if the key consumed so far is a match.
+    pub fn is_match(&self, state: &State) -> bool {
+        match *state {
+            None => false,
+            Some((s, ref dec)) => dec.is_boundary() && self.is_match[s],
+        }
+    }
+
+    /// Returns true if some extension of the key consumed so far can match.
+    pub fn can_match(&self, state: &State) -> bool {
+        state.map_or(false, |(s, _)| s != DEAD)
+    }
+
+    /// Feed the next byte of a key, where `class` returns the class of a
+    /// codepoint.
+    pub fn accept<F>(&self, state: &State, byte: u8, class: F) -> State
+    where F: Fn(char) -> usize {
+        let (s, dec) = match *state {
+            None => return None,
+            Some(state) => state,
+        };
+        match dec.push(byte) {
+            Decoded::Invalid => None,
+            Decoded::Incomplete(dec) => Some((s, dec)),
+            Decoded::Char(c) => {
+                let next = self.trans[s * self.classes + class(c)];
+                Some((next, Decoder::default()))
+            }
+        }
+    }
+
+    fn add_state(&mut self, is_match: bool) -> usize {
+        let id = self.is_match.len();
+        self.is_match.push(is_match);
+        for _ in 0..self.classes {
+            self.trans.push(DEAD);
+        }
+        id
+    }
+}
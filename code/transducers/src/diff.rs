@@ -0,0 +1,81 @@
+/*!
+The differences between two maps.
+
+`Diff` runs a union of two map streams with `fst::map::OpBuilder`, which
+yields every key once along with the values from each side that contains it.
+Keys on one side only were removed or added, and keys on both sides whose
+values differ were changed. Keys that are the same on both sides are skipped.
+
+Since the union is a merge of two ordered streams, only the current key of
+each side is in memory, so two indexes can be compared no matter how large
+they are. Sets can be compared by opening them as maps, in which case every
+value is `0` and no key is ever reported as changed.
+*/
+
+use fst::{IntoStreamer, Streamer};
+use fst::map;
+
+/// How a key differs between the left and right sides of a `Diff`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Change {
+    /// The key is only on the left, with the given value.
+    Removed(u64),
+    /// The key is only on the right, with the given value.
+    Added(u64),
+    /// The key is on both sides, with the left and right values.
+    Changed(u64, u64),
+}
+
+/// A stream of the keys that differ between two maps, in lexicographic
+/// order.
+///
+/// The `'m` lifetime parameter refers to the lifetime of the underlying maps.
+pub struct Diff<'m> {
+    union: map::Union<'m>,
+    key: Vec<u8>,
+}
+
+impl<'m> Diff<'m> {
+    /// Compare `left` with `right`.
+    ///
+    /// Either side can be any map stream, so this works equally well on
+    /// whole maps, ranges or searches.
+    pub fn new<L, R, S, T>(left: L, right: R) -> Diff<'m>
+    where L: for<'a> IntoStreamer<'a, Into=S, Item=(&'a [u8], u64)>,
+          S: 'm + for<'a> Streamer<'a, Item=(&'a [u8], u64)>,
+          R: for<'a> IntoStreamer<'a, Into=T, Item=(&'a [u8], u64)>,
+          T: 'm + for<'a> Streamer<'a, Item=(&'a [u8], u64)> {
+        let op = map::OpBuilder::new().add(left).add(right);
+        Diff { union: op.union(), key: vec![] }
+    }
+}
+
+impl<'a, 'm> Streamer<'a> for Diff<'m> {
+    type Item = (&'a [u8], Change);
+
+    fn next(&'a mut self) -> Option<(&'a [u8], Change)> {
+        let Diff { ref mut union, ref mut key } = *self;
+        while let Some((k, values)) = union.next() {
+            let (mut left, mut right) = (None, None);
+            for iv in values {
+                if iv.index == 0 {
+                    left = Some(iv.value);
+                } else {
+                    right = Some(iv.value);
+                }
+            }
+            let change = match (left, right) {
+                (Some(l), None) => Change::Removed(l),
+                (None, Some(r)) => Change::Added(r),
+                (Some(l), Some(r)) if l != r => Change::Changed(l, r),
+                _ => continue,
+            };
+            // The key has to be copied out, since it borrows from the union
+            // that the next iteration would advance.
+            key.clear();
+            key.extend_from_slice(k);
+            return Some((key, change));
+        }
+        None
+    }
+}
@@ -0,0 +1,83 @@
+/*!
+Rendering FSTs in the Graphviz "dot" format.
+
+This walks the raw FST starting at its root and emits every state and
+transition, which is how the set and map images in the blog post were made.
+The output looks like the hand written files in `blogdata/transducers/dot`:
+final states have a double circle and outputs are shown after a `/` on
+transitions (and on final states with a non-zero final output).
+*/
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use fst::raw::{CompiledAddr, Fst};
+
+use states;
+
+/// Options for controlling what `write_dot` emits.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// When true, label each state with a number. States are numbered in the
+    /// order they are first visited by a depth first traversal that follows
+    /// transitions in lexicographic order, so the root is always `0`.
+    pub state_names: bool,
+}
+
+/// Write the given FST in the dot format to `wtr`.
+pub fn write_dot<W: Write>(
+    fst: &Fst,
+    opts: &Options,
+    mut wtr: W,
+) -> io::Result<()> {
+    writeln!(wtr, "digraph automaton {{")?;
+    writeln!(wtr, "  labelloc=\"l\";")?;
+    writeln!(wtr, "  labeljust=\"l\";")?;
+    writeln!(wtr, "  rankdir=\"LR\";")?;
+    writeln!(wtr, "  node [shape=\"circle\"];")?;
+
+    let order = states(fst);
+    let ids: HashMap<CompiledAddr, usize> =
+        order.iter().enumerate().map(|(id, &addr)| (addr, id)).collect();
+    for &addr in &order {
+        let node = fst.node(addr);
+        let id = ids[&addr];
+
+        writeln!(wtr)?;
+        let mut label = String::new();
+        if opts.state_names {
+            label = id.to_string();
+        }
+        if node.is_final() && !node.final_output().is_zero() {
+            label = format!("{}/{}", label, node.final_output().value());
+        }
+        let peripheries = if node.is_final() { 2 } else { 1 };
+        writeln!(
+            wtr,
+            "  {} [label=\"{}\",peripheries={}];",
+            id, label, peripheries,
+        )?;
+        for t in node.transitions() {
+            let mut label = escape(t.inp);
+            if !t.out.is_zero() {
+                label = format!("{}/{}", label, t.out.value());
+            }
+            let to = ids[&t.addr];
+            writeln!(wtr, "  {} -> {} [label=\"{}\"];", id, to, label)?;
+        }
+    }
+    writeln!(wtr, "}}")?;
+    Ok(())
+}
+
+/// Escape a transition's input byte so that it's readable and safe to put
+/// inside a quoted dot label.
+fn escape(b: u8) -> String {
+    match b {
+        b'"' => "\\\"".into(),
+        b'\\' => "\\\\".into(),
+        b' ' => "\\\\x20".into(),
+        0x21..=0x7E => (b as char).to_string(),
+        _ => format!("\\\\x{:02X}", b),
+    }
+}
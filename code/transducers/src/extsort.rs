@@ -0,0 +1,421 @@
+/*!
+Sorting more keys than fit in memory.
+
+FSTs must be built by inserting keys in lexicographic order, but real data
+rarely comes that way. `ExternalSorter` buffers keys (and their values) in
+memory until a configurable limit is reached, sorts them and spills them to a
+temporary file as a sorted "run." Once all keys have been pushed, the runs are
+merged back together with a k-way merge that also removes duplicate keys. The
+result is a sorted stream that can be fed directly to `SetBuilder` or
+`MapBuilder`.
+
+Memory use is bounded by the buffer limit plus one buffered reader per run.
+*/
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::vec;
+
+/// The default amount of memory used for buffering keys before spilling a run.
+const DEFAULT_MAX_MEMORY: usize = 128 * (1 << 20);
+
+/// The approximate amount of memory used by a buffered entry, not including
+/// the bytes of the key itself.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Sorts and deduplicates a sequence of key-value pairs using bounded memory.
+///
+/// When the same key is pushed more than once, the value pushed first wins.
+/// If you only care about keys (i.e., you're building a set), then just use
+/// `0` for every value.
+#[derive(Debug)]
+pub struct ExternalSorter {
+    max_memory: usize,
+    tmp_dir: PathBuf,
+    buf: Vec<(Vec<u8>, u64)>,
+    buf_bytes: usize,
+    runs: Vec<TempFile>,
+}
+
+impl ExternalSorter {
+    /// Create a new sorter that buffers up to 128 MB of keys in memory and
+    /// writes its runs to the system's temporary directory.
+    pub fn new() -> ExternalSorter {
+        ExternalSorter {
+            max_memory: DEFAULT_MAX_MEMORY,
+            tmp_dir: env::temp_dir(),
+            buf: vec![],
+            buf_bytes: 0,
+            runs: vec![],
+        }
+    }
+
+    /// Set the approximate number of bytes to buffer before spilling a sorted
+    /// run to disk.
+    pub fn max_memory(&mut self, bytes: usize) -> &mut ExternalSorter {
+        self.max_memory = bytes;
+        self
+    }
+
+    /// Set the directory that sorted runs are written to.
+    pub fn tmp_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut ExternalSorter {
+        self.tmp_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Add a key to be sorted with the given value.
+    pub fn push<K: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        value: u64,
+    ) -> io::Result<()> {
+        let key = key.as_ref().to_vec();
+        self.buf_bytes += key.len() + ENTRY_OVERHEAD;
+        self.buf.push((key, value));
+        if self.buf_bytes >= self.max_memory {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of sorted runs that have been written to disk so
+    /// far.
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Finish pushing keys and return an iterator over all of them in sorted
+    /// order with duplicates removed.
+    ///
+    /// Temporary files are removed when the iterator is dropped.
+    pub fn finish(mut self) -> io::Result<Merge> {
+        let mut sources = vec![];
+        for run in &self.runs {
+            sources.push(Source::File(BufReader::new(File::open(&run.path)?)));
+        }
+        let mut buf = mem::replace(&mut self.buf, vec![]);
+        sort_run(&mut buf);
+        sources.push(Source::Memory(buf.into_iter()));
+
+        let mut merge = Merge {
+            sources: sources,
+            heap: BinaryHeap::new(),
+            last: None,
+            _runs: mem::replace(&mut self.runs, vec![]),
+        };
+        for i in 0..merge.sources.len() {
+            merge.refill(i)?;
+        }
+        Ok(merge)
+    }
+
+    /// Sort the buffer and write it to a new temporary file.
+    fn spill(&mut self) -> io::Result<()> {
+        let mut buf = mem::replace(&mut self.buf, vec![]);
+        self.buf_bytes = 0;
+        sort_run(&mut buf);
+
+        let (run, file) = TempFile::new(&self.tmp_dir)?;
+        let mut wtr = BufWriter::new(file);
+        for (key, value) in buf {
+            write_u64(&mut wtr, key.len() as u64)?;
+            wtr.write_all(&key)?;
+            write_u64(&mut wtr, value)?;
+        }
+        wtr.flush()?;
+        self.runs.push(run);
+        Ok(())
+    }
+}
+
+impl Default for ExternalSorter {
+    fn default() -> ExternalSorter {
+        ExternalSorter::new()
+    }
+}
+
+/// A sorted, deduplicated iterator over all keys pushed to an
+/// `ExternalSorter`.
+#[derive(Debug)]
+pub struct Merge {
+    sources: Vec<Source>,
+    heap: BinaryHeap<Head>,
+    last: Option<Vec<u8>>,
+    _runs: Vec<TempFile>,
+}
+
+impl Merge {
+    /// Read the next entry from the source at index `i` and put it on the
+    /// heap.
+    fn refill(&mut self, i: usize) -> io::Result<()> {
+        if let Some((key, value)) = self.sources[i].next()? {
+            self.heap.push(Head { key: key, value: value, source: i });
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Merge {
+    type Item = io::Result<(Vec<u8>, u64)>;
+
+    fn next(&mut self) -> Option<io::Result<(Vec<u8>, u64)>> {
+        while let Some(head) = self.heap.pop() {
+            if let Err(err) = self.refill(head.source) {
+                return Some(Err(err));
+            }
+            // Sources are ordered by the time their keys were pushed, and
+            // ties on the heap are broken by source. So the first time we see
+            // a key, its value is the one that was pushed first.
+            if self.last.as_ref().map_or(false, |last| *last == head.key) {
+                continue;
+            }
+            self.last = Some(head.key.clone());
+            return Some(Ok((head.key, head.value)));
+        }
+        None
+    }
+}
+
+/// Somewhere to read sorted entries from.
+#[derive(Debug)]
+enum Source {
+    /// The last buffer, which never needs to be written to disk.
+    Memory(vec::IntoIter<(Vec<u8>, u64)>),
+    /// A run that was spilled to disk.
+    File(BufReader<File>),
+}
+
+impl Source {
+    fn next(&mut self) -> io::Result<Option<(Vec<u8>, u64)>> {
+        match *self {
+            Source::Memory(ref mut it) => Ok(it.next()),
+            Source::File(ref mut rdr) => {
+                let len = match read_u64(rdr) {
+                    Ok(len) => len as usize,
+                    Err(ref err)
+                        if err.kind() == io::ErrorKind::UnexpectedEof =>
+                    {
+                        return Ok(None);
+                    }
+                    Err(err) => return Err(err),
+                };
+                let mut key = vec![0; len];
+                rdr.read_exact(&mut key)?;
+                let value = read_u64(rdr)?;
+                Ok(Some((key, value)))
+            }
+        }
+    }
+}
+
+/// The smallest unread entry of a single source.
+///
+/// The ordering is reversed so that `BinaryHeap`, which is a max-heap, gives
+/// us the smallest key first. Equal keys are ordered by source so that the
+/// merge is stable.
+#[derive(Debug, Eq, PartialEq)]
+struct Head {
+    key: Vec<u8>,
+    value: u64,
+    source: usize,
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Head) -> Ordering {
+        (&other.key, other.source).cmp(&(&self.key, self.source))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Head) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A file in a temporary directory that is removed when dropped.
+#[derive(Debug)]
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Create a new file in `dir`, opened for writing.
+    ///
+    /// The directory may be shared with other users, so the file is only
+    /// ever created, never opened: a file or symlink that's already there
+    /// under the same name (say, left behind by a process that had the same
+    /// PID) is skipped instead of being truncated or followed.
+    fn new(dir: &Path) -> io::Result<(TempFile, File)> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        loop {
+            let n = COUNTER.fetch_add(1, AtomicOrdering::SeqCst);
+            let name = format!("transducers-sort-{}-{}", process::id(), n);
+            let path = dir.join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((TempFile { path: path }, file)),
+                Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Sort a run by key. The sort is stable, so among equal keys the entry that
+/// was pushed first stays first.
+fn sort_run(buf: &mut Vec<(Vec<u8>, u64)>) {
+    buf.sort_by(|a, b| a.0.cmp(&b.0));
+}
+
+fn write_u64<W: Write>(wtr: &mut W, n: u64) -> io::Result<()> {
+    wtr.write_all(&n.to_le_bytes())
+}
+
+fn read_u64<R: Read>(rdr: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    rdr.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::ExternalSorter;
+
+    static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+    /// An empty directory for runs, which is removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            let name = format!("transducers-extsort-{}-{}", process::id(),
+                               NEXT_DIR.fetch_add(1, Ordering::SeqCst));
+            let dir = TempDir(env::temp_dir().join(name));
+            fs::create_dir(&dir.0).unwrap();
+            dir
+        }
+
+        fn files(&self) -> usize {
+            fs::read_dir(&self.0).unwrap().count()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A sorter that writes runs to `dir` and buffers `max_memory` bytes.
+    /// With a `max_memory` of 1, it spills a run after every key.
+    fn new_sorter(dir: &TempDir, max_memory: usize) -> ExternalSorter {
+        let mut sorter = ExternalSorter::new();
+        sorter.max_memory(max_memory).tmp_dir(&dir.0);
+        sorter
+    }
+
+    fn collect(sorter: ExternalSorter) -> Vec<(Vec<u8>, u64)> {
+        sorter.finish().unwrap().collect::<io::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn merge_order() {
+        let dir = TempDir::new();
+        // Each key is a few bytes, plus 32 bytes of overhead, so a run holds
+        // about 50 keys.
+        let mut sorter = new_sorter(&dir, 50 * 36);
+        let mut want = BTreeMap::new();
+        let mut x = 0x2545_f491u32;
+        for i in 0..2000 {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            let key = format!("{}", x % 1500).into_bytes();
+            sorter.push(&key, i).unwrap();
+            want.entry(key).or_insert(i);
+        }
+        assert!(sorter.runs() > 10, "{} runs", sorter.runs());
+        assert_eq!(collect(sorter), want.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn first_value_wins() {
+        let dir = TempDir::new();
+        // Spill after every key, so that duplicates are in different runs,
+        // and the last one is in memory.
+        let mut sorter = new_sorter(&dir, 1);
+        for &(k, v) in &[("b", 1), ("a", 2), ("b", 3), ("c", 4), ("a", 5)] {
+            sorter.push(k, v).unwrap();
+        }
+        sorter.push("b", 6).unwrap();
+        assert_eq!(sorter.runs(), 6);
+        assert_eq!(collect(sorter), vec![
+            (b"a".to_vec(), 2), (b"b".to_vec(), 1), (b"c".to_vec(), 4),
+        ]);
+
+        // Without spilling, duplicates are in the same run.
+        let mut sorter = new_sorter(&dir, 1 << 20);
+        for &(k, v) in &[("b", 1), ("a", 2), ("b", 3), ("a", 4)] {
+            sorter.push(k, v).unwrap();
+        }
+        assert_eq!(sorter.runs(), 0);
+        assert_eq!(collect(sorter), vec![
+            (b"a".to_vec(), 2), (b"b".to_vec(), 1),
+        ]);
+    }
+
+    #[test]
+    fn empty() {
+        let dir = TempDir::new();
+        assert_eq!(collect(new_sorter(&dir, 1)), vec![]);
+        let mut sorter = new_sorter(&dir, 1);
+        sorter.push("", 7).unwrap();
+        assert_eq!(collect(sorter), vec![(vec![], 7)]);
+        assert_eq!(dir.files(), 0);
+    }
+
+    #[test]
+    fn cleanup() {
+        let dir = TempDir::new();
+        let mut sorter = new_sorter(&dir, 1);
+        for i in 0..5u64 {
+            sorter.push(format!("{}", i), i).unwrap();
+        }
+        assert_eq!(dir.files(), 5);
+        // Dropping a merge that's only been partly read removes the runs.
+        let mut merge = sorter.finish().unwrap();
+        merge.next().unwrap().unwrap();
+        assert_eq!(dir.files(), 5);
+        drop(merge);
+        assert_eq!(dir.files(), 0);
+
+        // So does dropping a sorter that was never finished.
+        let mut sorter = new_sorter(&dir, 1);
+        sorter.push("a", 0).unwrap();
+        sorter.push("b", 0).unwrap();
+        assert_eq!(dir.files(), 2);
+        drop(sorter);
+        assert_eq!(dir.files(), 0);
+    }
+}
@@ -0,0 +1,435 @@
+/*!
+Shell style glob patterns.
+
+`Glob` matches keys against patterns like `*.example.com`, `Bruce*` or
+`[a-c]??`, which are a lot easier to write than the equivalent regular
+expressions. The syntax is:
+
+* `*` matches any sequence of characters, including an empty one.
+* `?` matches any single character.
+* `[abc]` matches any one of the characters listed, and `[a-c]` any character
+  in the range. A class starting with `!` or `^` matches any character *not*
+  listed. A `]` right after the opening bracket (or negation) is a literal,
+  and so is a `-` at the start or end of the class.
+* `\` matches the character following it literally, e.g., `\*`.
+* Anything else matches itself.
+
+Like the other automata in this crate, patterns are matched against
+codepoints, so keys that aren't valid UTF-8 never match. A glob is compiled
+into a DFA up front. If the DFA would need more than 10,000 states,
+compilation fails with `Error::TooManyStates`.
+*/
+
+use std::error;
+use std::fmt;
+
+use fst::Automaton;
+
+use dfa::{self, Dfa, STATE_LIMIT};
+
+/// An error that occurred while compiling a glob.
+#[derive(Debug)]
+pub enum Error {
+    /// A `[` at the given byte offset has no matching `]`.
+    UnclosedClass(usize),
+    /// A range in a character class at the given byte offset has its end
+    /// before its start, e.g., `[z-a]`.
+    InvalidRange(usize, char, char),
+    /// The pattern ends with a `\` that doesn't escape anything.
+    TrailingEscape,
+    /// The DFA would have more states than the given limit.
+    TooManyStates(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnclosedClass(offset) => write!(
+                f, "unclosed character class starting at offset {}", offset),
+            Error::InvalidRange(offset, start, end) => write!(
+                f, "invalid range {}-{} at offset {}", start, end, offset),
+            Error::TrailingEscape => write!(
+                f, "pattern ends with an unescaped backslash"),
+            Error::TooManyStates(size_limit) => write!(
+                f, "glob automaton exceeds size limit of {} states",
+                size_limit),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::UnclosedClass(_) => "unclosed character class",
+            Error::InvalidRange(..) => "invalid range in character class",
+            Error::TrailingEscape => "trailing backslash",
+            Error::TooManyStates(_) => "too many states",
+        }
+    }
+}
+
+/// A glob pattern compiled to an automaton.
+pub struct Glob {
+    pattern: String,
+    /// The literal characters at the start and end of the pattern.
+    prefix: String,
+    suffix: String,
+    /// The smallest codepoint in each character class. Every codepoint
+    /// belongs to the class of the last entry that isn't greater than it.
+    classes: Vec<u32>,
+    dfa: Dfa,
+}
+
+impl Glob {
+    /// Compile the given glob pattern.
+    pub fn new(pattern: &str) -> Result<Glob, Error> {
+        let tokens = parse(pattern)?;
+        let classes = classes(&tokens);
+        // The DFA is built with the subset construction from the NFA whose
+        // states are positions in the list of tokens.
+        let dfa = Dfa::new(
+            closure(&tokens, vec![0]),
+            classes.len(),
+            |set, class| {
+                let next = step(&tokens, set, classes[class]);
+                if next.is_empty() { None } else { Some(next) }
+            },
+            |set| is_match(&tokens, set),
+        ).map_err(|_| Error::TooManyStates(STATE_LIMIT))?;
+        let mut prefix = String::new();
+        for token in &tokens {
+            match *token {
+                Token::Char(c) => prefix.push(c),
+                _ => break,
+            }
+        }
+        let mut suffix = vec![];
+        for token in tokens.iter().rev() {
+            match *token {
+                Token::Char(c) => suffix.push(c),
+                _ => break,
+            }
+        }
+        Ok(Glob {
+            pattern: pattern.to_string(),
+            prefix: prefix,
+            suffix: suffix.into_iter().rev().collect(),
+            classes: classes,
+            dfa: dfa,
+        })
+    }
+
+    /// Returns the literal text every matching key starts with, e.g., `foo`
+    /// for `foo*.txt`.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Returns the literal text every matching key ends with, e.g., `.txt`
+    /// for `foo*.txt`. If the pattern has no wildcards, this is the same as
+    /// `prefix`.
+    pub fn suffix(&self) -> &str {
+        &self.suffix
+    }
+
+    fn class(&self, c: char) -> usize {
+        match self.classes.binary_search(&(c as u32)) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        }
+    }
+}
+
+impl fmt::Debug for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Glob({:?})", self.pattern)
+    }
+}
+
+/// The state of a `Glob` automaton: the DFA state reached after the last
+/// complete codepoint, along with any partially decoded codepoint. `None`
+/// means the key can't match.
+pub type State = dfa::State;
+
+impl Automaton for Glob {
+    type State = State;
+
+    fn start(&self) -> State {
+        self.dfa.start()
+    }
+
+    fn is_match(&self, state: &State) -> bool {
+        self.dfa.is_match(state)
+    }
+
+    fn can_match(&self, state: &State) -> bool {
+        self.dfa.can_match(state)
+    }
+
+    fn accept(&self, state: &State, byte: u8) -> State {
+        self.dfa.accept(state, byte, |c| self.class(c))
+    }
+}
+
+/// A single element of a glob.
+#[derive(Debug)]
+enum Token {
+    Char(char),
+    Any,
+    Star,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl Token {
+    /// Returns true if this token consumes the codepoint `c`.
+    fn matches(&self, c: u32) -> bool {
+        match *self {
+            Token::Char(t) => t as u32 == c,
+            Token::Any | Token::Star => true,
+            Token::Class { negated, ref ranges } => {
+                let found = ranges.iter()
+                    .any(|&(s, e)| s as u32 <= c && c <= e as u32);
+                found != negated
+            }
+        }
+    }
+}
+
+fn parse(pattern: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec![];
+    let mut it = pattern.char_indices().peekable();
+    while let Some((offset, c)) = it.next() {
+        let token = match c {
+            '*' => {
+                // `**` is the same as `*`, and keeping just one keeps the
+                // DFA smaller.
+                if let Some(&Token::Star) = tokens.last() {
+                    continue;
+                }
+                Token::Star
+            }
+            '?' => Token::Any,
+            '\\' => match it.next() {
+                None => return Err(Error::TrailingEscape),
+                Some((_, c)) => Token::Char(c),
+            },
+            '[' => {
+                let mut negated = false;
+                if let Some(&(_, '!')) | Some(&(_, '^')) = it.peek() {
+                    negated = true;
+                    it.next();
+                }
+                let mut ranges = vec![];
+                let mut first = true;
+                loop {
+                    let (at, start) = match it.next() {
+                        None => return Err(Error::UnclosedClass(offset)),
+                        Some((_, ']')) if !first => break,
+                        Some((_, '\\')) => match it.next() {
+                            None => return Err(Error::TrailingEscape),
+                            Some(escaped) => escaped,
+                        },
+                        Some(next) => next,
+                    };
+                    first = false;
+                    let mut end = start;
+                    if let Some(&(_, '-')) = it.peek() {
+                        let mut ahead = it.clone();
+                        ahead.next();
+                        match ahead.next() {
+                            None => return Err(Error::UnclosedClass(offset)),
+                            // A trailing `-` is a literal.
+                            Some((_, ']')) => {}
+                            Some((_, '\\')) => match ahead.next() {
+                                None => return Err(Error::TrailingEscape),
+                                Some((_, c)) => {
+                                    end = c;
+                                    it = ahead;
+                                }
+                            },
+                            Some((_, c)) => {
+                                end = c;
+                                it = ahead;
+                            }
+                        }
+                    }
+                    if end < start {
+                        return Err(Error::InvalidRange(at, start, end));
+                    }
+                    ranges.push((start, end));
+                }
+                Token::Class { negated: negated, ranges: ranges }
+            }
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Partition all codepoints into classes such that every token either
+/// matches every codepoint in a class or none of them. Each class is
+/// represented by its smallest codepoint.
+fn classes(tokens: &[Token]) -> Vec<u32> {
+    let mut bounds = vec![0];
+    for token in tokens {
+        match *token {
+            Token::Char(c) => {
+                bounds.push(c as u32);
+                bounds.push(c as u32 + 1);
+            }
+            Token::Class { ref ranges, .. } => {
+                for &(s, e) in ranges {
+                    bounds.push(s as u32);
+                    bounds.push(e as u32 + 1);
+                }
+            }
+            Token::Any | Token::Star => {}
+        }
+    }
+    bounds.sort();
+    bounds.dedup();
+    bounds
+}
+
+/// Returns the positions reached from `set` on a character in the class
+/// whose smallest codepoint is `c`.
+fn step(tokens: &[Token], set: &[usize], c: u32) -> Vec<usize> {
+    let mut next = vec![];
+    for &pos in set {
+        if pos == tokens.len() || !tokens[pos].matches(c) {
+            continue;
+        }
+        match tokens[pos] {
+            Token::Star => next.push(pos),
+            _ => next.push(pos + 1),
+        }
+    }
+    closure(tokens, next)
+}
+
+/// Add every position reachable by skipping over a `*` (which may match
+/// nothing), and return the positions sorted and deduplicated.
+fn closure(tokens: &[Token], mut set: Vec<usize>) -> Vec<usize> {
+    let mut i = 0;
+    while i < set.len() {
+        let pos = set[i];
+        if let Some(&Token::Star) = tokens.get(pos) {
+            set.push(pos + 1);
+        }
+        i += 1;
+    }
+    set.sort();
+    set.dedup();
+    set
+}
+
+fn is_match(tokens: &[Token], set: &[usize]) -> bool {
+    set.last() == Some(&tokens.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use matches;
+    use super::Glob;
+
+    fn is_match(pattern: &str, key: &str) -> bool {
+        matches(&Glob::new(pattern).unwrap(), key.as_bytes())
+    }
+
+    fn error(pattern: &str) -> String {
+        Glob::new(pattern).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn wildcards() {
+        for key in &["", "a", "abc", "é"] {
+            assert!(is_match("*", key));
+        }
+        assert!(is_match("?", "a"));
+        assert!(is_match("?", "é"));
+        assert!(!is_match("?", ""));
+        assert!(!is_match("?", "ab"));
+        assert!(is_match("a*b", "ab"));
+        assert!(is_match("a*b", "axxb"));
+        assert!(!is_match("a*b", "axxbc"));
+        assert!(is_match("*.example.com", "www.example.com"));
+        assert!(!is_match("*.example.com", "example.com"));
+        assert!(is_match("a**b", "axb"));
+        assert!(is_match("*a?", "xaab"));
+        // Keys that aren't valid UTF-8 never match.
+        assert!(!matches(&Glob::new("*").unwrap(), b"\xFF"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(is_match("[a-c]??", "bxy"));
+        assert!(!is_match("[a-c]??", "dxy"));
+        assert!(!is_match("[a-c]??", "bx"));
+        assert!(is_match("[xa-cz]", "z"));
+        for pattern in &["[!a-c]", "[^a-c]"] {
+            assert!(is_match(pattern, "d"));
+            assert!(is_match(pattern, "é"));
+            assert!(!is_match(pattern, "a"));
+            assert!(!is_match(pattern, ""));
+        }
+        // A `]` first is a literal, as is a `-` first or last.
+        assert!(is_match("[]a]", "]"));
+        assert!(is_match("[]a]", "a"));
+        assert!(!is_match("[]a]", "b"));
+        assert!(is_match("[!]a]", "b"));
+        assert!(!is_match("[!]a]", "]"));
+        assert!(is_match("[-a]", "-"));
+        assert!(is_match("[a-]", "-"));
+        assert!(!is_match("[a-]", "b"));
+        assert!(is_match("[à-ü]", "é"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(is_match("\\*", "*"));
+        assert!(!is_match("\\*", "a"));
+        assert!(is_match("\\?\\[", "?["));
+        assert!(is_match("\\\\", "\\"));
+        assert!(is_match("[\\]]", "]"));
+        assert!(is_match("[a\\-z]", "-"));
+        assert!(!is_match("[a\\-z]", "b"));
+        assert!(is_match("[\\!a]", "!"));
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(error("[abc"),
+                   "unclosed character class starting at offset 0");
+        assert_eq!(error("x[a-"),
+                   "unclosed character class starting at offset 1");
+        assert_eq!(error("[]"),
+                   "unclosed character class starting at offset 0");
+        assert_eq!(error("é[z-a]"), "invalid range z-a at offset 3");
+        assert_eq!(error("abc\\"), "pattern ends with an unescaped backslash");
+        assert_eq!(error("[a\\"), "pattern ends with an unescaped backslash");
+        assert_eq!(error("[a-\\"), "pattern ends with an unescaped backslash");
+        // Remembering the last 14 characters after an `a` takes 2^14
+        // states.
+        assert_eq!(error("*a??????????????"),
+                   "glob automaton exceeds size limit of 10000 states");
+    }
+
+    #[test]
+    fn literals() {
+        let cases = [
+            ("foo*.txt", "foo", ".txt"),
+            ("abc", "abc", "abc"),
+            ("*", "", ""),
+            ("", "", ""),
+            ("a\\*b?c", "a*b", "c"),
+            ("[ab]x", "", "x"),
+            ("é?ü", "é", "ü"),
+        ];
+        for &(pattern, prefix, suffix) in &cases {
+            let glob = Glob::new(pattern).unwrap();
+            assert_eq!(glob.prefix(), prefix, "prefix of {:?}", pattern);
+            assert_eq!(glob.suffix(), suffix, "suffix of {:?}", pattern);
+        }
+    }
+}
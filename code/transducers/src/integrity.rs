@@ -0,0 +1,436 @@
+/*!
+A wrapper format that lets damaged FST files be detected.
+
+An FST file is just the bytes written by the builder, so `Set::from_path`
+trusts whatever it finds. A truncated or corrupted copy either fails with a
+confusing error or, worse, opens fine and returns garbage. A *sealed* file
+puts a header in front of the FST that records:
+
+* a magic number and the version of this format,
+* the number of keys in the FST,
+* the length and CRC-32 checksum of the FST,
+* build metadata, as a list of `(name, value)` pairs, like when and from what
+  the FST was built,
+* a CRC-32 checksum of the header itself.
+
+All integers are little endian:
+
+```text
+magic          8 bytes   "FSTSEAL\0"
+version        u32
+keys           u64
+fst length     u64
+fst checksum   u32
+metadata       u32 count, then for each pair, u32 length + name bytes and
+               u32 length + value bytes
+header crc     u32       (of every byte above)
+fst            fst length bytes
+```
+
+`seal` wraps an existing FST file. `open_set` and `open_map` memory map a
+sealed file and cheaply check everything but the FST's checksum, while
+`verify` also reads every byte of the FST to check its checksum. (The
+functions of the same name at the root of this crate open both sealed and
+plain FSTs, so every tool accepts sealed files.) Failures are reported
+with an `Error` that separates damaged files from version mismatches and I/O
+failures, so that, e.g., a nightly job can rebuild an index that's corrupt
+but stop for one written by a newer version of this code.
+*/
+
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use fst::{self, Map, Set};
+use fst::raw::{Fst, MmapReadOnly};
+
+/// The magic number that starts every sealed file.
+pub const MAGIC: &'static [u8; 8] = b"FSTSEAL\0";
+
+/// The version of the format written by this module.
+pub const VERSION: u32 = 1;
+
+/// An error reading or writing a sealed FST.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the file failed.
+    Io(io::Error),
+    /// The file, or the FST inside it, was written by an incompatible
+    /// version.
+    Version { expected: u64, got: u64 },
+    /// The file is damaged or isn't a sealed FST at all.
+    Corrupt(Corruption),
+}
+
+/// The ways in which a sealed FST can be damaged.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Corruption {
+    /// The file doesn't start with `MAGIC`.
+    BadMagic,
+    /// The file ended in the middle of the header.
+    TruncatedHeader,
+    /// The header's checksum doesn't match its contents.
+    HeaderChecksum { expected: u32, got: u32 },
+    /// The file doesn't have as many bytes as the header says it should.
+    Length { expected: u64, got: u64 },
+    /// The FST's checksum doesn't match the one in the header.
+    Checksum { expected: u32, got: u32 },
+    /// The FST has a different number of keys than the header says.
+    KeyCount { expected: u64, got: u64 },
+    /// The FST itself couldn't be read.
+    Fst(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "I/O error: {}", err),
+            Error::Version { expected, got } => write!(
+                f, "unsupported version {} (expected {})", got, expected),
+            Error::Corrupt(ref c) => write!(f, "corrupt FST: {}", c),
+        }
+    }
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Corruption::BadMagic => write!(f, "not a sealed FST"),
+            Corruption::TruncatedHeader => write!(f, "truncated header"),
+            Corruption::HeaderChecksum { expected, got } => write!(
+                f, "header checksum is {:08x} but should be {:08x}",
+                got, expected),
+            Corruption::Length { expected, got } => write!(
+                f, "file has {} bytes but should have {}", got, expected),
+            Corruption::Checksum { expected, got } => write!(
+                f, "checksum is {:08x} but should be {:08x}", got, expected),
+            Corruption::KeyCount { expected, got } => write!(
+                f, "FST has {} keys but should have {}", got, expected),
+            Corruption::Fst(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(ref err) => err.description(),
+            Error::Version { .. } => "unsupported version",
+            Error::Corrupt(_) => "corrupt FST",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<fst::Error> for Error {
+    fn from(err: fst::Error) -> Error {
+        match err {
+            fst::Error::Io(err) => Error::Io(err),
+            fst::Error::Fst(fst::raw::Error::Version { expected, got }) => {
+                Error::Version { expected: expected, got: got }
+            }
+            err => Error::Corrupt(Corruption::Fst(err.to_string())),
+        }
+    }
+}
+
+impl From<Corruption> for Error {
+    fn from(c: Corruption) -> Error {
+        Error::Corrupt(c)
+    }
+}
+
+/// The header of a sealed FST.
+#[derive(Clone, Debug)]
+pub struct Header {
+    /// The version of the format the file was written with.
+    pub version: u32,
+    /// The number of keys in the FST.
+    pub keys: u64,
+    /// The length of the FST, in bytes.
+    pub fst_len: u64,
+    /// The CRC-32 checksum of the FST.
+    pub checksum: u32,
+    /// Build metadata, in the order it was given to `seal`.
+    pub metadata: Vec<(String, String)>,
+    /// The length of the header, i.e., where the FST starts.
+    pub len: u64,
+}
+
+impl Header {
+    /// Returns the value of the metadata entry named `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.metadata.iter()
+            .find(|&&(ref n, _)| n == name)
+            .map(|&(_, ref v)| &**v)
+    }
+}
+
+/// Returns whether the file at `path` starts with `MAGIC`.
+pub fn is_sealed<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    let mut magic = vec![];
+    File::open(path)?.take(MAGIC.len() as u64).read_to_end(&mut magic)?;
+    Ok(&*magic == MAGIC)
+}
+
+/// Write the FST in the file at `fst_path` to `wtr` with a header recording
+/// `metadata`. Returns the header that was written.
+pub fn seal<P: AsRef<Path>, W: Write>(
+    fst_path: P,
+    mut wtr: W,
+    metadata: &[(String, String)],
+) -> Result<Header, Error> {
+    let mmap = MmapReadOnly::open_path(fst_path)?;
+    let keys = Fst::from_mmap(mmap.range(0, mmap.len()))?.len() as u64;
+    let bytes = unsafe { mmap.as_slice() };
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+
+    let mut head = vec![];
+    head.extend_from_slice(MAGIC);
+    push_u32(&mut head, VERSION);
+    push_u64(&mut head, keys);
+    push_u64(&mut head, bytes.len() as u64);
+    push_u32(&mut head, crc.sum());
+    push_u32(&mut head, metadata.len() as u32);
+    for &(ref name, ref value) in metadata {
+        push_u32(&mut head, name.len() as u32);
+        head.extend_from_slice(name.as_bytes());
+        push_u32(&mut head, value.len() as u32);
+        head.extend_from_slice(value.as_bytes());
+    }
+    let mut head_crc = Crc32::new();
+    head_crc.update(&head);
+    push_u32(&mut head, head_crc.sum());
+
+    wtr.write_all(&head)?;
+    wtr.write_all(bytes)?;
+    wtr.flush()?;
+    Ok(Header {
+        version: VERSION,
+        keys: keys,
+        fst_len: bytes.len() as u64,
+        checksum: crc.sum(),
+        metadata: metadata.to_vec(),
+        len: head.len() as u64,
+    })
+}
+
+/// Read and check the header at the start of `rdr`.
+///
+/// This checks the magic number, the header's own checksum and then the
+/// version, but not the FST that follows it. The version is only trusted
+/// once the checksum matches, so a damaged version field is reported as
+/// corruption rather than as a version mismatch. (This means later versions
+/// of the format must keep the layout of the header, up to and including its
+/// checksum, so that this code can tell them apart from damaged files.)
+pub fn read_header<R: Read>(rdr: R) -> Result<Header, Error> {
+    let mut rdr = HeaderReader { rdr: rdr, crc: Crc32::new(), len: 0 };
+    let mut magic = [0; 8];
+    rdr.read(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(From::from(Corruption::BadMagic));
+    }
+    let version = rdr.read_u32()?;
+    let keys = rdr.read_u64()?;
+    let fst_len = rdr.read_u64()?;
+    let checksum = rdr.read_u32()?;
+    let count = rdr.read_u32()?;
+    let mut metadata = vec![];
+    for _ in 0..count {
+        let name = rdr.read_bytes()?;
+        let value = rdr.read_bytes()?;
+        metadata.push((name, value));
+    }
+    let expected = rdr.crc.sum();
+    let got = rdr.read_u32()?;
+    if got != expected {
+        return Err(From::from(Corruption::HeaderChecksum {
+            expected: expected,
+            got: got,
+        }));
+    }
+    if version != VERSION {
+        return Err(Error::Version {
+            expected: VERSION as u64,
+            got: version as u64,
+        });
+    }
+    Ok(Header {
+        version: version,
+        keys: keys,
+        fst_len: fst_len,
+        checksum: checksum,
+        // `seal` only writes strings, so this is lossless for any header
+        // whose checksum matches.
+        metadata: metadata.into_iter().map(|(name, value)| {
+            (String::from_utf8_lossy(&name).into_owned(),
+             String::from_utf8_lossy(&value).into_owned())
+        }).collect(),
+        len: rdr.len,
+    })
+}
+
+/// Check every byte of the sealed FST at `path`, returning its header if
+/// it's intact.
+pub fn verify<P: AsRef<Path>>(path: P) -> Result<Header, Error> {
+    let (header, _) = open(path, true)?;
+    Ok(header)
+}
+
+/// Memory map the sealed set at `path`.
+///
+/// The header, file length and key count are checked, but the checksum of
+/// the set isn't, since that would mean reading the whole file. Use `verify`
+/// for that.
+pub fn open_set<P: AsRef<Path>>(path: P) -> Result<(Header, Set), Error> {
+    let (header, fst) = open(path, false)?;
+    Ok((header, Set::from(fst)))
+}
+
+/// Memory map the sealed map at `path`. As with `open_set`, this doesn't
+/// check the map's checksum.
+pub fn open_map<P: AsRef<Path>>(path: P) -> Result<(Header, Map), Error> {
+    let (header, fst) = open(path, false)?;
+    Ok((header, Map::from(fst)))
+}
+
+/// Open a sealed FST, also checking its checksum if `checksum` is true.
+fn open<P: AsRef<Path>>(
+    path: P,
+    checksum: bool,
+) -> Result<(Header, Fst), Error> {
+    let mmap = MmapReadOnly::open_path(path)?;
+    let header = read_header(unsafe { mmap.as_slice() })?;
+    let expected = header.len + header.fst_len;
+    if mmap.len() as u64 != expected {
+        return Err(From::from(Corruption::Length {
+            expected: expected,
+            got: mmap.len() as u64,
+        }));
+    }
+    let mmap = mmap.range(header.len as usize, header.fst_len as usize);
+    if checksum {
+        let mut crc = Crc32::new();
+        crc.update(unsafe { mmap.as_slice() });
+        if crc.sum() != header.checksum {
+            return Err(From::from(Corruption::Checksum {
+                expected: header.checksum,
+                got: crc.sum(),
+            }));
+        }
+    }
+    let fst = Fst::from_mmap(mmap)?;
+    if fst.len() as u64 != header.keys {
+        return Err(From::from(Corruption::KeyCount {
+            expected: header.keys,
+            got: fst.len() as u64,
+        }));
+    }
+    Ok((header, fst))
+}
+
+/// Reads the fields of a header while keeping track of their checksum.
+struct HeaderReader<R> {
+    rdr: R,
+    crc: Crc32,
+    len: u64,
+}
+
+impl<R: Read> HeaderReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        self.rdr.read_exact(buf).map_err(|err| {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                Error::Corrupt(Corruption::TruncatedHeader)
+            } else {
+                Error::Io(err)
+            }
+        })?;
+        self.crc.update(buf);
+        self.len += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        let mut buf = [0; 4];
+        self.read(&mut buf)?;
+        Ok(buf.iter().rev().fold(0, |n, &b| (n << 8) | b as u32))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        let mut buf = [0; 8];
+        self.read(&mut buf)?;
+        Ok(buf.iter().rev().fold(0, |n, &b| (n << 8) | b as u64))
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>, Error> {
+        let len = self.read_u32()? as u64;
+        // A damaged length could be huge, so read what's there instead of
+        // allocating the whole thing up front.
+        let mut buf = vec![];
+        (&mut self.rdr).take(len).read_to_end(&mut buf)?;
+        if buf.len() as u64 != len {
+            return Err(From::from(Corruption::TruncatedHeader));
+        }
+        self.crc.update(&buf);
+        self.len += len;
+        Ok(buf)
+    }
+}
+
+fn push_u32(buf: &mut Vec<u8>, n: u32) {
+    for i in 0..4 {
+        buf.push((n >> (8 * i)) as u8);
+    }
+}
+
+fn push_u64(buf: &mut Vec<u8>, n: u64) {
+    for i in 0..8 {
+        buf.push((n >> (8 * i)) as u8);
+    }
+}
+
+/// The CRC-32 checksum used by zlib, PNG and friends.
+struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    fn new() -> Crc32 {
+        let mut table = [0; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0..8 {
+                c = if c & 1 == 1 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        Crc32 { table: table, crc: !0 }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            let i = (self.crc ^ b as u32) & 0xFF;
+            self.crc = self.table[i as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    fn sum(&self) -> u32 {
+        !self.crc
+    }
+}
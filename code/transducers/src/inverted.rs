@@ -0,0 +1,495 @@
+/*!
+A small full text index: an FST term dictionary plus a postings file.
+
+The blog post points out that a map can index text by mapping each term to
+something. Here, that something is an offset into a postings file, which
+lists every document the term appears in along with the positions (word
+numbers) it appears at. That's enough to answer AND, OR and phrase queries,
+and since the dictionary is an FST, any automaton can pick the terms: a
+query term can be expanded into every term within some edit distance, or
+matching some regex, and their postings combined.
+
+An index is a directory with three files:
+
+* `terms.fst` maps each term to the offset of its postings.
+* `postings` holds the postings of every term, one after the other. Each
+  starts with its length in bytes, then the number of documents, then, for
+  each document, the difference from the previous document ID, the number of
+  positions and the differences between consecutive positions. Every number
+  is a LEB128 varint, so common terms with dense postings stay small.
+* `docs` lists the name of every document, one per line. A document's ID is
+  its line number, starting at 0.
+
+Text is split into terms at anything that isn't alphanumeric, and terms are
+lowercased. Building sorts `(term, document, position)` triples with an
+`ExternalSorter`, so memory use is bounded no matter how big the corpus is.
+*/
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+
+use fst::{Automaton, IntoStreamer, Map, MapBuilder, Streamer};
+use fst::raw::MmapReadOnly;
+use fst_levenshtein::Levenshtein;
+use fst_regex::Regex;
+
+use {Result, open_map};
+use extsort::ExternalSorter;
+use varint;
+
+/// The documents containing a term (or matching a query), along with the
+/// positions of the matches in each document.
+pub type Postings = Vec<Posting>;
+
+/// One document in a postings list.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Posting {
+    /// The document's ID.
+    pub doc: u32,
+    /// The positions of the matches in the document, in ascending order.
+    /// For a phrase, these are the positions of its first term.
+    pub positions: Vec<u32>,
+}
+
+/// Split text into lowercased terms, in order.
+pub fn terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+        .collect()
+}
+
+/// Builds an index in a directory.
+pub struct IndexBuilder {
+    dir: PathBuf,
+    docs: Vec<String>,
+    sorter: ExternalSorter,
+}
+
+impl IndexBuilder {
+    /// Create a builder that writes an index to `dir`, creating the
+    /// directory if necessary.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<IndexBuilder> {
+        fs::create_dir_all(&dir)?;
+        Ok(IndexBuilder {
+            dir: dir.as_ref().to_path_buf(),
+            docs: vec![],
+            sorter: ExternalSorter::new(),
+        })
+    }
+
+    /// The sorter used for the index's postings. Use this to set its memory
+    /// limit or temporary directory before adding any documents.
+    pub fn sorter(&mut self) -> &mut ExternalSorter {
+        &mut self.sorter
+    }
+
+    /// Add a document with the given name and text, and return its ID.
+    pub fn add_document(&mut self, name: &str, text: &str) -> Result<u32> {
+        if name.contains('\n') {
+            return Err(From::from(format!(
+                "document name contains a newline: {:?}", name)));
+        }
+        let doc = self.docs.len() as u32;
+        self.docs.push(name.to_string());
+        for (pos, term) in terms(text).into_iter().enumerate() {
+            let mut key = term.into_bytes();
+            key.push(0);
+            key.extend_from_slice(&u32_be(doc));
+            key.extend_from_slice(&u32_be(pos as u32));
+            self.sorter.push(key, 0)?;
+        }
+        Ok(doc)
+    }
+
+    /// Add the file at `path` as a document named after its path. Bytes
+    /// that aren't valid UTF-8 are replaced.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<u32> {
+        let mut bytes = vec![];
+        File::open(&path)?.read_to_end(&mut bytes)?;
+        let name = path.as_ref().to_string_lossy().into_owned();
+        self.add_document(&name, &String::from_utf8_lossy(&bytes))
+    }
+
+    /// Add every file in `dir` and its subdirectories, in lexicographic
+    /// order of their paths. Returns the number of files added.
+    pub fn add_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<usize> {
+        let mut files = vec![];
+        let mut stack = vec![dir.as_ref().to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    stack.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
+        for path in &files {
+            self.add_file(path)?;
+        }
+        Ok(files.len())
+    }
+
+    /// Write the index.
+    pub fn finish(self) -> Result<()> {
+        let mut docs = io::BufWriter::new(
+            File::create(self.dir.join("docs"))?);
+        for name in &self.docs {
+            writeln!(docs, "{}", name)?;
+        }
+        docs.flush()?;
+
+        let mut terms = MapBuilder::new(io::BufWriter::new(
+            File::create(self.dir.join("terms.fst"))?))?;
+        let mut postings = io::BufWriter::new(
+            File::create(self.dir.join("postings"))?);
+        let mut offset = 0;
+        let mut term: Vec<u8> = vec![];
+        let mut list: Postings = vec![];
+        for result in self.sorter.finish()? {
+            let (key, _) = result?;
+            let i = key.len() - 9;
+            let doc = be_u32(&key[i + 1..i + 5]);
+            let pos = be_u32(&key[i + 5..]);
+            if key[..i] != term[..] {
+                if !list.is_empty() {
+                    terms.insert(&term, offset)?;
+                    offset += write_postings(&mut postings, &list)?;
+                    list.clear();
+                }
+                term = key[..i].to_vec();
+            }
+            if list.last().map_or(true, |p| p.doc != doc) {
+                list.push(Posting { doc: doc, positions: vec![] });
+            }
+            list.last_mut().unwrap().positions.push(pos);
+        }
+        if !list.is_empty() {
+            terms.insert(&term, offset)?;
+            write_postings(&mut postings, &list)?;
+        }
+        postings.flush()?;
+        terms.finish()?;
+        Ok(())
+    }
+}
+
+/// A query against an index.
+#[derive(Clone, Debug)]
+pub enum Query {
+    /// Documents containing the term.
+    Term(String),
+    /// Documents containing any term within the given edit distance of the
+    /// term.
+    Fuzzy(String, u32),
+    /// Documents containing any term matching the regex.
+    Regex(String),
+    /// Documents matching every query.
+    And(Vec<Query>),
+    /// Documents matching any of the queries.
+    Or(Vec<Query>),
+    /// Documents where the queries match at consecutive positions.
+    Phrase(Vec<Query>),
+}
+
+/// An index opened for searching.
+pub struct Index {
+    terms: Map,
+    /// An index without any terms has an empty postings file, which can't
+    /// be memory mapped.
+    postings: Option<MmapReadOnly>,
+    docs: Vec<String>,
+}
+
+impl Index {
+    /// Open the index in `dir`. The term dictionary and postings are memory
+    /// mapped, so callers must not modify them while the index is in use.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Index> {
+        let dir = dir.as_ref();
+        let mut docs = vec![];
+        for line in io::BufReader::new(File::open(dir.join("docs"))?).lines() {
+            docs.push(line?);
+        }
+        let file = File::open(dir.join("postings"))?;
+        let postings = if file.metadata()?.len() == 0 {
+            None
+        } else {
+            Some(MmapReadOnly::open(&file)?)
+        };
+        Ok(Index {
+            terms: open_map(dir.join("terms.fst"))?,
+            postings: postings,
+            docs: docs,
+        })
+    }
+
+    /// The names of the documents in the index, indexed by ID.
+    pub fn docs(&self) -> &[String] {
+        &self.docs
+    }
+
+    /// The term dictionary, mapping each term to the offset of its postings.
+    pub fn terms(&self) -> &Map {
+        &self.terms
+    }
+
+    /// Find the documents matching `query`, in ascending order of ID.
+    pub fn search(&self, query: &Query) -> Result<Postings> {
+        match *query {
+            Query::Term(ref term) => {
+                match self.terms.get(term.to_lowercase()) {
+                    None => Ok(vec![]),
+                    Some(offset) => self.read_postings(offset),
+                }
+            }
+            Query::Fuzzy(ref term, distance) => {
+                let lev = Levenshtein::new(&term.to_lowercase(), distance)?;
+                self.expand(lev)
+            }
+            Query::Regex(ref re) => self.expand(Regex::new(re)?),
+            Query::And(ref queries) => {
+                let mut result: Option<Postings> = None;
+                for q in queries {
+                    let postings = self.search(q)?;
+                    result = Some(match result {
+                        None => postings,
+                        Some(result) => intersect(&result, &postings),
+                    });
+                    if result.as_ref().map_or(false, |r| r.is_empty()) {
+                        break;
+                    }
+                }
+                Ok(result.unwrap_or(vec![]))
+            }
+            Query::Or(ref queries) => {
+                let mut result = vec![];
+                for q in queries {
+                    result = union(&result, &self.search(q)?);
+                }
+                Ok(result)
+            }
+            Query::Phrase(ref queries) => {
+                let mut result: Option<Postings> = None;
+                for (offset, q) in queries.iter().enumerate() {
+                    let postings = self.search(q)?;
+                    result = Some(match result {
+                        None => postings,
+                        Some(result) => {
+                            follow(&result, &postings, offset as u32)
+                        }
+                    });
+                    if result.as_ref().map_or(false, |r| r.is_empty()) {
+                        break;
+                    }
+                }
+                Ok(result.unwrap_or(vec![]))
+            }
+        }
+    }
+
+    /// Combine the postings of every term matched by `aut`, as if they were
+    /// one term.
+    pub fn expand<A: Automaton>(&self, aut: A) -> Result<Postings> {
+        let mut result = vec![];
+        let mut stream = self.terms.search(aut).into_stream();
+        while let Some((_, offset)) = stream.next() {
+            result = union(&result, &self.read_postings(offset)?);
+        }
+        Ok(result)
+    }
+
+    fn read_postings(&self, offset: u64) -> Result<Postings> {
+        let postings = match self.postings {
+            None => &[][..],
+            Some(ref mmap) => unsafe { mmap.as_slice() },
+        };
+        if offset >= postings.len() as u64 {
+            return Err(From::from(format!(
+                "corrupt postings: offset {} is out of bounds", offset)));
+        }
+        let mut buf = &postings[offset as usize..];
+        let len = varint::read(&mut buf)?;
+        if len > buf.len() as u64 {
+            return Err(From::from(format!(
+                "corrupt postings: list at offset {} is truncated", offset)));
+        }
+        decode_postings(&buf[..len as usize])
+    }
+}
+
+/// Returns the documents in both `a` and `b`, with the positions from both.
+pub fn intersect(a: &[Posting], b: &[Posting]) -> Postings {
+    intersect_with(a, b, merge)
+}
+
+/// Returns the documents in `a` or `b`, with the positions from both.
+pub fn union(a: &[Posting], b: &[Posting]) -> Postings {
+    let mut result = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if j == b.len() || (i < a.len() && a[i].doc < b[j].doc) {
+            result.push(a[i].clone());
+            i += 1;
+        } else if i == a.len() || b[j].doc < a[i].doc {
+            result.push(b[j].clone());
+            j += 1;
+        } else {
+            result.push(Posting {
+                doc: a[i].doc,
+                positions: merge(&a[i].positions, &b[j].positions),
+            });
+            i += 1;
+            j += 1;
+        }
+    }
+    result
+}
+
+/// Returns the positions in `a` such that `b` matches `offset` positions
+/// later in the same document.
+fn follow(a: &[Posting], b: &[Posting], offset: u32) -> Postings {
+    intersect_with(a, b, |pa, pb| {
+        pa.iter()
+            .cloned()
+            .filter(|&p| pb.binary_search(&(p + offset)).is_ok())
+            .collect()
+    })
+}
+
+/// Returns the documents in both `a` and `b` for which `combine` returns at
+/// least one position.
+fn intersect_with<F>(a: &[Posting], b: &[Posting], mut combine: F) -> Postings
+where F: FnMut(&[u32], &[u32]) -> Vec<u32> {
+    let mut result = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].doc < b[j].doc {
+            i += 1;
+        } else if a[i].doc > b[j].doc {
+            j += 1;
+        } else {
+            let positions = combine(&a[i].positions, &b[j].positions);
+            if !positions.is_empty() {
+                result.push(Posting { doc: a[i].doc, positions: positions });
+            }
+            i += 1;
+            j += 1;
+        }
+    }
+    result
+}
+
+/// Merge two sorted lists of positions, removing duplicates.
+fn merge(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    merged.extend_from_slice(a);
+    merged.extend_from_slice(b);
+    merged.sort();
+    merged.dedup();
+    merged
+}
+
+/// Write the postings of one term and return the number of bytes written.
+fn write_postings<W: Write>(mut wtr: W, postings: &[Posting]) -> Result<u64> {
+    let mut buf = vec![];
+    varint::write(&mut buf, postings.len() as u64);
+    let mut prev_doc = 0;
+    for p in postings {
+        varint::write(&mut buf, (p.doc - prev_doc) as u64);
+        prev_doc = p.doc;
+        varint::write(&mut buf, p.positions.len() as u64);
+        let mut prev_pos = 0;
+        for &pos in &p.positions {
+            varint::write(&mut buf, (pos - prev_pos) as u64);
+            prev_pos = pos;
+        }
+    }
+    let mut len = vec![];
+    varint::write(&mut len, buf.len() as u64);
+    wtr.write_all(&len)?;
+    wtr.write_all(&buf)?;
+    Ok((len.len() + buf.len()) as u64)
+}
+
+fn decode_postings(mut buf: &[u8]) -> Result<Postings> {
+    let count = varint::read(&mut buf)?;
+    let mut postings = vec![];
+    let mut doc = 0;
+    for _ in 0..count {
+        doc = add_delta(doc, varint::read(&mut buf)?)?;
+        let npositions = varint::read(&mut buf)?;
+        let mut positions = vec![];
+        let mut pos = 0;
+        for _ in 0..npositions {
+            pos = add_delta(pos, varint::read(&mut buf)?)?;
+            positions.push(pos);
+        }
+        postings.push(Posting { doc: doc, positions: positions });
+    }
+    Ok(postings)
+}
+
+/// Add a delta read from a postings list to a document or position, which
+/// only goes past `u32::MAX` if the list is corrupt.
+fn add_delta(n: u32, delta: u64) -> Result<u32> {
+    if delta <= u32::max_value() as u64 {
+        if let Some(sum) = n.checked_add(delta as u32) {
+            return Ok(sum);
+        }
+    }
+    Err(From::from("corrupt postings: document or position is out of range"))
+}
+
+fn u32_be(n: u32) -> [u8; 4] {
+    [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |n, &b| (n << 8) | b as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use varint;
+
+    use super::{Posting, decode_postings, write_postings};
+
+    #[test]
+    fn postings_roundtrip() {
+        let postings = vec![
+            Posting { doc: 3, positions: vec![0, 5, 900] },
+            Posting { doc: 10, positions: vec![] },
+            Posting {
+                doc: u32::max_value(),
+                positions: vec![u32::max_value()],
+            },
+        ];
+        let mut buf = vec![];
+        let n = write_postings(&mut buf, &postings).unwrap();
+        assert_eq!(n as usize, buf.len());
+        let mut rdr = &buf[..];
+        assert_eq!(varint::read(&mut rdr).unwrap() as usize, rdr.len());
+        assert_eq!(decode_postings(rdr).unwrap(), postings);
+    }
+
+    #[test]
+    fn postings_overflow() {
+        // Two documents whose IDs add up to more than `u32::MAX`.
+        let mut buf = vec![];
+        for &n in &[2, u32::max_value() as u64, 0, 1, 0] {
+            varint::write(&mut buf, n);
+        }
+        assert!(decode_postings(&buf).is_err());
+
+        // A position that doesn't fit in a `u32` on its own.
+        let mut buf = vec![];
+        for &n in &[1, 0, 1, 1 << 32] {
+            varint::write(&mut buf, n);
+        }
+        assert!(decode_postings(&buf).is_err());
+    }
+}
@@ -0,0 +1,524 @@
+/*!
+A key-value store whose values are arbitrary bytes, keyed by an FST.
+
+A `Map` can only associate a key with a `u64`. That's enough when the value
+is a count or an ID, but most data stores want to look up a string, a
+serialized record or a blob. The usual trick, which the `inverted` module
+also uses for postings, is to make the `u64` an offset into a second file
+that holds the real values. `FstKv` packages that up: keys are still
+searched with ranges and automata, and each result comes with its value.
+
+A store is a directory with two files:
+
+* `keys.fst` maps each key to the offset of its value.
+* `values` starts with the magic number `FSTKVAL\0`, followed by the value
+  of every key in key order. Each value is written as a LEB128 varint of its
+  length in bytes shifted left by one, whose lowest bit is set if the value
+  is compressed, followed by that many bytes.
+
+Both files are memory mapped when a store is opened, so reading a value that
+isn't compressed copies nothing: `get` and streams return slices of the
+`values` file.
+
+With `FstKvBuilder::compress`, values are compressed with a small LZ77
+scheme, which is cheap to decode and does well on values with repeated
+substrings, like JSON records or URLs. It's nowhere near as good as a real
+compression library, but this crate doesn't depend on one. A compressed
+value is a varint of its decompressed length followed by a sequence of
+operations, each starting with a varint tag: if the tag's lowest bit is
+clear, the rest of it is a number of literal bytes that follow, and
+otherwise it's a copy of `(tag >> 1) + 4` bytes, starting at a distance
+given by another varint back from the end of what's been decompressed so
+far. Each value is compressed on its own, so that reading one never means
+decompressing another, which also means short values rarely shrink. A value
+is only stored compressed if that makes it smaller.
+*/
+
+use std::borrow::Cow;
+use std::cmp;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use fst::{Automaton, IntoStreamer, Map, MapBuilder, Streamer, map};
+use fst::automaton::AlwaysMatch;
+use fst::raw::MmapReadOnly;
+
+use {Result, open_map};
+use varint;
+
+/// The magic number that starts every values file.
+pub const MAGIC: &'static [u8; 8] = b"FSTKVAL\0";
+
+/// Values shorter than this aren't worth trying to compress.
+const MIN_COMPRESS: usize = 32;
+
+/// The shortest copy the compressor emits.
+const MIN_MATCH: usize = 4;
+
+/// The number of bits in the compressor's hash of 4 bytes.
+const HASH_BITS: u32 = 14;
+
+/// Builds a store in a directory.
+///
+/// Keys must be inserted in lexicographic order, as with `MapBuilder`.
+pub struct FstKvBuilder {
+    keys: MapBuilder<io::BufWriter<File>>,
+    values: io::BufWriter<File>,
+    offset: u64,
+    compress: bool,
+    buf: Vec<u8>,
+}
+
+impl FstKvBuilder {
+    /// Create a builder that writes a store to `dir`, creating the directory
+    /// if necessary.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<FstKvBuilder> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let keys = MapBuilder::new(io::BufWriter::new(
+            File::create(dir.join("keys.fst"))?))?;
+        let mut values = io::BufWriter::new(
+            File::create(dir.join("values"))?);
+        values.write_all(MAGIC)?;
+        Ok(FstKvBuilder {
+            keys: keys,
+            values: values,
+            offset: MAGIC.len() as u64,
+            compress: false,
+            buf: vec![],
+        })
+    }
+
+    /// Whether to compress values that get smaller when compressed. This is
+    /// off by default.
+    pub fn compress(&mut self, yes: bool) -> &mut FstKvBuilder {
+        self.compress = yes;
+        self
+    }
+
+    /// Add `key` with the given value. It's an error if `key` isn't greater
+    /// than every key added before it.
+    pub fn insert<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where K: AsRef<[u8]>, V: AsRef<[u8]> {
+        // The key goes first, so that nothing is written for a key that's
+        // out of order.
+        self.keys.insert(key, self.offset)?;
+        let value = value.as_ref();
+        let mut compressed = false;
+        if self.compress && value.len() >= MIN_COMPRESS {
+            compress(value, &mut self.buf);
+            compressed = self.buf.len() < value.len();
+        }
+        let payload = if compressed { &self.buf[..] } else { value };
+        let mut header = vec![];
+        varint::write(
+            &mut header, (payload.len() as u64) << 1 | compressed as u64);
+        self.values.write_all(&header)?;
+        self.values.write_all(payload)?;
+        self.offset += (header.len() + payload.len()) as u64;
+        Ok(())
+    }
+
+    /// Write the store.
+    pub fn finish(mut self) -> Result<()> {
+        self.values.flush()?;
+        self.keys.finish()?;
+        Ok(())
+    }
+}
+
+/// A store opened for reading.
+pub struct FstKv {
+    keys: Map,
+    values: MmapReadOnly,
+}
+
+impl FstKv {
+    /// Open the store in `dir`. Both of its files are memory mapped, so
+    /// callers must not modify them while the store is in use.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<FstKv> {
+        let dir = dir.as_ref();
+        let keys = open_map(dir.join("keys.fst"))?;
+        let path = dir.join("values");
+        let values = MmapReadOnly::open_path(&path)?;
+        if !unsafe { values.as_slice() }.starts_with(MAGIC) {
+            return Err(From::from(format!(
+                "{} is not a values file", path.display())));
+        }
+        Ok(FstKv { keys: keys, values: values })
+    }
+
+    /// The number of keys in the store.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns true if the store has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The map from each key to the offset of its value.
+    pub fn keys(&self) -> &Map {
+        &self.keys
+    }
+
+    /// Returns the value of `key`, if it's in the store. The value is
+    /// borrowed from the values file unless it had to be decompressed.
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Cow<[u8]>>> {
+        let offset = match self.keys.get(key) {
+            None => return Ok(None),
+            Some(offset) => offset,
+        };
+        let (compressed, payload) = record(self.values(), offset)?;
+        if !compressed {
+            return Ok(Some(Cow::Borrowed(payload)));
+        }
+        let mut value = vec![];
+        decompress(payload, &mut value)?;
+        Ok(Some(Cow::Owned(value)))
+    }
+
+    /// Returns true if `key` is in the store.
+    pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.keys.contains_key(key)
+    }
+
+    /// Returns a builder for a stream of every key in the store, or every key
+    /// in a range once bounds are set, with their values.
+    pub fn range(&self) -> StreamBuilder<AlwaysMatch> {
+        StreamBuilder { builder: self.keys.range(), values: self.values() }
+    }
+
+    /// Returns a builder for a stream of the keys matching `aut`, with their
+    /// values.
+    pub fn search<A: Automaton>(&self, aut: A) -> StreamBuilder<A> {
+        StreamBuilder {
+            builder: self.keys.search(aut),
+            values: self.values(),
+        }
+    }
+
+    fn values(&self) -> &[u8] {
+        unsafe { self.values.as_slice() }
+    }
+}
+
+/// A query against a store, which may be restricted to a range of keys.
+pub struct StreamBuilder<'s, A = AlwaysMatch> {
+    builder: map::StreamBuilder<'s, A>,
+    values: &'s [u8],
+}
+
+impl<'s, A: Automaton> StreamBuilder<'s, A> {
+    /// Only include keys greater than or equal to `bound`.
+    pub fn ge<K: AsRef<[u8]>>(mut self, bound: K) -> StreamBuilder<'s, A> {
+        self.builder = self.builder.ge(bound);
+        self
+    }
+
+    /// Only include keys greater than `bound`.
+    pub fn gt<K: AsRef<[u8]>>(mut self, bound: K) -> StreamBuilder<'s, A> {
+        self.builder = self.builder.gt(bound);
+        self
+    }
+
+    /// Only include keys less than or equal to `bound`.
+    pub fn le<K: AsRef<[u8]>>(mut self, bound: K) -> StreamBuilder<'s, A> {
+        self.builder = self.builder.le(bound);
+        self
+    }
+
+    /// Only include keys less than `bound`.
+    pub fn lt<K: AsRef<[u8]>>(mut self, bound: K) -> StreamBuilder<'s, A> {
+        self.builder = self.builder.lt(bound);
+        self
+    }
+
+    /// Start the query.
+    pub fn into_stream(self) -> Stream<'s, A> {
+        Stream {
+            stream: self.builder.into_stream(),
+            values: self.values,
+            buf: vec![],
+        }
+    }
+}
+
+/// The keys of a store matching a query, in lexicographic order, with their
+/// values.
+pub struct Stream<'s, A: Automaton> {
+    stream: map::Stream<'s, A>,
+    values: &'s [u8],
+    /// The last value that was decompressed.
+    buf: Vec<u8>,
+}
+
+impl<'s, A: Automaton> Stream<'s, A> {
+    /// Returns the next key and its value, or `None` once every key has been
+    /// returned. Reading a value fails if the values file is corrupt.
+    pub fn next(&mut self) -> Result<Option<(&[u8], &[u8])>> {
+        let (key, offset) = match self.stream.next() {
+            None => return Ok(None),
+            Some(next) => next,
+        };
+        let (compressed, payload) = record(self.values, offset)?;
+        if !compressed {
+            return Ok(Some((key, payload)));
+        }
+        decompress(payload, &mut self.buf)?;
+        Ok(Some((key, &self.buf)))
+    }
+}
+
+/// Returns the value at `offset` in a values file, along with whether it's
+/// compressed.
+fn record(values: &[u8], offset: u64) -> Result<(bool, &[u8])> {
+    if offset < MAGIC.len() as u64 || offset >= values.len() as u64 {
+        return Err(From::from(format!(
+            "corrupt values: offset {} is out of bounds", offset)));
+    }
+    let mut rdr = &values[offset as usize..];
+    let header = varint::read(&mut rdr)?;
+    let len = header >> 1;
+    if len > rdr.len() as u64 {
+        return Err(From::from(format!(
+            "corrupt values: value at offset {} is truncated", offset)));
+    }
+    Ok((header & 1 == 1, &rdr[..len as usize]))
+}
+
+/// Compress `input` into `out`, replacing its contents.
+///
+/// This is a greedy LZ77: a table maps a hash of every 4 bytes seen so far
+/// to where they were last seen, and whenever the next 4 bytes were seen
+/// before, the match is extended as far as it goes and written as a copy.
+fn compress(input: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+    varint::write(out, input.len() as u64);
+    let mut table = vec![0usize; 1 << HASH_BITS];
+    let (mut literals, mut i) = (0, 0);
+    while i + MIN_MATCH <= input.len() {
+        let h = hash(&input[i..i + MIN_MATCH]);
+        // Positions are stored plus one, so that 0 means empty.
+        let candidate = table[h];
+        table[h] = i + 1;
+        if candidate == 0 {
+            i += 1;
+            continue;
+        }
+        let start = candidate - 1;
+        if input[start..start + MIN_MATCH] != input[i..i + MIN_MATCH] {
+            i += 1;
+            continue;
+        }
+        let mut len = MIN_MATCH;
+        while i + len < input.len() && input[start + len] == input[i + len] {
+            len += 1;
+        }
+        write_literals(out, &input[literals..i]);
+        varint::write(out, ((len - MIN_MATCH) << 1 | 1) as u64);
+        varint::write(out, (i - start) as u64);
+        i += len;
+        literals = i;
+    }
+    write_literals(out, &input[literals..]);
+}
+
+/// Decompress `payload` into `out`, replacing its contents.
+fn decompress(mut payload: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let corrupt = || From::from("corrupt values: invalid compressed value");
+    out.clear();
+    let len = varint::read(&mut payload)?;
+    // Don't trust a corrupt length with a huge allocation.
+    out.reserve(cmp::min(len, 1 << 20) as usize);
+    while !payload.is_empty() {
+        let tag = varint::read(&mut payload)?;
+        let remaining = len - out.len() as u64;
+        if tag & 1 == 0 {
+            let n = tag >> 1;
+            if n > remaining || n > payload.len() as u64 {
+                return Err(corrupt());
+            }
+            out.extend_from_slice(&payload[..n as usize]);
+            payload = &payload[n as usize..];
+        } else {
+            let n = tag >> 1;
+            let distance = varint::read(&mut payload)?;
+            if n.saturating_add(MIN_MATCH as u64) > remaining
+                || distance == 0
+                || distance > out.len() as u64
+            {
+                return Err(corrupt());
+            }
+            // Copies may overlap what they produce, e.g., a distance of 1
+            // repeats the last byte, so this goes a byte at a time.
+            let start = out.len() - distance as usize;
+            for i in 0..n as usize + MIN_MATCH {
+                let b = out[start + i];
+                out.push(b);
+            }
+        }
+    }
+    if out.len() as u64 != len {
+        return Err(corrupt());
+    }
+    Ok(())
+}
+
+fn write_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    if !literals.is_empty() {
+        varint::write(out, (literals.len() as u64) << 1);
+        out.extend_from_slice(literals);
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let n = bytes.iter().fold(0u32, |n, &b| (n << 8) | b as u32);
+    (n.wrapping_mul(0x9E3779B1) >> (32 - HASH_BITS)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::{FstKv, FstKvBuilder, MIN_COMPRESS, compress, decompress};
+
+    static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+    /// A directory in the temporary directory that's removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            let name = format!("transducers-kv-{}-{}", process::id(),
+                               NEXT_DIR.fetch_add(1, Ordering::SeqCst));
+            TempDir(env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Values covering every path through the compressor.
+    fn values() -> Vec<Vec<u8>> {
+        let mut values = vec![
+            vec![],
+            b"a".to_vec(),
+            b"abc".to_vec(),
+            b"abcd".to_vec(),
+            // Too short to compress, but with a repeat.
+            b"abcdabcd".to_vec(),
+            vec![b'x'; MIN_COMPRESS - 1],
+            vec![b'x'; MIN_COMPRESS],
+            // A copy at a distance of 1, which overlaps its own output.
+            vec![b'z'; 1000],
+            b"abab".iter().cycle().take(999).cloned().collect(),
+            b"https://example.com/a/b/c?q=1 https://example.com/a/b/d?q=2"
+                .to_vec(),
+        ];
+        // Bytes that don't repeat, which don't get smaller.
+        let mut x = 0x9E37_79B9u32;
+        values.push((0..500).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        }).collect());
+        // A repeat that's further back than the last time its hash was seen.
+        let mut v = b"0123456789".to_vec();
+        v.extend_from_slice(&[b'-'; 100]);
+        v.extend_from_slice(b"0123456789");
+        values.push(v);
+        values
+    }
+
+    #[test]
+    fn roundtrip() {
+        let (mut compressed, mut out) = (vec![], vec![]);
+        for value in values() {
+            compress(&value, &mut compressed);
+            decompress(&compressed, &mut out).unwrap();
+            assert_eq!(out, value);
+        }
+    }
+
+    #[test]
+    fn overlapping_copy() {
+        let mut compressed = vec![];
+        compress(&[b'z'; 1000], &mut compressed);
+        // The length, a literal `z`, and one copy of the rest.
+        assert!(compressed.len() < 10, "{:?}", compressed);
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut out = vec![];
+        // A copy before the start of the output, a copy at distance 0, a
+        // literal longer than the payload, and a length that doesn't match.
+        assert!(decompress(&[8, 1, 1], &mut out).is_err());
+        assert!(decompress(&[8, 2, b'a', 1, 0], &mut out).is_err());
+        assert!(decompress(&[8, 10, b'a'], &mut out).is_err());
+        assert!(decompress(&[8, 2, b'a'], &mut out).is_err());
+        // A copy or a literal past the length.
+        assert!(decompress(&[2, 2, b'a', 1, 1], &mut out).is_err());
+        assert!(decompress(&[1, 4, b'a', b'b'], &mut out).is_err());
+        // A truncated varint.
+        assert!(decompress(&[0x80], &mut out).is_err());
+
+        // Every truncation and every change to a single byte of a
+        // compressed value either decompresses or fails, without panicking.
+        let mut compressed = vec![];
+        for value in values() {
+            compress(&value, &mut compressed);
+            for i in 0..compressed.len() {
+                let _ = decompress(&compressed[..i], &mut out);
+                for &b in &[0x00, 0x01, 0x7f, 0xff] {
+                    let mut corrupt = compressed.clone();
+                    corrupt[i] = b;
+                    let _ = decompress(&corrupt, &mut out);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn store() {
+        for &compressed in &[false, true] {
+            let dir = TempDir::new();
+            let values = values();
+            let keys: Vec<String> =
+                (0..values.len()).map(|i| format!("key{:02}", i)).collect();
+            let mut builder = FstKvBuilder::new(&dir.0).unwrap();
+            builder.compress(compressed);
+            for (key, value) in keys.iter().zip(&values) {
+                builder.insert(key, value).unwrap();
+            }
+            builder.finish().unwrap();
+
+            let kv = FstKv::open(&dir.0).unwrap();
+            assert_eq!(kv.len(), values.len());
+            for (key, value) in keys.iter().zip(&values) {
+                assert_eq!(&*kv.get(key).unwrap().unwrap(), &value[..]);
+            }
+            assert!(kv.get("nope").unwrap().is_none());
+
+            let mut stream = kv.range().into_stream();
+            let mut i = 0;
+            while let Some((key, value)) = stream.next().unwrap() {
+                assert_eq!(key, keys[i].as_bytes());
+                assert_eq!(value, &values[i][..]);
+                i += 1;
+            }
+            assert_eq!(i, values.len());
+        }
+    }
+}
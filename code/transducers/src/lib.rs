@@ -0,0 +1,125 @@
+/*!
+Support code for the command line tools that accompany the "Index 1,600,000,000
+Keys with Automata and Rust" blog post.
+
+The programs in `src/bin` that were extracted from the post are standalone
+examples. The hand written tools (like `fst-index`) share the code in this
+crate.
+*/
+
+extern crate fst;
+extern crate fst_levenshtein;
+extern crate fst_regex;
+extern crate unicode_normalization;
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+
+use fst::{Automaton, Map, Set};
+use fst::raw::{CompiledAddr, Fst};
+
+pub mod args;
+pub mod autocomplete;
+pub mod cached;
+pub mod combinators;
+pub mod combine;
+pub mod corpus;
+pub mod damerau;
+pub mod diff;
+pub mod dot;
+pub mod extsort;
+pub mod glob;
+pub mod integrity;
+pub mod inverted;
+pub mod kv;
+pub mod normalize;
+pub mod ordinal;
+pub mod page;
+pub mod query;
+pub mod rank;
+pub mod reverse;
+pub mod shard;
+pub mod stats;
+pub mod subsequence;
+mod bounds;
+mod dfa;
+mod topk;
+mod utf8;
+mod varint;
+
+/// The error type used by the tools in this crate.
+pub type Result<T> = ::std::result::Result<T, Box<Error + Send + Sync>>;
+
+/// Open the set stored in the file at the given path.
+///
+/// The file is memory mapped. Callers must not modify the file while the set
+/// is in use. If the file was sealed with `integrity::seal`, its header is
+/// checked first.
+pub fn open_set<P: AsRef<Path>>(path: P) -> Result<Set> {
+    if integrity::is_sealed(&path)? {
+        return Ok(integrity::open_set(path)?.1);
+    }
+    Ok(unsafe { Set::from_path(path)? })
+}
+
+/// Open the map stored in the file at the given path.
+///
+/// Since a set is just a map where every value is `0`, this can open sets
+/// too. The file is memory mapped. Callers must not modify the file while the
+/// map is in use. As with `open_set`, sealed files are checked first.
+pub fn open_map<P: AsRef<Path>>(path: P) -> Result<Map> {
+    if integrity::is_sealed(&path)? {
+        return Ok(integrity::open_map(path)?.1);
+    }
+    Ok(unsafe { Map::from_path(path)? })
+}
+
+/// Returns the smallest key greater than every key starting with `prefix`,
+/// or `None` if there is no such key (i.e., the prefix is empty or all
+/// `0xFF` bytes).
+pub fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xFF {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// Returns true if `aut` matches `key`, by running it over the key's bytes.
+///
+/// This tests one key without searching an FST, e.g., to filter keys that
+/// came from somewhere else.
+pub fn matches<A: Automaton>(aut: &A, key: &[u8]) -> bool {
+    let mut state = aut.start();
+    for &b in key {
+        if !aut.can_match(&state) {
+            return false;
+        }
+        state = aut.accept(&state, b);
+    }
+    aut.is_match(&state)
+}
+
+/// Returns the address of every state in the FST, in the order they are
+/// first visited by a depth first traversal from the root that follows
+/// transitions in lexicographic order.
+pub fn states(fst: &Fst) -> Vec<CompiledAddr> {
+    let mut seen = HashSet::new();
+    let mut order = vec![];
+    let mut stack = vec![fst.root().addr()];
+    while let Some(addr) = stack.pop() {
+        if !seen.insert(addr) {
+            continue;
+        }
+        order.push(addr);
+        // Push in reverse so that the smallest transition is visited first.
+        let children: Vec<_> =
+            fst.node(addr).transitions().map(|t| t.addr).collect();
+        stack.extend(children.into_iter().rev());
+    }
+    order
+}
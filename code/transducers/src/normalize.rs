@@ -0,0 +1,318 @@
+/*!
+Searching an FST as if its keys were case folded and Unicode normalized.
+
+The automata in `fst_levenshtein` and `fst_regex` compare bytes, so `Bruce`,
+`bruce` and `BRUCE` are different keys, and so are `é` written as one
+codepoint (U+00E9) and as `e` followed by a combining acute accent (U+0301).
+Rebuilding the FST with normalized keys fixes that, but loses the original
+keys.
+
+`Normalized` instead wraps any automaton and normalizes the keys of the FST on
+the fly while it is being searched. Each key is decoded, decomposed (NFD, or
+NFKD for compatibility equivalence), optionally case folded, and only then
+given to the wrapped automaton. Two strings are canonically equivalent
+exactly when their decompositions are equal, so this matches keys under NFC
+(or NFKC) equivalence too.
+
+The wrapped automaton must be built from a query in the same normal form,
+which `Normalizer::normalize` produces. For example, with
+`let norm = Normalizer::new()`, the automaton
+`norm.automaton(Levenshtein::new(&norm.normalize("Bruce"), 1)?)` matches
+`bruce`, `BRUCE` and `Bruse`.
+
+Regular expressions need a little care: the pattern itself can't be run
+through `normalize` (that would turn `\PL` into `\pl`), so write its literal
+parts in lowercase and avoid precomposed accented characters.
+*/
+
+use fst::Automaton;
+use unicode_normalization::char::{
+    canonical_combining_class, decompose_canonical, decompose_compatible,
+};
+
+use utf8::{Decoded, Decoder};
+
+/// Describes how keys and queries are normalized.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Normalizer {
+    case_fold: bool,
+    compatibility: bool,
+}
+
+impl Normalizer {
+    /// Create a normalizer that applies canonical decomposition and simple
+    /// case folding.
+    pub fn new() -> Normalizer {
+        Normalizer { case_fold: true, compatibility: false }
+    }
+
+    /// Whether to apply simple case folding. Enabled by default.
+    pub fn case_fold(mut self, yes: bool) -> Normalizer {
+        self.case_fold = yes;
+        self
+    }
+
+    /// Whether to use compatibility decomposition (NFKD) instead of
+    /// canonical decomposition (NFD). This makes, e.g., `ﬁ` equivalent to
+    /// `fi`. Disabled by default.
+    pub fn compatibility(mut self, yes: bool) -> Normalizer {
+        self.compatibility = yes;
+        self
+    }
+
+    /// Normalize the given string. This is what keys look like to an
+    /// automaton wrapped by `Normalizer::automaton`.
+    pub fn normalize(&self, s: &str) -> String {
+        let mut out = String::with_capacity(s.len());
+        let mut marks = vec![];
+        for c in s.chars() {
+            self.decompose(c, &mut |d| {
+                if canonical_combining_class(d) == 0 {
+                    flush(&mut marks, |m| out.push(m));
+                    out.push(d);
+                } else {
+                    marks.push(d);
+                }
+            });
+        }
+        flush(&mut marks, |m| out.push(m));
+        out
+    }
+
+    /// Wrap an automaton so that it sees every key of the FST in normalized
+    /// form.
+    pub fn automaton<A: Automaton>(&self, aut: A) -> Normalized<A> {
+        Normalized { aut: aut, norm: *self }
+    }
+
+    /// Decompose a single codepoint, case folding it along the way if
+    /// enabled.
+    ///
+    /// Case folding can produce a codepoint that decomposes further, so this
+    /// computes `NFD(fold(NFD(c)))`, which is what Unicode recommends for
+    /// caseless matching.
+    fn decompose<F: FnMut(char)>(&self, c: char, emit: &mut F) {
+        if !self.case_fold {
+            return self.decompose_one(c, emit);
+        }
+        self.decompose_one(c, &mut |d| {
+            self.decompose_one(simple_fold(d), emit)
+        });
+    }
+
+    fn decompose_one<F: FnMut(char)>(&self, c: char, emit: &mut F) {
+        if self.compatibility {
+            decompose_compatible(c, |d| emit(d));
+        } else {
+            decompose_canonical(c, |d| emit(d));
+        }
+    }
+}
+
+impl Default for Normalizer {
+    fn default() -> Normalizer {
+        Normalizer::new()
+    }
+}
+
+/// An automaton that runs `A` on the normalized form of each key.
+#[derive(Clone, Debug)]
+pub struct Normalized<A> {
+    aut: A,
+    norm: Normalizer,
+}
+
+/// The state of a `Normalized` automaton.
+#[derive(Clone, Debug)]
+pub struct NormalizedState<S> {
+    /// The state of the wrapped automaton, or `None` if the key isn't valid
+    /// UTF-8.
+    inner: Option<S>,
+    /// The partially decoded codepoint, if any.
+    dec: Decoder,
+    /// Combining marks that haven't been given to the wrapped automaton yet,
+    /// because a later mark may need to be ordered before them.
+    marks: Vec<char>,
+}
+
+impl<A: Automaton> Normalized<A> {
+    /// Give the UTF-8 encoding of `c` to the wrapped automaton.
+    fn feed(&self, state: &mut A::State, c: char) {
+        let mut buf = [0; 4];
+        for &b in c.encode_utf8(&mut buf).as_bytes() {
+            *state = self.aut.accept(state, b);
+        }
+    }
+
+    /// Give all pending combining marks, in canonical order, to the wrapped
+    /// automaton.
+    fn feed_marks(&self, state: &mut A::State, marks: &mut Vec<char>) {
+        flush(marks, |m| self.feed(state, m));
+    }
+}
+
+impl<A> Automaton for Normalized<A>
+where A: Automaton, A::State: Clone {
+    type State = NormalizedState<A::State>;
+
+    fn start(&self) -> NormalizedState<A::State> {
+        NormalizedState {
+            inner: Some(self.aut.start()),
+            dec: Decoder::default(),
+            marks: vec![],
+        }
+    }
+
+    fn is_match(&self, state: &NormalizedState<A::State>) -> bool {
+        let inner = match state.inner {
+            Some(ref inner) if state.dec.is_boundary() => inner,
+            _ => return false,
+        };
+        if state.marks.is_empty() {
+            return self.aut.is_match(inner);
+        }
+        // The key might end here, so ask the wrapped automaton what it
+        // thinks after the pending marks.
+        let mut inner = inner.clone();
+        self.feed_marks(&mut inner, &mut state.marks.clone());
+        self.aut.is_match(&inner)
+    }
+
+    fn can_match(&self, state: &NormalizedState<A::State>) -> bool {
+        state.inner.as_ref().map_or(false, |s| self.aut.can_match(s))
+    }
+
+    fn accept(
+        &self,
+        state: &NormalizedState<A::State>,
+        byte: u8,
+    ) -> NormalizedState<A::State> {
+        let mut next = NormalizedState {
+            inner: None,
+            dec: Decoder::default(),
+            marks: vec![],
+        };
+        let mut inner = match state.inner {
+            None => return next,
+            Some(ref inner) => inner.clone(),
+        };
+        next.marks = state.marks.clone();
+        match state.dec.push(byte) {
+            Decoded::Invalid => return next,
+            Decoded::Incomplete(dec) => next.dec = dec,
+            Decoded::Char(c) => {
+                let marks = &mut next.marks;
+                self.norm.decompose(c, &mut |d| {
+                    if canonical_combining_class(d) == 0 {
+                        self.feed_marks(&mut inner, marks);
+                        self.feed(&mut inner, d);
+                    } else {
+                        marks.push(d);
+                    }
+                });
+            }
+        }
+        next.inner = Some(inner);
+        next
+    }
+}
+
+/// Emit the given combining marks in canonical order (i.e., stably sorted by
+/// their canonical combining class), leaving `marks` empty.
+fn flush<F: FnMut(char)>(marks: &mut Vec<char>, mut emit: F) {
+    marks.sort_by_key(|&m| canonical_combining_class(m));
+    for m in marks.drain(..) {
+        emit(m);
+    }
+}
+
+/// Map a codepoint to its simple case folding.
+///
+/// The standard library doesn't expose the case folding tables, so this uses
+/// the lowercase mapping whenever it's a single codepoint. The exceptions
+/// below are every codepoint where that disagrees with the simple (status C
+/// and S) mappings in CaseFolding.txt for Unicode 14.0, found by comparing
+/// the two for every codepoint. Codepoints added in later versions of Unicode
+/// fall back to their lowercase mapping.
+pub fn simple_fold(c: char) -> char {
+    match c {
+        '\u{B5}' => '\u{3BC}',   // MICRO SIGN
+        '\u{17F}' => 's',         // LATIN SMALL LETTER LONG S
+        '\u{345}' => '\u{3B9}',  // COMBINING GREEK YPOGEGRAMMENI
+        '\u{3C2}' => '\u{3C3}',  // GREEK SMALL LETTER FINAL SIGMA
+        '\u{3D0}' => '\u{3B2}',  // GREEK BETA SYMBOL
+        '\u{3D1}' => '\u{3B8}',  // GREEK THETA SYMBOL
+        '\u{3D5}' => '\u{3C6}',  // GREEK PHI SYMBOL
+        '\u{3D6}' => '\u{3C0}',  // GREEK PI SYMBOL
+        '\u{3F0}' => '\u{3BA}',  // GREEK KAPPA SYMBOL
+        '\u{3F1}' => '\u{3C1}',  // GREEK RHO SYMBOL
+        '\u{3F5}' => '\u{3B5}',  // GREEK LUNATE EPSILON SYMBOL
+        // Cherokee folds to its capital letters, which were encoded first,
+        // so the capitals fold to themselves and the small letters to them.
+        '\u{13A0}'..='\u{13F5}' => c,
+        '\u{13F8}'..='\u{13FD}' => shift(c, 0x13F8, 0x13F0),
+        '\u{AB70}'..='\u{ABBF}' => shift(c, 0xAB70, 0x13A0),
+        '\u{1C80}' => '\u{432}',  // CYRILLIC SMALL LETTER ROUNDED VE
+        '\u{1C81}' => '\u{434}',  // CYRILLIC SMALL LETTER LONG-LEGGED DE
+        '\u{1C82}' => '\u{43E}',  // CYRILLIC SMALL LETTER NARROW O
+        '\u{1C83}' => '\u{441}',  // CYRILLIC SMALL LETTER WIDE ES
+        '\u{1C84}' => '\u{442}',  // CYRILLIC SMALL LETTER TALL TE
+        '\u{1C85}' => '\u{442}',  // CYRILLIC SMALL LETTER THREE-LEGGED TE
+        '\u{1C86}' => '\u{44A}',  // CYRILLIC SMALL LETTER TALL HARD SIGN
+        '\u{1C87}' => '\u{463}',  // CYRILLIC SMALL LETTER TALL YAT
+        '\u{1C88}' => '\u{A64B}', // CYRILLIC SMALL LETTER UNBLENDED UK
+        '\u{1E9B}' => '\u{1E61}', // LATIN SMALL LETTER LONG S WITH DOT ABOVE
+        '\u{1FBE}' => '\u{3B9}',  // GREEK PROSGEGRAMMENI
+        _ => {
+            let mut lower = c.to_lowercase();
+            match (lower.next(), lower.next()) {
+                (Some(l), None) => l,
+                _ => c,
+            }
+        }
+    }
+}
+
+/// Map `c`, which is in a block of letters starting at `from`, to the letter
+/// at the same position in the block starting at `to`.
+fn shift(c: char, from: u32, to: u32) -> char {
+    ::std::char::from_u32(c as u32 - from + to).expect("a shifted letter")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Normalizer, simple_fold};
+
+    #[test]
+    fn fold_exceptions() {
+        let norm = Normalizer::new();
+        // MICRO SIGN and GREEK SMALL LETTER MU.
+        assert_eq!(norm.normalize("\u{B5}m"), norm.normalize("\u{3BC}m"));
+        // LATIN SMALL LETTER LONG S.
+        assert_eq!(norm.normalize("\u{17F}t"), "st");
+        // Final and medial sigma.
+        assert_eq!(norm.normalize("ΣΟΦΟΣ"), norm.normalize("σοφος"));
+        // CYRILLIC SMALL LETTER TALL TE.
+        assert_eq!(norm.normalize("\u{1C84}"), "т");
+    }
+
+    #[test]
+    fn cherokee() {
+        // CHEROKEE LETTER A, its small form, and the last letters with
+        // small forms outside of the main block.
+        assert_eq!(simple_fold('\u{13A0}'), '\u{13A0}');
+        assert_eq!(simple_fold('\u{AB70}'), '\u{13A0}');
+        assert_eq!(simple_fold('\u{ABBF}'), '\u{13EF}');
+        assert_eq!(simple_fold('\u{13F5}'), '\u{13F5}');
+        assert_eq!(simple_fold('\u{13FD}'), '\u{13F5}');
+    }
+
+    #[test]
+    fn idempotent() {
+        // Folding a folded codepoint doesn't change it.
+        for c in (0..0x11_0000).filter_map(::std::char::from_u32) {
+            let folded = simple_fold(c);
+            assert_eq!(simple_fold(folded), folded, "{:?}", c);
+        }
+    }
+}
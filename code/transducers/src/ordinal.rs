@@ -0,0 +1,280 @@
+/*!
+Maps whose values increase with their keys, which can be searched by value.
+
+A map answers "what is the value of this key?" by following the key's bytes
+from the root and adding up the outputs along the way. If the values increase
+with the keys, as with the IDs in a term dictionary, the map can also answer
+the reverse question, "which key has this value?", by letting the outputs
+steer instead.
+
+The builder pushes the common part of the values of neighbouring keys as
+close to the root as possible, and for increasing values, the common part is
+just the smallest value. So the smallest key below any transition has the sum
+of the outputs leading to it as its value, and the rest of the keys below it
+have larger values. To find the key with a given value, start at the root
+and, at each state, stop if the state is final and its output gives the
+value, or take the last transition whose output doesn't overshoot it. That
+takes time proportional to the length of the key found.
+
+`OrdinalMapBuilder` checks that values increase as keys are inserted, since a
+map that breaks the rule would silently give wrong answers.
+*/
+
+use std::io;
+use std::path::Path;
+
+use fst::{Map, MapBuilder};
+use fst::raw::{Node, Transition};
+
+use {Result, open_map};
+
+/// Builds an `OrdinalMap`.
+pub struct OrdinalMapBuilder<W> {
+    map: MapBuilder<W>,
+    last: Option<u64>,
+}
+
+impl OrdinalMapBuilder<Vec<u8>> {
+    /// Create a builder that writes the map to memory.
+    pub fn memory() -> OrdinalMapBuilder<Vec<u8>> {
+        OrdinalMapBuilder { map: MapBuilder::memory(), last: None }
+    }
+}
+
+impl<W: io::Write> OrdinalMapBuilder<W> {
+    /// Create a builder that writes the map to `wtr`.
+    pub fn new(wtr: W) -> Result<OrdinalMapBuilder<W>> {
+        Ok(OrdinalMapBuilder { map: MapBuilder::new(wtr)?, last: None })
+    }
+
+    /// Insert a key and its value. As with `MapBuilder`, keys must be
+    /// inserted in lexicographic order, and each value must be greater than
+    /// the one before it.
+    pub fn insert<K: AsRef<[u8]>>(&mut self, key: K, value: u64) -> Result<()> {
+        if let Some(last) = self.last {
+            if value <= last {
+                return Err(From::from(format!(
+                    "values must increase, but {} follows {}", value, last)));
+            }
+        }
+        self.map.insert(key, value)?;
+        self.last = Some(value);
+        Ok(())
+    }
+
+    /// Finish writing the map.
+    pub fn finish(self) -> Result<()> {
+        self.map.finish()?;
+        Ok(())
+    }
+
+    /// Finish writing the map and return the underlying writer.
+    pub fn into_inner(self) -> Result<W> {
+        Ok(self.map.into_inner()?)
+    }
+}
+
+/// A map whose values increase with its keys.
+pub struct OrdinalMap {
+    map: Map,
+}
+
+impl OrdinalMap {
+    /// Open the map in the file at `path`, which must have been written by
+    /// an `OrdinalMapBuilder` (or otherwise have values that increase with
+    /// its keys). The file is memory mapped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<OrdinalMap> {
+        Ok(OrdinalMap::from_map(open_map(path)?))
+    }
+
+    /// Read a map written to memory by an `OrdinalMapBuilder`.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<OrdinalMap> {
+        Ok(OrdinalMap::from_map(Map::from_bytes(bytes)?))
+    }
+
+    /// Use a map as an ordinal map. Its values must increase with its keys,
+    /// which isn't checked, or the results of `key` and `ceil` are
+    /// meaningless.
+    pub fn from_map(map: Map) -> OrdinalMap {
+        OrdinalMap { map: map }
+    }
+
+    /// The underlying map.
+    pub fn as_map(&self) -> &Map {
+        &self.map
+    }
+
+    /// The number of keys in the map.
+    pub fn len(&self) -> u64 {
+        self.map.len() as u64
+    }
+
+    /// Returns true if the map has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the value of `key`, if it's in the map.
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<u64> {
+        self.map.get(key)
+    }
+
+    /// Returns the key whose value is `value`, if there is one.
+    pub fn key(&self, value: u64) -> Option<Vec<u8>> {
+        let fst = self.map.as_fst();
+        let mut node = fst.root();
+        let mut out = 0;
+        let mut key = vec![];
+        loop {
+            if node.is_final() && out + node.final_output().value() == value {
+                return Some(key);
+            }
+            let i = partition_point(&node, |t| out + t.out.value() <= value);
+            if i == 0 {
+                return None;
+            }
+            let t = node.transition(i - 1);
+            out += t.out.value();
+            key.push(t.inp);
+            node = fst.node(t.addr);
+        }
+    }
+
+    /// Returns the value of the smallest key greater than or equal to `key`,
+    /// or `None` if every key is less than `key`.
+    pub fn ceil<K: AsRef<[u8]>>(&self, key: K) -> Option<u64> {
+        let fst = self.map.as_fst();
+        let mut node = fst.root();
+        let mut out = 0;
+        // The value of the first key after every key below `node`.
+        let mut after = None;
+        for &b in key.as_ref() {
+            let i = partition_point(&node, |t| t.inp < b);
+            if i < node.len() && node.transition(i).inp == b {
+                if i + 1 < node.len() {
+                    after = Some(out + node.transition(i + 1).out.value());
+                }
+                let t = node.transition(i);
+                out += t.out.value();
+                node = fst.node(t.addr);
+            } else if i < node.len() {
+                // The smallest key greater than `key` starts with the next
+                // transition.
+                return Some(out + node.transition(i).out.value());
+            } else {
+                return after;
+            }
+        }
+        // Every key below here starts with `key`, so the smallest is the
+        // first key not less than it. There's at least one unless the map is
+        // empty.
+        if node.is_final() {
+            Some(out + node.final_output().value())
+        } else if node.len() > 0 {
+            Some(out + node.transition(0).out.value())
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the number of transitions out of `node` for which `pred` is
+/// true, assuming it's true for every transition before the first one for
+/// which it's false.
+fn partition_point<F>(node: &Node, pred: F) -> usize
+where F: Fn(Transition) -> bool {
+    let (mut lo, mut hi) = (0, node.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if pred(node.transition(mid)) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::{OrdinalMap, OrdinalMapBuilder};
+
+    /// A xorshift generator, so the tests are random but repeatable.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn key(&mut self) -> Vec<u8> {
+            let len = self.next_u64() % 6;
+            (0..len).map(|_| b"abc"[self.next_u64() as usize % 3]).collect()
+        }
+    }
+
+    /// Returns sorted keys with increasing values, which have gaps between
+    /// them, and the map built from them.
+    fn build(rng: &mut Rng, n: usize) -> (Vec<(Vec<u8>, u64)>, OrdinalMap) {
+        let keys: BTreeSet<Vec<u8>> = (0..n).map(|_| rng.key()).collect();
+        let mut value = rng.next_u64() % 3;
+        let mut entries = vec![];
+        for key in keys {
+            entries.push((key, value));
+            value += 1 + rng.next_u64() % 4;
+        }
+        let mut builder = OrdinalMapBuilder::memory();
+        for &(ref key, value) in &entries {
+            builder.insert(key, value).unwrap();
+        }
+        let map = OrdinalMap::from_bytes(builder.into_inner().unwrap());
+        (entries, map.unwrap())
+    }
+
+    #[test]
+    fn random() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for &n in &[0, 1, 2, 5, 20, 100, 400] {
+            let (entries, map) = build(&mut rng, n);
+            assert_eq!(map.len(), entries.len() as u64);
+
+            let last = entries.last().map_or(0, |&(_, v)| v);
+            for value in 0..last + 3 {
+                let expected = entries.iter()
+                    .find(|&&(_, v)| v == value)
+                    .map(|&(ref k, _)| k.clone());
+                assert_eq!(map.key(value), expected, "value {}", value);
+            }
+
+            // Every key, the empty key and random keys, most of which fall
+            // between the keys in the map.
+            let mut probes: Vec<Vec<u8>> =
+                entries.iter().map(|&(ref k, _)| k.clone()).collect();
+            probes.push(vec![]);
+            probes.push(b"d".to_vec());
+            probes.extend((0..200).map(|_| rng.key()));
+            for probe in &probes {
+                let ceil = entries.iter()
+                    .find(|&&(ref k, _)| k >= probe)
+                    .map(|&(_, v)| v);
+                assert_eq!(map.ceil(probe), ceil, "ceil of {:?}", probe);
+                let get = entries.iter()
+                    .find(|&&(ref k, _)| k == probe)
+                    .map(|&(_, v)| v);
+                assert_eq!(map.get(probe), get, "get of {:?}", probe);
+            }
+        }
+    }
+
+    #[test]
+    fn values_must_increase() {
+        let mut builder = OrdinalMapBuilder::memory();
+        builder.insert("a", 1).unwrap();
+        assert!(builder.insert("b", 1).is_err());
+    }
+}
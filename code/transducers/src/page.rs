@@ -0,0 +1,249 @@
+/*!
+Paginated searches that resume from an opaque cursor.
+
+An API that returns "the next 100 keys matching `\pL+`" can't keep a stream
+open between requests, and skipping the first `n` matches of a fresh search
+on every request makes page `n` cost as much as every page before it.
+Instead, each `Page` comes with a `Cursor` that records the last key on the
+page. The next page is a fresh search with the cursor as an exclusive lower
+bound, which the FST turns into a seek straight to the first key after it:
+the cost of a page depends on its size, not on how far into the results it
+is.
+
+Cursors are printed as hex, so they can be put in a URL or a JSON document
+as is. They're opaque to clients but not secret or signed: a client can make
+up a cursor, which just starts the next page from some other key. A cursor
+never lets a search escape its own bounds.
+
+Results are consistent across pages as long as the FST doesn't change. If it
+is rebuilt between requests, a cursor still works, and continues after its
+key in the new FST.
+*/
+
+use std::fmt;
+use std::str::FromStr;
+
+use fst::{Automaton, Streamer};
+use fst::automaton::AlwaysMatch;
+use fst::raw::Fst;
+
+use Result;
+use bounds::Bounds;
+
+/// The default number of keys on a page.
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Where the next page of a search starts.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Cursor {
+    /// The last key on the previous page.
+    after: Vec<u8>,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in &self.after {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Box<::std::error::Error + Send + Sync>;
+
+    fn from_str(s: &str) -> Result<Cursor> {
+        let invalid = || format!("invalid cursor {:?}", s);
+        // `from_str_radix` would also accept a sign.
+        if s.len() % 2 != 0 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(From::from(invalid()));
+        }
+        let mut after = Vec::with_capacity(s.len() / 2);
+        for i in (0..s.len()).step_by(2) {
+            let b = u8::from_str_radix(&s[i..i + 2], 16)
+                .map_err(|_| invalid())?;
+            after.push(b);
+        }
+        Ok(Cursor { after: after })
+    }
+}
+
+/// One page of the results of a search.
+#[derive(Clone, Debug)]
+pub struct Page {
+    /// The keys on the page, in order, with their values. (For sets, every
+    /// value is `0`.)
+    pub entries: Vec<(Vec<u8>, u64)>,
+    /// Where the next page starts, or `None` if this is the last page.
+    pub next: Option<Cursor>,
+}
+
+/// A search or range query that's read a page at a time.
+///
+/// The same `Paginator` can serve every page of the query, for any number of
+/// clients, since all of the state of a client's progress is in its cursor.
+pub struct Paginator<A> {
+    aut: A,
+    bounds: Bounds,
+    page_size: usize,
+}
+
+impl Paginator<AlwaysMatch> {
+    /// Paginate every key, or every key in a range once bounds are set.
+    pub fn range() -> Paginator<AlwaysMatch> {
+        Paginator::search(AlwaysMatch)
+    }
+}
+
+impl<A: Automaton> Paginator<A> {
+    /// Paginate the keys matching `aut`.
+    pub fn search(aut: A) -> Paginator<A> {
+        Paginator {
+            aut: aut,
+            bounds: Bounds::new(),
+            page_size: DEFAULT_PAGE_SIZE,
+        }
+    }
+
+    /// Only include keys greater than or equal to `bound`.
+    pub fn ge<K: AsRef<[u8]>>(mut self, bound: K) -> Paginator<A> {
+        self.bounds.ge(bound.as_ref());
+        self
+    }
+
+    /// Only include keys greater than `bound`.
+    pub fn gt<K: AsRef<[u8]>>(mut self, bound: K) -> Paginator<A> {
+        self.bounds.gt(bound.as_ref());
+        self
+    }
+
+    /// Only include keys less than or equal to `bound`.
+    pub fn le<K: AsRef<[u8]>>(mut self, bound: K) -> Paginator<A> {
+        self.bounds.le(bound.as_ref());
+        self
+    }
+
+    /// Only include keys less than `bound`.
+    pub fn lt<K: AsRef<[u8]>>(mut self, bound: K) -> Paginator<A> {
+        self.bounds.lt(bound.as_ref());
+        self
+    }
+
+    /// Set the number of keys on each page. The default is 100. A page size
+    /// of 0 is treated as 1.
+    pub fn page_size(mut self, n: usize) -> Paginator<A> {
+        self.page_size = if n == 0 { 1 } else { n };
+        self
+    }
+
+    /// Returns the page that starts at `cursor` in `fst`, or the first page
+    /// if `cursor` is `None`. Sets and maps can be paginated through
+    /// `Set::as_fst` and `Map::as_fst`.
+    pub fn page(&self, fst: &Fst, cursor: Option<&Cursor>) -> Page {
+        let after = cursor.map(|cursor| &*cursor.after);
+        let mut stream = self.bounds.search(fst, &self.aut, after);
+        let mut entries: Vec<(Vec<u8>, u64)> = vec![];
+        while let Some((key, out)) = stream.next() {
+            if entries.len() == self.page_size {
+                // There's at least one more key, so there's a next page.
+                let after = entries[entries.len() - 1].0.clone();
+                let next = Some(Cursor { after: after });
+                return Page { entries: entries, next: next };
+            }
+            entries.push((key.to_vec(), out.value()));
+        }
+        Page { entries: entries, next: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fst::Set;
+    use fst::automaton::AlwaysMatch;
+
+    use super::{Cursor, Page, Paginator};
+
+    fn set() -> Set {
+        let keys = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"];
+        Set::from_iter(&keys).unwrap()
+    }
+
+    fn keys(page: &Page) -> String {
+        page.entries.iter()
+            .map(|&(ref key, _)| String::from_utf8(key.clone()).unwrap())
+            .collect()
+    }
+
+    fn cursor(key: &str) -> Cursor {
+        Cursor { after: key.as_bytes().to_vec() }
+    }
+
+    /// Every page of `paginator`, starting after `start`.
+    fn pages(
+        paginator: &Paginator<AlwaysMatch>,
+        start: Option<&str>,
+    ) -> Vec<String> {
+        let set = set();
+        let mut cursor = start.map(cursor);
+        let mut pages = vec![];
+        loop {
+            let page = paginator.page(set.as_fst(), cursor.as_ref());
+            pages.push(keys(&page));
+            match page.next {
+                None => return pages,
+                Some(next) => cursor = Some(next),
+            }
+        }
+    }
+
+    #[test]
+    fn pages_in_order() {
+        let paginator = Paginator::range().page_size(3);
+        assert_eq!(pages(&paginator, None), ["abc", "def", "ghi", "j"]);
+        // When the last page is exactly full, there's no next page.
+        let paginator = Paginator::range().page_size(5);
+        assert_eq!(pages(&paginator, None), ["abcde", "fghij"]);
+        let paginator = Paginator::range().page_size(10);
+        assert_eq!(pages(&paginator, None), ["abcdefghij"]);
+        let paginator = Paginator::range().ge("c").le("f").page_size(2);
+        assert_eq!(pages(&paginator, None), ["cd", "ef"]);
+        let paginator = Paginator::range().gt("c").lt("f").page_size(2);
+        assert_eq!(pages(&paginator, None), ["de"]);
+    }
+
+    #[test]
+    fn cursor_within_bounds() {
+        // A cursor before the lower bound doesn't escape it.
+        let paginator = Paginator::range().ge("e").page_size(2);
+        assert_eq!(pages(&paginator, Some("a")), ["ef", "gh", "ij"]);
+        assert_eq!(pages(&paginator, Some("")), ["ef", "gh", "ij"]);
+        let paginator = Paginator::range().gt("e").page_size(2);
+        assert_eq!(pages(&paginator, Some("a")), ["fg", "hi", "j"]);
+        // A cursor on the lower bound is exclusive either way.
+        assert_eq!(pages(&paginator, Some("e")), ["fg", "hi", "j"]);
+        let paginator = Paginator::range().ge("e").page_size(2);
+        assert_eq!(pages(&paginator, Some("e")), ["fg", "hi", "j"]);
+        // A cursor past the upper bound gives an empty last page.
+        let paginator = Paginator::range().le("c");
+        assert_eq!(pages(&paginator, Some("x")), [""]);
+        let paginator = Paginator::range().lt("c");
+        assert_eq!(pages(&paginator, Some("b")), [""]);
+        // A cursor between keys continues from the next one.
+        let paginator = Paginator::range().ge("b").le("h").page_size(3);
+        assert_eq!(pages(&paginator, Some("cc")), ["def", "gh"]);
+    }
+
+    #[test]
+    fn cursor_strings() {
+        let cursor = Cursor { after: vec![b'a', 0xFF, 0] };
+        assert_eq!(cursor.to_string(), "61ff00");
+        assert_eq!("61ff00".parse::<Cursor>().unwrap(), cursor);
+        assert_eq!("61FF00".parse::<Cursor>().unwrap(), cursor);
+        assert_eq!("".parse::<Cursor>().unwrap(), Cursor { after: vec![] });
+        for bad in &["6", "61f", "zz", "+1", "-1", " 1", "\u{e9}1"] {
+            assert!(bad.parse::<Cursor>().is_err(), "{:?}", bad);
+        }
+        // Two bytes, but not hex.
+        assert!("\u{e9}".parse::<Cursor>().is_err());
+    }
+}
@@ -0,0 +1,917 @@
+/*!
+A boolean query language over set files.
+
+`setop` and `setop-regex` hard code which sets to combine and how. A `Query`
+instead describes the combination as an expression like
+
+```text
+(a.fst | b.fst) & !c.fst & re:"\pL+"
+```
+
+Operands are either set files or *filters*, which select keys by their
+contents:
+
+* `re:"..."` matches keys matching a regular expression.
+* `fuzzy:foo~1` matches keys within one edit of `foo`. The distance defaults
+  to 1 if the `~` is left off.
+* `prefix:br` matches keys starting with `br`.
+* `glob:"*.org"` matches keys matching a shell style glob.
+
+Any operand can be quoted, which is needed if it contains spaces or any of
+the operators. Inside quotes, `\"` is a quote and `\\` a backslash, and any
+other backslash is kept as is, so regexes don't need double escaping.
+
+Operators, from lowest to highest precedence, are `|` (union) and `^`
+(symmetric difference), `&` (intersection) and `!` (complement), and
+parentheses group. Since there's no set of all keys, a complement or filter
+only makes sense when intersected with a set: `a.fst & !c.fst` is the keys
+in `a.fst` but not `c.fst`, and `a.fst & re:"x"` searches `a.fst` with the
+regex.
+
+The whole query compiles into `set::OpBuilder` operations over streams, and
+filters are pushed down to the sets they apply to and combined into a single
+automaton per set, so each set is searched just once. Errors point at the
+offending column of the query.
+*/
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use fst::{Automaton, IntoStreamer, Set, Streamer};
+use fst::set;
+use fst_levenshtein::Levenshtein;
+use fst_regex::Regex;
+
+use open_set;
+use glob::{self, Glob};
+
+/// An error in a query, along with where it is.
+#[derive(Debug)]
+pub struct Error {
+    query: String,
+    column: usize,
+    message: String,
+}
+
+impl Error {
+    /// The column of the query where the error is, starting at 1.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "error at column {}: {}", self.column, self.message)?;
+        writeln!(f, "    {}", self.query)?;
+        write!(f, "    {}^", " ".repeat(self.column - 1))
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+/// A parsed query.
+#[derive(Clone, Debug)]
+pub struct Query {
+    query: String,
+    expr: SetExpr,
+}
+
+/// The sets a query refers to, by file name.
+pub type Sets = HashMap<String, Set>;
+
+impl Query {
+    /// Parse a query.
+    pub fn parse(query: &str) -> Result<Query, Error> {
+        let mut parser = Parser {
+            query: query,
+            chars: query.chars().collect(),
+            pos: 0,
+        };
+        let ast = parser.parse()?;
+        let node = plan(ast).map_err(|(column, msg)| {
+            error(query, column, msg)
+        })?;
+        let expr = node.resolve().map_err(|(column, msg)| {
+            error(query, column, msg)
+        })?;
+        Ok(Query { query: query.to_string(), expr: expr })
+    }
+
+    /// Memory map every set file named in the query.
+    pub fn open_sets(&self) -> Result<Sets, Error> {
+        let mut sets = HashMap::new();
+        let mut files = vec![];
+        self.expr.files(&mut files);
+        for (path, column) in files {
+            if sets.contains_key(path) {
+                continue;
+            }
+            let set = open_set(path).map_err(|err| {
+                self.error(column, format!("{}: {}", path, err))
+            })?;
+            sets.insert(path.to_string(), set);
+        }
+        Ok(sets)
+    }
+
+    /// Run this query against the given sets, which must include every set
+    /// named in the query (see `open_sets`). Keys are streamed in
+    /// lexicographic order.
+    pub fn search<'s>(&self, sets: &'s Sets) -> Result<Keys<'s>, Error> {
+        self.eval(&self.expr, sets)
+    }
+
+    fn eval<'s>(
+        &self,
+        expr: &SetExpr,
+        sets: &'s Sets,
+    ) -> Result<Keys<'s>, Error> {
+        match *expr {
+            SetExpr::File { ref path, column, ref filter } => {
+                let set = match sets.get(path) {
+                    None => {
+                        let msg = format!("{}: set wasn't opened", path);
+                        return Err(self.error(column, msg));
+                    }
+                    Some(set) => set,
+                };
+                Ok(match *filter {
+                    None => Keys(Box::new(set.stream())),
+                    Some(ref filter) => {
+                        let aut = filter.compile().map_err(|(column, msg)| {
+                            self.error(column, msg)
+                        })?;
+                        Keys(Box::new(set.search(aut).into_stream()))
+                    }
+                })
+            }
+            SetExpr::Op(op, ref operands) => {
+                let mut builder = set::OpBuilder::new();
+                for operand in operands {
+                    builder = builder.add(self.eval(operand, sets)?);
+                }
+                Ok(match op {
+                    Op::Union => Keys(Box::new(builder.union())),
+                    Op::Intersection => {
+                        Keys(Box::new(builder.intersection()))
+                    }
+                    Op::Difference => Keys(Box::new(builder.difference())),
+                    Op::SymmetricDifference => {
+                        Keys(Box::new(builder.symmetric_difference()))
+                    }
+                })
+            }
+        }
+    }
+
+    fn error(&self, column: usize, message: String) -> Error {
+        error(&self.query, column, message)
+    }
+}
+
+fn error(query: &str, column: usize, message: String) -> Error {
+    Error { query: query.to_string(), column: column, message: message }
+}
+
+/// The keys matching a query, in lexicographic order.
+pub struct Keys<'s>(Box<for<'a> Streamer<'a, Item=&'a [u8]> + 's>);
+
+impl<'a, 's> Streamer<'a> for Keys<'s> {
+    type Item = &'a [u8];
+
+    fn next(&'a mut self) -> Option<&'a [u8]> {
+        self.0.next()
+    }
+}
+
+/// The parsed form of a query, before deciding how to run it.
+#[derive(Debug)]
+enum Ast {
+    File(String, usize),
+    Filter(FilterExpr, usize),
+    Not(Box<Ast>, usize),
+    And(Box<Ast>, Box<Ast>),
+    Or(Box<Ast>, Box<Ast>),
+    Xor(Box<Ast>, Box<Ast>),
+}
+
+struct Parser<'q> {
+    query: &'q str,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl<'q> Parser<'q> {
+    fn parse(&mut self) -> Result<Ast, Error> {
+        let ast = self.parse_or()?;
+        self.skip_space();
+        match self.peek() {
+            None => Ok(ast),
+            Some(')') => Err(self.error(self.pos, "unmatched ')'")),
+            Some(_) => Err(self.error(self.pos, "expected an operator")),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Ast, Error> {
+        let mut ast = self.parse_and()?;
+        loop {
+            self.skip_space();
+            match self.peek() {
+                Some('|') => {
+                    self.pos += 1;
+                    let rhs = self.parse_and()?;
+                    ast = Ast::Or(Box::new(ast), Box::new(rhs));
+                }
+                Some('^') => {
+                    self.pos += 1;
+                    let rhs = self.parse_and()?;
+                    ast = Ast::Xor(Box::new(ast), Box::new(rhs));
+                }
+                _ => return Ok(ast),
+            }
+        }
+    }
+
+    fn parse_and(&mut self) -> Result<Ast, Error> {
+        let mut ast = self.parse_not()?;
+        loop {
+            self.skip_space();
+            if self.peek() != Some('&') {
+                return Ok(ast);
+            }
+            self.pos += 1;
+            let rhs = self.parse_not()?;
+            ast = Ast::And(Box::new(ast), Box::new(rhs));
+        }
+    }
+
+    fn parse_not(&mut self) -> Result<Ast, Error> {
+        self.skip_space();
+        if self.peek() == Some('!') {
+            let at = self.pos;
+            self.pos += 1;
+            let ast = self.parse_not()?;
+            return Ok(Ast::Not(Box::new(ast), at + 1));
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Ast, Error> {
+        self.skip_space();
+        let start = self.pos;
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let ast = self.parse_or()?;
+                self.skip_space();
+                if self.peek() != Some(')') {
+                    return Err(self.error(start, "unclosed '('"));
+                }
+                self.pos += 1;
+                Ok(ast)
+            }
+            None | Some(')') | Some('|') | Some('^') | Some('&') => {
+                Err(self.error(start, "expected a set file, filter or '('"))
+            }
+            Some(_) => self.parse_word(),
+        }
+    }
+
+    /// Parse a set file or filter, which runs until whitespace or an
+    /// operator that isn't quoted.
+    fn parse_word(&mut self) -> Result<Ast, Error> {
+        let start = self.pos;
+        // The part of the word before the first quote, which determines
+        // whether this is a filter.
+        let mut head = None;
+        let mut word = String::new();
+        // The position in the query of each character of `word`, so that
+        // errors inside the word can point at the character at fault.
+        let mut cols = vec![];
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || "()|^&!".contains(c) {
+                break;
+            }
+            if c != '"' {
+                word.push(c);
+                cols.push(self.pos);
+                self.pos += 1;
+                continue;
+            }
+            if head.is_none() {
+                head = Some(word.clone());
+            }
+            let quote = self.pos;
+            self.pos += 1;
+            loop {
+                match self.peek() {
+                    None => return Err(self.error(quote, "unclosed quote")),
+                    Some('"') => break,
+                    Some('\\') => {
+                        self.pos += 1;
+                        match self.peek() {
+                            Some(c) if c == '"' || c == '\\' => {
+                                word.push(c);
+                                cols.push(self.pos);
+                            }
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                                cols.push(self.pos - 1);
+                                cols.push(self.pos);
+                            }
+                            None => {
+                                return Err(self.error(quote, "unclosed quote"));
+                            }
+                        }
+                    }
+                    Some(c) => {
+                        word.push(c);
+                        cols.push(self.pos);
+                    }
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+        }
+        let column = start + 1;
+        let head = head.unwrap_or(word.clone());
+        let filter = if head.starts_with("re:") {
+            FilterExpr::Regex(word[3..].to_string(), column)
+        } else if head.starts_with("fuzzy:") {
+            let word = &word[6..];
+            match word.rfind('~') {
+                None => FilterExpr::Fuzzy(word.to_string(), 1, column),
+                Some(i) => match word[i + 1..].parse() {
+                    Ok(distance) => FilterExpr::Fuzzy(
+                        word[..i].to_string(), distance, column),
+                    Err(_) => {
+                        // Point at the distance, or just past the `~` if
+                        // there isn't one.
+                        let tilde = 6 + word[..i].chars().count();
+                        let at = match cols.get(tilde + 1) {
+                            Some(&at) => at,
+                            None => cols[tilde] + 1,
+                        };
+                        return Err(self.error(at, "invalid edit distance"));
+                    }
+                },
+            }
+        } else if head.starts_with("prefix:") {
+            FilterExpr::Prefix(word[7..].to_string())
+        } else if head.starts_with("glob:") {
+            FilterExpr::Glob(word[5..].to_string(), column)
+        } else {
+            return Ok(Ast::File(word, column));
+        };
+        Ok(Ast::Filter(filter, column))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn skip_space(&mut self) {
+        while self.peek().map_or(false, |c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// An error at the given (0-based) character offset.
+    fn error(&self, pos: usize, msg: &str) -> Error {
+        error(self.query, pos + 1, msg.to_string())
+    }
+}
+
+/// An expression that produces a stream of keys.
+#[derive(Clone, Debug)]
+enum SetExpr {
+    File { path: String, column: usize, filter: Option<FilterExpr> },
+    Op(Op, Vec<SetExpr>),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Op {
+    Union,
+    Intersection,
+    /// The keys in the first operand but none of the others.
+    Difference,
+    /// The keys in an odd number of operands.
+    SymmetricDifference,
+}
+
+impl SetExpr {
+    /// Combine two expressions, merging operands with the same operation so
+    /// that, e.g., `a | b | c` is a single union.
+    fn op(op: Op, lhs: SetExpr, rhs: SetExpr) -> SetExpr {
+        let mut operands = match lhs {
+            SetExpr::Op(lop, operands) if lop == op => operands,
+            lhs => vec![lhs],
+        };
+        match rhs {
+            SetExpr::Op(rop, more) if rop == op && op != Op::Difference => {
+                operands.extend(more);
+            }
+            rhs => operands.push(rhs),
+        }
+        SetExpr::Op(op, operands)
+    }
+
+    /// Only keep keys matched by `filter`.
+    fn restrict(self, filter: &FilterExpr) -> SetExpr {
+        match self {
+            SetExpr::File { path, column, filter: None } => SetExpr::File {
+                path: path,
+                column: column,
+                filter: Some(filter.clone()),
+            },
+            SetExpr::File { path, column, filter: Some(f) } => SetExpr::File {
+                path: path,
+                column: column,
+                filter: Some(FilterExpr::And(
+                    Box::new(f), Box::new(filter.clone()))),
+            },
+            SetExpr::Op(Op::Difference, mut operands) => {
+                // Keys not in the first operand are never produced, so only
+                // it needs to be searched with the filter.
+                let first = operands.remove(0).restrict(filter);
+                operands.insert(0, first);
+                SetExpr::Op(Op::Difference, operands)
+            }
+            SetExpr::Op(op, operands) => SetExpr::Op(
+                op,
+                operands.into_iter().map(|e| e.restrict(filter)).collect(),
+            ),
+        }
+    }
+
+    fn files<'a>(&'a self, files: &mut Vec<(&'a str, usize)>) {
+        match *self {
+            SetExpr::File { ref path, column, .. } => {
+                files.push((path, column))
+            }
+            SetExpr::Op(_, ref operands) => {
+                for operand in operands {
+                    operand.files(files);
+                }
+            }
+        }
+    }
+}
+
+/// Any expression, in the normal form `(base & filter) & !minus`. A missing
+/// `base` stands for every key, which can't be streamed, so such an
+/// expression only makes sense when intersected with a set.
+struct Node {
+    base: Option<SetExpr>,
+    filter: Option<FilterExpr>,
+    minus: Option<SetExpr>,
+    column: usize,
+}
+
+type PlanResult<T> = Result<T, (usize, String)>;
+
+impl Node {
+    /// Turn this node into something that can be streamed.
+    fn resolve(self) -> PlanResult<SetExpr> {
+        let mut expr = match self.base {
+            None => {
+                return Err((self.column, "expression must be intersected \
+                                          with a set file".to_string()));
+            }
+            Some(base) => base,
+        };
+        if let Some(ref filter) = self.filter {
+            expr = expr.restrict(filter);
+        }
+        if let Some(minus) = self.minus {
+            expr = SetExpr::op(Op::Difference, expr, minus);
+        }
+        Ok(expr)
+    }
+}
+
+fn plan(ast: Ast) -> PlanResult<Node> {
+    Ok(match ast {
+        Ast::File(path, column) => Node {
+            base: Some(SetExpr::File {
+                path: path,
+                column: column,
+                filter: None,
+            }),
+            filter: None,
+            minus: None,
+            column: column,
+        },
+        Ast::Filter(filter, column) => Node {
+            base: None,
+            filter: Some(filter),
+            minus: None,
+            column: column,
+        },
+        Ast::Not(ast, column) => {
+            let node = plan(*ast)?;
+            match node {
+                Node { base: Some(base), filter: None, minus: None, .. } => {
+                    Node {
+                        base: None,
+                        filter: None,
+                        minus: Some(base),
+                        column: column,
+                    }
+                }
+                Node { base: None, filter: Some(f), minus: None, .. } => {
+                    Node {
+                        base: None,
+                        filter: Some(FilterExpr::Not(Box::new(f))),
+                        minus: None,
+                        column: column,
+                    }
+                }
+                Node { base: None, filter: None, minus: Some(minus), .. } => {
+                    Node {
+                        base: Some(minus),
+                        filter: None,
+                        minus: None,
+                        column: column,
+                    }
+                }
+                _ => {
+                    return Err((column, "can only negate a set expression \
+                                         or a filter".to_string()));
+                }
+            }
+        }
+        Ast::And(lhs, rhs) => {
+            let (lhs, rhs) = (plan(*lhs)?, plan(*rhs)?);
+            Node {
+                base: both(lhs.base, rhs.base, |a, b| {
+                    SetExpr::op(Op::Intersection, a, b)
+                }),
+                filter: both(lhs.filter, rhs.filter, |a, b| {
+                    FilterExpr::And(Box::new(a), Box::new(b))
+                }),
+                minus: both(lhs.minus, rhs.minus, |a, b| {
+                    SetExpr::op(Op::Union, a, b)
+                }),
+                column: lhs.column,
+            }
+        }
+        Ast::Or(lhs, rhs) => {
+            let (lhs, rhs) = (plan(*lhs)?, plan(*rhs)?);
+            if let (Some(f), Some(g)) = (only_filter(&lhs), only_filter(&rhs)) {
+                Node {
+                    base: None,
+                    filter: Some(FilterExpr::Or(
+                        Box::new(f.clone()), Box::new(g.clone()))),
+                    minus: None,
+                    column: lhs.column,
+                }
+            } else {
+                set_op(Op::Union, lhs, rhs)?
+            }
+        }
+        Ast::Xor(lhs, rhs) => {
+            let (lhs, rhs) = (plan(*lhs)?, plan(*rhs)?);
+            set_op(Op::SymmetricDifference, lhs, rhs)?
+        }
+    })
+}
+
+/// Combine two nodes that must both be streamable.
+fn set_op(op: Op, lhs: Node, rhs: Node) -> PlanResult<Node> {
+    let column = lhs.column;
+    let (lhs, rhs) = (lhs.resolve()?, rhs.resolve()?);
+    Ok(Node {
+        base: Some(SetExpr::op(op, lhs, rhs)),
+        filter: None,
+        minus: None,
+        column: column,
+    })
+}
+
+fn only_filter(node: &Node) -> Option<&FilterExpr> {
+    match *node {
+        Node { base: None, filter: Some(ref f), minus: None, .. } => Some(f),
+        _ => None,
+    }
+}
+
+/// Combine two optional values with `f` if both are present.
+fn both<T, F>(a: Option<T>, b: Option<T>, f: F) -> Option<T>
+where F: FnOnce(T, T) -> T {
+    match (a, b) {
+        (None, None) => None,
+        (Some(a), None) | (None, Some(a)) => Some(a),
+        (Some(a), Some(b)) => Some(f(a, b)),
+    }
+}
+
+/// A filter before it's compiled to an automaton. Leaves keep their column
+/// for reporting compile errors.
+#[derive(Clone, Debug)]
+enum FilterExpr {
+    Regex(String, usize),
+    Fuzzy(String, u32, usize),
+    Prefix(String),
+    Glob(String, usize),
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+}
+
+impl FilterExpr {
+    fn compile(&self) -> PlanResult<Filter> {
+        Ok(match *self {
+            FilterExpr::Regex(ref re, column) => {
+                Filter::Regex(Regex::new(re).map_err(|e| {
+                    (column, e.to_string())
+                })?)
+            }
+            FilterExpr::Fuzzy(ref query, distance, column) => {
+                Filter::Fuzzy(Levenshtein::new(query, distance).map_err(|e| {
+                    (column, e.to_string())
+                })?)
+            }
+            FilterExpr::Prefix(ref prefix) => {
+                Filter::Prefix(prefix.as_bytes().to_vec())
+            }
+            FilterExpr::Glob(ref pattern, column) => {
+                Filter::Glob(Glob::new(pattern).map_err(|e| {
+                    (column, e.to_string())
+                })?)
+            }
+            FilterExpr::And(ref a, ref b) => {
+                Filter::And(Box::new(a.compile()?), Box::new(b.compile()?))
+            }
+            FilterExpr::Or(ref a, ref b) => {
+                Filter::Or(Box::new(a.compile()?), Box::new(b.compile()?))
+            }
+            FilterExpr::Not(ref a) => Filter::Not(Box::new(a.compile()?)),
+        })
+    }
+}
+
+/// A compiled filter.
+///
+/// Filters are combined at run time, so this can't use the combinators on
+/// `fst::Automaton`, whose types grow with the expression.
+enum Filter {
+    Regex(Regex),
+    Fuzzy(Levenshtein),
+    Prefix(Vec<u8>),
+    Glob(Glob),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+enum FilterState {
+    Regex(<Regex as Automaton>::State),
+    Fuzzy(<Levenshtein as Automaton>::State),
+    /// The number of bytes of the prefix matched so far, or `None` if the
+    /// key doesn't start with it.
+    Prefix(Option<usize>),
+    Glob(glob::State),
+    Pair(Box<FilterState>, Box<FilterState>),
+    Not(Box<FilterState>),
+}
+
+impl Automaton for Filter {
+    type State = FilterState;
+
+    fn start(&self) -> FilterState {
+        match *self {
+            Filter::Regex(ref re) => FilterState::Regex(re.start()),
+            Filter::Fuzzy(ref lev) => FilterState::Fuzzy(lev.start()),
+            Filter::Prefix(_) => FilterState::Prefix(Some(0)),
+            Filter::Glob(ref glob) => FilterState::Glob(glob.start()),
+            Filter::And(ref a, ref b) | Filter::Or(ref a, ref b) => {
+                FilterState::Pair(Box::new(a.start()), Box::new(b.start()))
+            }
+            Filter::Not(ref a) => FilterState::Not(Box::new(a.start())),
+        }
+    }
+
+    fn is_match(&self, state: &FilterState) -> bool {
+        match (self, state) {
+            (&Filter::Regex(ref re), &FilterState::Regex(ref s)) => {
+                re.is_match(s)
+            }
+            (&Filter::Fuzzy(ref lev), &FilterState::Fuzzy(ref s)) => {
+                lev.is_match(s)
+            }
+            (&Filter::Prefix(ref p), &FilterState::Prefix(s)) => {
+                s == Some(p.len())
+            }
+            (&Filter::Glob(ref glob), &FilterState::Glob(ref s)) => {
+                glob.is_match(s)
+            }
+            (&Filter::And(ref a, ref b), &FilterState::Pair(ref x, ref y)) => {
+                a.is_match(x) && b.is_match(y)
+            }
+            (&Filter::Or(ref a, ref b), &FilterState::Pair(ref x, ref y)) => {
+                a.is_match(x) || b.is_match(y)
+            }
+            (&Filter::Not(ref a), &FilterState::Not(ref s)) => !a.is_match(s),
+            _ => unreachable!("filter and state don't agree"),
+        }
+    }
+
+    fn can_match(&self, state: &FilterState) -> bool {
+        match (self, state) {
+            (&Filter::Regex(ref re), &FilterState::Regex(ref s)) => {
+                re.can_match(s)
+            }
+            (&Filter::Fuzzy(ref lev), &FilterState::Fuzzy(ref s)) => {
+                lev.can_match(s)
+            }
+            (&Filter::Prefix(_), &FilterState::Prefix(s)) => s.is_some(),
+            (&Filter::Glob(ref glob), &FilterState::Glob(ref s)) => {
+                glob.can_match(s)
+            }
+            (&Filter::And(ref a, ref b), &FilterState::Pair(ref x, ref y)) => {
+                a.can_match(x) && b.can_match(y)
+            }
+            (&Filter::Or(ref a, ref b), &FilterState::Pair(ref x, ref y)) => {
+                a.can_match(x) || b.can_match(y)
+            }
+            (&Filter::Not(ref a), &FilterState::Not(ref s)) => {
+                !a.will_always_match(s)
+            }
+            _ => unreachable!("filter and state don't agree"),
+        }
+    }
+
+    fn will_always_match(&self, state: &FilterState) -> bool {
+        match (self, state) {
+            (&Filter::Regex(ref re), &FilterState::Regex(ref s)) => {
+                re.will_always_match(s)
+            }
+            (&Filter::Fuzzy(ref lev), &FilterState::Fuzzy(ref s)) => {
+                lev.will_always_match(s)
+            }
+            (&Filter::Prefix(ref p), &FilterState::Prefix(s)) => {
+                s == Some(p.len())
+            }
+            (&Filter::Glob(ref glob), &FilterState::Glob(ref s)) => {
+                glob.will_always_match(s)
+            }
+            (&Filter::And(ref a, ref b), &FilterState::Pair(ref x, ref y)) => {
+                a.will_always_match(x) && b.will_always_match(y)
+            }
+            (&Filter::Or(ref a, ref b), &FilterState::Pair(ref x, ref y)) => {
+                a.will_always_match(x) || b.will_always_match(y)
+            }
+            (&Filter::Not(ref a), &FilterState::Not(ref s)) => {
+                !a.can_match(s)
+            }
+            _ => unreachable!("filter and state don't agree"),
+        }
+    }
+
+    fn accept(&self, state: &FilterState, byte: u8) -> FilterState {
+        match (self, state) {
+            (&Filter::Regex(ref re), &FilterState::Regex(ref s)) => {
+                FilterState::Regex(re.accept(s, byte))
+            }
+            (&Filter::Fuzzy(ref lev), &FilterState::Fuzzy(ref s)) => {
+                FilterState::Fuzzy(lev.accept(s, byte))
+            }
+            (&Filter::Prefix(ref p), &FilterState::Prefix(s)) => {
+                FilterState::Prefix(match s {
+                    Some(i) if i == p.len() => Some(i),
+                    Some(i) if p[i] == byte => Some(i + 1),
+                    _ => None,
+                })
+            }
+            (&Filter::Glob(ref glob), &FilterState::Glob(ref s)) => {
+                FilterState::Glob(glob.accept(s, byte))
+            }
+            (&Filter::And(ref a, ref b), &FilterState::Pair(ref x, ref y))
+            | (&Filter::Or(ref a, ref b), &FilterState::Pair(ref x, ref y)) => {
+                FilterState::Pair(
+                    Box::new(a.accept(x, byte)),
+                    Box::new(b.accept(y, byte)),
+                )
+            }
+            (&Filter::Not(ref a), &FilterState::Not(ref s)) => {
+                FilterState::Not(Box::new(a.accept(s, byte)))
+            }
+            _ => unreachable!("filter and state don't agree"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fst::{Set, Streamer};
+
+    use super::{Query, Sets};
+
+    fn sets() -> Sets {
+        let mut sets = Sets::new();
+        let files = [
+            ("a.fst", vec!["apple", "banana", "cherry", "date"]),
+            ("b.fst", vec!["banana", "berry", "cherry", "fig"]),
+            ("c.fst", vec!["cherry", "date", "fig", "grape"]),
+        ];
+        for &(name, ref keys) in &files {
+            sets.insert(name.to_string(), Set::from_iter(keys).unwrap());
+        }
+        sets
+    }
+
+    fn search(query: &str) -> Vec<String> {
+        let sets = sets();
+        let query = Query::parse(query).unwrap();
+        let mut keys = query.search(&sets).unwrap();
+        let mut found = vec![];
+        while let Some(key) = keys.next() {
+            found.push(String::from_utf8(key.to_vec()).unwrap());
+        }
+        found
+    }
+
+    /// The column of the error for `query`, whether it's found while
+    /// parsing or when compiling the filters.
+    fn column(query: &str) -> usize {
+        let sets = sets();
+        let result = Query::parse(query).and_then(|q| {
+            q.search(&sets).map(|_| ())
+        });
+        match result {
+            Ok(()) => panic!("{:?} should be an error", query),
+            Err(err) => err.column(),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(search("a.fst | b.fst & c.fst"),
+                   ["apple", "banana", "cherry", "date", "fig"]);
+        assert_eq!(search("(a.fst | b.fst) & c.fst"),
+                   ["cherry", "date", "fig"]);
+        // `|` and `^` have the same precedence and group to the left.
+        assert_eq!(search("a.fst ^ b.fst | c.fst"),
+                   ["apple", "berry", "cherry", "date", "fig", "grape"]);
+        assert_eq!(search("a.fst ^ (b.fst | c.fst)"),
+                   ["apple", "berry", "fig", "grape"]);
+        assert_eq!(search("!a.fst & b.fst"), ["berry", "fig"]);
+        assert_eq!(search("!!a.fst & b.fst"), ["banana", "cherry"]);
+        assert_eq!(search("((a.fst))"), ["apple", "banana", "cherry", "date"]);
+    }
+
+    #[test]
+    fn normal_form() {
+        // Bases, filters and complements can come in any order.
+        assert_eq!(search("!c.fst & glob:\"b*\" & (a.fst | b.fst)"),
+                   ["banana", "berry"]);
+        assert_eq!(search("re:\"[a-d].*\" & a.fst & !b.fst"),
+                   ["apple", "date"]);
+        assert_eq!(search("a.fst & !glob:\"*e*\""), ["banana"]);
+        assert_eq!(search("a.fst & (prefix:b | prefix:d)"),
+                   ["banana", "date"]);
+        assert!(search("a.fst & prefix:b & !c.fst & !b.fst").is_empty());
+        assert_eq!(search("(a.fst & !b.fst) | (c.fst & prefix:g)"),
+                   ["apple", "date", "grape"]);
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(search("b.fst & re:\"b[ae].*\""), ["banana", "berry"]);
+        assert_eq!(search("b.fst & re:b.*"), ["banana", "berry"]);
+        assert_eq!(search("b.fst & fuzzy:bery"), ["berry"]);
+        assert_eq!(search("c.fst & fuzzy:\"fog\"~1"), ["fig"]);
+        assert!(search("c.fst & fuzzy:figs~0").is_empty());
+        assert_eq!(search("c.fst & fuzzy:gate~2"), ["date", "grape"]);
+        assert_eq!(search("c.fst & glob:\"?a*\""), ["date"]);
+        assert_eq!(search("a.fst & glob:*an*"), ["banana"]);
+    }
+
+    #[test]
+    fn columns() {
+        assert_eq!(column("a.fst )"), 7);
+        assert_eq!(column("(a.fst"), 1);
+        assert_eq!(column("a.fst b.fst"), 7);
+        assert_eq!(column("a.fst & "), 9);
+        assert_eq!(column("a.fst & \"b.fst"), 9);
+        assert_eq!(column("re:x"), 1);
+        assert_eq!(column("a.fst | re:x"), 9);
+        assert_eq!(column("!(a.fst & re:x)"), 1);
+        assert_eq!(column("a.fst & re:\"(x\""), 9);
+        assert_eq!(column("a.fst & glob:\"[a\""), 9);
+        // A bad edit distance points at the distance, past any quotes.
+        assert_eq!(column("a.fst & fuzzy:foo~x"), 19);
+        assert_eq!(column("a.fst & fuzzy:\"f o\"~x"), 21);
+        assert_eq!(column("a.fst & fuzzy:\"f\\\"o\"~x"), 22);
+        assert_eq!(column("a.fst & fuzzy:fo\"o~x\""), 20);
+        assert_eq!(column("a.fst & fuzzy:foo~"), 19);
+    }
+}
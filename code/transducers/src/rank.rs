@@ -0,0 +1,253 @@
+/*!
+Sets that can answer rank and select queries.
+
+A plain set can say whether a key is in it, but not where. A `RankSet` is an
+`OrdinalMap` in which every key's value is its *rank*: the number of keys
+before it. The output on each transition then ends up being the number of
+keys in the subtrees of the transitions before it (plus one if the state it
+leaves is final), so, in time proportional to the length of a key:
+
+* `rank(key)` counts the keys less than `key`, by finding the value of the
+  first key not less than it,
+* `select(n)` finds the key with rank `n`, by looking it up by value, and
+* `count_range(lo, hi)` counts the keys in a range with two ranks.
+
+This is what pagination ("show keys 1,000,000 through 1,000,050") and
+uniform sampling need, neither of which a plain set can do without streaming
+from the start.
+
+Any other map or set would open as a `RankSet` just as well and then give
+wrong answers, so the builder marks the FST with its own type (the number FST
+headers reserve for this) and `RankSet` refuses to open FSTs without it.
+`RankSet::from_map` skips the check, for maps known to hold ranks.
+*/
+
+use std::io;
+use std::path::Path;
+
+use fst::Map;
+use fst::raw::{self, FstType};
+
+use {Result, open_map};
+use ordinal::OrdinalMap;
+
+/// The type recorded in the header of FSTs written by `RankSetBuilder`.
+const FST_TYPE: FstType = 0x4b4e_4152; // "RANK"
+
+/// Builds a `RankSet`.
+pub struct RankSetBuilder<W> {
+    fst: raw::Builder<W>,
+    len: u64,
+}
+
+impl RankSetBuilder<Vec<u8>> {
+    /// Create a builder that writes the set to memory.
+    pub fn memory() -> RankSetBuilder<Vec<u8>> {
+        // Writing the header to memory can't fail.
+        let fst = raw::Builder::new_type(vec![], FST_TYPE).unwrap();
+        RankSetBuilder { fst: fst, len: 0 }
+    }
+}
+
+impl<W: io::Write> RankSetBuilder<W> {
+    /// Create a builder that writes the set to `wtr`.
+    pub fn new(wtr: W) -> Result<RankSetBuilder<W>> {
+        let fst = raw::Builder::new_type(wtr, FST_TYPE)?;
+        Ok(RankSetBuilder { fst: fst, len: 0 })
+    }
+
+    /// Insert a key. As with `SetBuilder`, keys must be inserted in
+    /// lexicographic order.
+    pub fn insert<K: AsRef<[u8]>>(&mut self, key: K) -> Result<()> {
+        self.fst.insert(key, self.len)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Finish writing the set.
+    pub fn finish(self) -> Result<()> {
+        self.fst.finish()?;
+        Ok(())
+    }
+
+    /// Finish writing the set and return the underlying writer.
+    pub fn into_inner(self) -> Result<W> {
+        Ok(self.fst.into_inner()?)
+    }
+}
+
+/// A set that knows the rank of each of its keys.
+pub struct RankSet {
+    map: OrdinalMap,
+}
+
+impl RankSet {
+    /// Open the set in the file at `path`, which must have been written by a
+    /// `RankSetBuilder`. The file is memory mapped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<RankSet> {
+        let map = open_map(&path)?;
+        if map.as_fst().fst_type() != FST_TYPE {
+            return Err(From::from(format!(
+                "{}: not a rank set (build it with `fst-index build --ranked`)",
+                path.as_ref().display())));
+        }
+        Ok(RankSet::from_map(map))
+    }
+
+    /// Read a set written to memory by a `RankSetBuilder`.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<RankSet> {
+        let map = Map::from_bytes(bytes)?;
+        if map.as_fst().fst_type() != FST_TYPE {
+            return Err(From::from("not a rank set"));
+        }
+        Ok(RankSet::from_map(map))
+    }
+
+    /// Use a map as a rank set, without checking that it was written by a
+    /// `RankSetBuilder`. Every key's value must be its rank, or the results
+    /// of queries are meaningless.
+    pub fn from_map(map: Map) -> RankSet {
+        RankSet { map: OrdinalMap::from_map(map) }
+    }
+
+    /// The underlying map, in which every key's value is its rank.
+    pub fn as_map(&self) -> &Map {
+        self.map.as_map()
+    }
+
+    /// The number of keys in the set.
+    pub fn len(&self) -> u64 {
+        self.map.len()
+    }
+
+    /// Returns true if the set has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if `key` is in the set.
+    pub fn contains<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.map.as_map().contains_key(key)
+    }
+
+    /// Returns the number of keys less than `key`, whether or not `key` is
+    /// in the set.
+    pub fn rank<K: AsRef<[u8]>>(&self, key: K) -> u64 {
+        self.map.ceil(key).unwrap_or(self.len())
+    }
+
+    /// Returns the key with rank `n`, i.e., the `n`th smallest key, counting
+    /// from 0. Returns `None` if the set has `n` or fewer keys.
+    pub fn select(&self, n: u64) -> Option<Vec<u8>> {
+        if n >= self.len() {
+            return None;
+        }
+        self.map.key(n)
+    }
+
+    /// Returns the number of keys greater than or equal to `lo` and less
+    /// than `hi`.
+    pub fn count_range<K: AsRef<[u8]>, L: AsRef<[u8]>>(
+        &self,
+        lo: K,
+        hi: L,
+    ) -> u64 {
+        self.rank(hi).saturating_sub(self.rank(lo))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use fst::{Map, MapBuilder};
+
+    use super::{RankSet, RankSetBuilder};
+
+    /// A xorshift generator, so the tests are random but repeatable.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn key(&mut self) -> Vec<u8> {
+            let len = self.next_u64() % 6;
+            (0..len).map(|_| b"abc"[self.next_u64() as usize % 3]).collect()
+        }
+    }
+
+    fn build(keys: &[Vec<u8>]) -> RankSet {
+        let mut builder = RankSetBuilder::memory();
+        for key in keys {
+            builder.insert(key).unwrap();
+        }
+        RankSet::from_bytes(builder.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn random() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for &n in &[0, 1, 2, 5, 20, 100, 400] {
+            let keys: BTreeSet<Vec<u8>> = (0..n).map(|_| rng.key()).collect();
+            let keys: Vec<Vec<u8>> = keys.into_iter().collect();
+            let set = build(&keys);
+            assert_eq!(set.len(), keys.len() as u64);
+            assert_eq!(set.is_empty(), keys.is_empty());
+
+            for n in 0..keys.len() as u64 + 2 {
+                assert_eq!(set.select(n), keys.get(n as usize).cloned());
+            }
+
+            // Every key, the empty key and random keys, most of which fall
+            // between the keys in the set.
+            let mut probes = keys.clone();
+            probes.push(vec![]);
+            probes.push(b"d".to_vec());
+            probes.extend((0..100).map(|_| rng.key()));
+            for probe in &probes {
+                let rank = keys.iter().filter(|k| *k < probe).count() as u64;
+                assert_eq!(set.rank(probe), rank, "rank of {:?}", probe);
+                assert_eq!(set.contains(probe), keys.contains(probe));
+            }
+            // The first keys and some random ones, to keep this quadratic
+            // check quick.
+            let some: Vec<&Vec<u8>> = probes.iter().take(20)
+                .chain(probes.iter().rev().take(20))
+                .collect();
+            for &lo in &some {
+                for &hi in &some {
+                    let count = keys.iter()
+                        .filter(|k| *k >= lo && *k < hi)
+                        .count() as u64;
+                    assert_eq!(set.count_range(lo, hi), count,
+                               "count of {:?}..{:?}", lo, hi);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn marked() {
+        let mut builder = RankSetBuilder::memory();
+        for key in &["a", "b", "c"] {
+            builder.insert(key).unwrap();
+        }
+        let set = RankSet::from_bytes(builder.into_inner().unwrap()).unwrap();
+        assert_eq!(set.rank("b"), 1);
+
+        // The same map, but not written by a `RankSetBuilder`.
+        let mut builder = MapBuilder::memory();
+        for (rank, key) in ["a", "b", "c"].iter().enumerate() {
+            builder.insert(key, rank as u64).unwrap();
+        }
+        let bytes = builder.into_inner().unwrap();
+        assert!(RankSet::from_bytes(bytes.clone()).is_err());
+        let map = Map::from_bytes(bytes).unwrap();
+        assert_eq!(RankSet::from_map(map).rank("b"), 1);
+    }
+}
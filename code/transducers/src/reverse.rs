@@ -0,0 +1,212 @@
+/*!
+A set paired with a second set of its keys reversed, for suffix queries.
+
+An FST can only skip keys that don't share a *prefix* with the query, so a
+pattern like `*.org` has to look at every key. Storing every key a second
+time with its bytes reversed turns suffixes into prefixes: the keys ending in
+`.org` are exactly the reversed keys starting with `gro.`, which a range
+query finds directly.
+
+`PairedSetBuilder` writes both sets at once. The reversed keys come out of
+order, so they're sorted with an `ExternalSorter` before being written.
+`PairedSet` opens both and answers prefix, suffix and glob queries, routing
+each to whichever set can answer it without a full scan and reversing keys
+back before returning them.
+*/
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use fst::{IntoStreamer, Set, SetBuilder, Streamer};
+use fst::set;
+
+use {Result, matches, open_set, prefix_end};
+use extsort::ExternalSorter;
+use glob::Glob;
+
+/// Returns the path of the reversed set that accompanies the set at `path`,
+/// which is `path` with `.rev` appended.
+pub fn reversed_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut name = OsString::from(path.as_ref());
+    name.push(".rev");
+    PathBuf::from(name)
+}
+
+/// Builds a set and its reversed companion together.
+pub struct PairedSetBuilder<W> {
+    forward: SetBuilder<W>,
+    reversed: W,
+    sorter: ExternalSorter,
+}
+
+impl PairedSetBuilder<io::BufWriter<File>> {
+    /// Create a builder that writes the set to `path` and the reversed set
+    /// to `reversed_path(path)`.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<PairedSetBuilder<io::BufWriter<File>>> {
+        let forward = io::BufWriter::new(File::create(&path)?);
+        let reversed = io::BufWriter::new(File::create(reversed_path(path))?);
+        PairedSetBuilder::new(forward, reversed)
+    }
+}
+
+impl<W: Write> PairedSetBuilder<W> {
+    /// Create a builder that writes the set to `forward` and the reversed set
+    /// to `reversed`.
+    pub fn new(forward: W, reversed: W) -> Result<PairedSetBuilder<W>> {
+        Ok(PairedSetBuilder {
+            forward: SetBuilder::new(forward)?,
+            reversed: reversed,
+            sorter: ExternalSorter::new(),
+        })
+    }
+
+    /// The sorter used for the reversed keys. Use this to set its memory
+    /// limit or temporary directory before inserting any keys.
+    pub fn sorter(&mut self) -> &mut ExternalSorter {
+        &mut self.sorter
+    }
+
+    /// Insert a key. As with `SetBuilder`, keys must be inserted in
+    /// lexicographic order.
+    pub fn insert<K: AsRef<[u8]>>(&mut self, key: K) -> Result<()> {
+        let key = key.as_ref();
+        self.forward.insert(key)?;
+        let reversed: Vec<u8> = key.iter().rev().cloned().collect();
+        self.sorter.push(reversed, 0)?;
+        Ok(())
+    }
+
+    /// Finish writing both sets.
+    pub fn finish(self) -> Result<()> {
+        self.forward.finish()?;
+        let mut reversed = SetBuilder::new(self.reversed)?;
+        for result in self.sorter.finish()? {
+            let (key, _) = result?;
+            reversed.insert(key)?;
+        }
+        reversed.finish()?;
+        Ok(())
+    }
+}
+
+/// A set and its reversed companion.
+pub struct PairedSet {
+    forward: Set,
+    reversed: Set,
+}
+
+impl PairedSet {
+    /// Open the set at `path` and its companion at `reversed_path(path)`.
+    /// Both files are memory mapped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<PairedSet> {
+        let forward = open_set(&path)?;
+        let reversed = open_set(reversed_path(&path))?;
+        PairedSet::new(forward, reversed)
+    }
+
+    /// Pair a set with its reversed companion. Returns an error if they have
+    /// a different number of keys, which means they weren't built together.
+    pub fn new(forward: Set, reversed: Set) -> Result<PairedSet> {
+        if forward.len() != reversed.len() {
+            return Err(From::from(format!(
+                "reversed set has {} keys but the set has {}",
+                reversed.len(), forward.len())));
+        }
+        Ok(PairedSet { forward: forward, reversed: reversed })
+    }
+
+    /// The set of keys in their original order.
+    pub fn forward(&self) -> &Set {
+        &self.forward
+    }
+
+    /// Stream every key starting with `prefix`, in lexicographic order.
+    pub fn starts_with(&self, prefix: &[u8]) -> Matches {
+        let mut range = self.forward.range().ge(prefix);
+        if let Some(end) = prefix_end(prefix) {
+            range = range.lt(end);
+        }
+        Matches::new(Inner::Range(range.into_stream()))
+    }
+
+    /// Stream every key ending with `suffix`.
+    ///
+    /// Keys are found in the reversed set, so they come out in the
+    /// lexicographic order of their *reversed* bytes rather than their own.
+    pub fn ends_with(&self, suffix: &[u8]) -> Matches {
+        Matches::new(Inner::Reversed(self.reversed_range(suffix), None))
+    }
+
+    /// Stream every key matching the glob `pattern`.
+    ///
+    /// If the pattern starts with a wildcard but ends with literal text, as
+    /// in `*.org` or `*.example.??.org`, the candidates are found with
+    /// `ends_with` and then checked against the whole pattern, and come out
+    /// in the same order. Otherwise, the pattern is run against the set
+    /// directly, which only has to look at keys starting with the pattern's
+    /// literal prefix (if any).
+    pub fn glob(&self, pattern: &str) -> Result<Matches> {
+        let glob = Glob::new(pattern)?;
+        if !glob.prefix().is_empty() || glob.suffix().is_empty() {
+            let stream = self.forward.search(glob).into_stream();
+            return Ok(Matches::new(Inner::Search(stream)));
+        }
+        let stream = self.reversed_range(glob.suffix().as_bytes());
+        Ok(Matches::new(Inner::Reversed(stream, Some(glob))))
+    }
+
+    fn reversed_range(&self, suffix: &[u8]) -> set::Stream {
+        let reversed: Vec<u8> = suffix.iter().rev().cloned().collect();
+        let mut range = self.reversed.range().ge(&reversed);
+        if let Some(end) = prefix_end(&reversed) {
+            range = range.lt(end);
+        }
+        range.into_stream()
+    }
+}
+
+/// A stream of keys from a `PairedSet` query.
+pub struct Matches<'s> {
+    inner: Inner<'s>,
+    key: Vec<u8>,
+}
+
+enum Inner<'s> {
+    Range(set::Stream<'s>),
+    Search(set::Stream<'s, Glob>),
+    /// Reversed keys, which are turned back around and kept if they match
+    /// the glob (if any).
+    Reversed(set::Stream<'s>, Option<Glob>),
+}
+
+impl<'s> Matches<'s> {
+    fn new(inner: Inner<'s>) -> Matches<'s> {
+        Matches { inner: inner, key: vec![] }
+    }
+}
+
+impl<'a, 's> Streamer<'a> for Matches<'s> {
+    type Item = &'a [u8];
+
+    fn next(&'a mut self) -> Option<&'a [u8]> {
+        let Matches { ref mut inner, ref mut key } = *self;
+        match *inner {
+            Inner::Range(ref mut stream) => stream.next(),
+            Inner::Search(ref mut stream) => stream.next(),
+            Inner::Reversed(ref mut stream, ref glob) => {
+                while let Some(reversed) = stream.next() {
+                    key.clear();
+                    key.extend(reversed.iter().rev());
+                    if glob.as_ref().map_or(true, |g| matches(g, key)) {
+                        return Some(key);
+                    }
+                }
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,1024 @@
+/*!
+Building and searching FSTs in shards, in parallel.
+
+An FST is built by inserting keys one at a time in lexicographic order, so
+`SetBuilder` and `MapBuilder` can only ever use one core. `ShardedBuilder`
+cuts its (already sorted) input into contiguous chunks and builds each chunk
+as a separate shard in a pool of threads, so that with `N` threads, `N`
+shards are being built at once.
+
+Keys are buffered until a chunk is full, and the full chunk is then handed to
+the next idle thread. Each thread holds on to at most one chunk at a time, so
+at most `threads + 1` chunks are in memory at once, and their size is chosen
+to keep the total under `max_memory`. When every thread is busy, `push`
+blocks until one is done.
+
+Since every shard holds a contiguous range of keys, the shards written to a
+directory by `finish` are ordered, disjoint and can be searched together with
+`ShardedSet` or `ShardedMap`, which is what makes building in parallel pay
+off. `finish_into` instead concatenates them into a single FST. Concatenation
+is a single pass over every key, which is cheaper than building from text
+(there's nothing to parse, sort or deduplicate) but still about as much work
+as inserting every key into one builder, so it only pays when the parallel
+part of the job is the expensive one.
+
+If a builder is dropped before `finish` succeeds, e.g., because a key was out
+of order or a shard failed to build, it removes the shards it wrote.
+
+`Progress` counts the keys inserted into shards and the bytes written so far,
+and can be read from any thread while the build is running.
+
+`ShardedSet` and `ShardedMap` search shards, however they were built, as if
+they were one FST. Like a union with `OpBuilder`, the results are in order
+and each key is found once, but each shard is searched by a pool of threads
+in batches of a few hundred keys, which are merged as they arrive. A search
+only reads ahead a batch or so per shard, so a search with a `limit` (or one
+that's dropped early) does little more work than it has to. Resuming a shard
+from the last key of its previous batch is a `gt` bound on a fresh search,
+which costs time proportional to the length of the key rather than a rescan.
+*/
+
+use std::cmp::{self, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use fst::{Automaton, Map, MapBuilder, SetBuilder, Streamer};
+use fst::automaton::AlwaysMatch;
+use fst::map::IndexedValue;
+
+use {Result, open_map};
+use bounds::Bounds;
+use combine::Reducer;
+
+/// The default amount of memory used for buffering keys.
+const DEFAULT_MAX_MEMORY: usize = 128 * (1 << 20);
+
+/// The default number of threads building shards.
+const DEFAULT_THREADS: usize = 4;
+
+/// The approximate amount of memory used by a buffered entry, not including
+/// the bytes of the key itself.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// How many keys a thread inserts between updates to `Progress`.
+const PROGRESS_INTERVAL: usize = 1 << 12;
+
+/// A chunk of sorted keys and their values, along with the index of the shard
+/// it becomes.
+type Chunk = (usize, Vec<(Vec<u8>, u64)>);
+
+/// Builds a set or map as a sequence of shards, in parallel.
+///
+/// Keys must be pushed in lexicographic order without duplicates, as with
+/// `SetBuilder` and `MapBuilder`. To build from unsorted input, push the
+/// output of an `ExternalSorter`.
+pub struct ShardedBuilder {
+    is_map: bool,
+    dir: PathBuf,
+    threads: usize,
+    max_memory: usize,
+    progress: Arc<Progress>,
+    pool: Option<Pool>,
+    chunk: Vec<(Vec<u8>, u64)>,
+    chunk_bytes: usize,
+    /// The last key of the last chunk sent to the pool.
+    last: Option<Vec<u8>>,
+    shards: usize,
+    /// Whether every shard was built, so that they should be kept.
+    finished: bool,
+}
+
+/// The threads building shards, and the channel for sending them chunks.
+struct Pool {
+    send: SyncSender<Chunk>,
+    handles: Vec<JoinHandle<Result<()>>>,
+}
+
+impl ShardedBuilder {
+    /// Create a builder for a set, which writes its shards to `dir`.
+    pub fn set<P: AsRef<Path>>(dir: P) -> ShardedBuilder {
+        ShardedBuilder::new(dir.as_ref(), false)
+    }
+
+    /// Create a builder for a map, which writes its shards to `dir`.
+    pub fn map<P: AsRef<Path>>(dir: P) -> ShardedBuilder {
+        ShardedBuilder::new(dir.as_ref(), true)
+    }
+
+    fn new(dir: &Path, is_map: bool) -> ShardedBuilder {
+        ShardedBuilder {
+            is_map: is_map,
+            dir: dir.to_path_buf(),
+            threads: DEFAULT_THREADS,
+            max_memory: DEFAULT_MAX_MEMORY,
+            progress: Arc::new(Progress::new()),
+            pool: None,
+            chunk: vec![],
+            chunk_bytes: 0,
+            last: None,
+            shards: 0,
+            finished: false,
+        }
+    }
+
+    /// Set the number of threads building shards. The default is 4.
+    ///
+    /// This has no effect once a key has been pushed.
+    pub fn threads(&mut self, threads: usize) -> &mut ShardedBuilder {
+        self.threads = if threads == 0 { 1 } else { threads };
+        self
+    }
+
+    /// Set the approximate number of bytes of keys to buffer across all
+    /// threads. The default is 128 MB. This doesn't count the memory used
+    /// by the builders themselves, which is small and doesn't grow with the
+    /// number of keys.
+    pub fn max_memory(&mut self, bytes: usize) -> &mut ShardedBuilder {
+        self.max_memory = bytes;
+        self
+    }
+
+    /// Returns a handle to the build's progress, which can be sent to
+    /// another thread to report on it.
+    pub fn progress(&self) -> Arc<Progress> {
+        self.progress.clone()
+    }
+
+    /// Add a key with the given value. For sets, the value is ignored.
+    pub fn push<K: AsRef<[u8]>>(&mut self, key: K, value: u64) -> Result<()> {
+        let key = key.as_ref();
+        {
+            let last = match self.chunk.last() {
+                Some(&(ref last, _)) => Some(last),
+                None => self.last.as_ref(),
+            };
+            if let Some(last) = last {
+                if key <= &**last {
+                    return Err(From::from(format!(
+                        "keys must be distinct and in lexicographic order, \
+                         but {:?} follows {:?}",
+                        String::from_utf8_lossy(key),
+                        String::from_utf8_lossy(last))));
+                }
+            }
+        }
+        self.chunk_bytes += key.len() + ENTRY_OVERHEAD;
+        self.chunk.push((key.to_vec(), value));
+        if self.chunk_bytes >= self.chunk_limit() {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Finish building every shard and return their paths, in the order of
+    /// their keys.
+    pub fn finish(mut self) -> Result<Vec<PathBuf>> {
+        if !self.chunk.is_empty() || self.shards == 0 {
+            // Even an empty set gets a shard, so that there's something to
+            // open.
+            self.flush()?;
+        }
+        self.join()?;
+        self.finished = true;
+        self.progress.finish();
+        Ok((0..self.shards).map(|i| shard_path(&self.dir, i)).collect())
+    }
+
+    /// Finish building every shard, concatenate them into a single FST
+    /// written to `wtr`, and remove the shards.
+    pub fn finish_into<W: Write>(self, wtr: W) -> Result<()> {
+        let is_map = self.is_map;
+        let shards = self.finish()?;
+        let result = concat(&shards, wtr, is_map);
+        for path in &shards {
+            let _ = fs::remove_file(path);
+        }
+        result
+    }
+
+    /// Wait for every thread to finish building its shard, and return the
+    /// first error any of them hit.
+    fn join(&mut self) -> Result<()> {
+        let pool = match self.pool.take() {
+            None => return Ok(()),
+            Some(pool) => pool,
+        };
+        drop(pool.send);
+        let mut result = Ok(());
+        for handle in pool.handles {
+            let joined = match handle.join() {
+                Ok(joined) => joined,
+                Err(_) => Err(From::from("a shard builder panicked")),
+            };
+            if result.is_ok() {
+                result = joined;
+            }
+        }
+        result
+    }
+
+    /// The number of bytes of keys that make a full chunk.
+    fn chunk_limit(&self) -> usize {
+        self.max_memory / (self.threads + 1)
+    }
+
+    /// Send the current chunk to the pool to be built as the next shard.
+    fn flush(&mut self) -> Result<()> {
+        if self.pool.is_none() {
+            fs::create_dir_all(&self.dir)?;
+            self.pool = Some(self.start_pool());
+        }
+        let chunk = mem::replace(&mut self.chunk, vec![]);
+        self.chunk_bytes = 0;
+        self.last = chunk.last().map(|&(ref key, _)| key.clone());
+        let index = self.shards;
+        self.shards += 1;
+        let sent = match self.pool {
+            Some(ref pool) => pool.send.send((index, chunk)).is_ok(),
+            None => unreachable!(),
+        };
+        if !sent {
+            // Every thread has quit, which only happens when they failed.
+            return Err(From::from(match self.pool.take() {
+                Some(pool) => join_error(pool),
+                None => "shard builders quit".to_string(),
+            }));
+        }
+        Ok(())
+    }
+
+    fn start_pool(&self) -> Pool {
+        // A rendezvous channel, so that a chunk is only handed over when a
+        // thread is ready to build it and no extra chunks pile up.
+        let (send, recv) = mpsc::sync_channel::<Chunk>(0);
+        let recv = Arc::new(Mutex::new(recv));
+        let mut handles = vec![];
+        for _ in 0..self.threads {
+            let recv = recv.clone();
+            let dir = self.dir.clone();
+            let progress = self.progress.clone();
+            let is_map = self.is_map;
+            handles.push(thread::spawn(move || {
+                worker(&recv, &dir, is_map, &progress)
+            }));
+        }
+        Pool { send: send, handles: handles }
+    }
+}
+
+impl Drop for ShardedBuilder {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        // Wait for the threads first, so that none of them is still writing
+        // a shard when it's removed.
+        let _ = self.join();
+        for i in 0..self.shards {
+            let _ = fs::remove_file(shard_path(&self.dir, i));
+        }
+    }
+}
+
+/// Build chunks into shards until the channel is closed.
+fn worker(
+    recv: &Mutex<Receiver<Chunk>>,
+    dir: &Path,
+    is_map: bool,
+    progress: &Progress,
+) -> Result<()> {
+    loop {
+        let received = match recv.lock() {
+            Ok(recv) => recv.recv(),
+            Err(_) => return Ok(()),
+        };
+        let (index, chunk) = match received {
+            Ok(chunk) => chunk,
+            Err(_) => return Ok(()),
+        };
+        let file = File::create(shard_path(dir, index))?;
+        let wtr = BufWriter::new(CountingWriter {
+            wtr: file,
+            progress: progress,
+        });
+        let mut builder = Builder::new(wtr, is_map)?;
+        for (i, (key, value)) in chunk.into_iter().enumerate() {
+            builder.insert(&key, value)?;
+            if (i + 1) % PROGRESS_INTERVAL == 0 {
+                progress.keys.fetch_add(PROGRESS_INTERVAL, Ordering::Relaxed);
+            }
+        }
+        let len = builder.len();
+        builder.finish()?;
+        progress.keys.fetch_add(len % PROGRESS_INTERVAL, Ordering::Relaxed);
+        progress.shards.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Join every thread in the pool and return the first error any of them
+/// reported.
+fn join_error(pool: Pool) -> String {
+    drop(pool.send);
+    for handle in pool.handles {
+        match handle.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => return err.to_string(),
+            Err(_) => return "a shard builder panicked".to_string(),
+        }
+    }
+    "shard builders quit".to_string()
+}
+
+/// Concatenate shards holding disjoint, increasing ranges of keys (like those
+/// written by `ShardedBuilder`) into a single set or map written to `wtr`.
+///
+/// This fails if the shards are out of order or overlap.
+pub fn concat<P, W>(shards: &[P], wtr: W, is_map: bool) -> Result<()>
+where P: AsRef<Path>, W: Write {
+    let mut builder = Builder::new(wtr, is_map)?;
+    for path in shards {
+        let map = open_map(path)?;
+        let mut stream = map.stream();
+        while let Some((key, value)) = stream.next() {
+            builder.insert(key, value)?;
+        }
+    }
+    builder.finish()
+}
+
+/// Returns the path of the shard with the given index in `dir`. Zero padding
+/// keeps the shards in order when their names are sorted.
+fn shard_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("shard-{:06}.fst", index))
+}
+
+/// Returns the paths of the shards written to `dir` by
+/// `ShardedBuilder::finish`, in order.
+pub fn shard_paths<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_shard = path.file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| {
+                name.starts_with("shard-") && name.ends_with(".fst")
+            });
+        if is_shard {
+            paths.push(path);
+        }
+    }
+    // The indices are padded with zeros, so this sorts them numerically.
+    paths.sort();
+    Ok(paths)
+}
+
+/// The progress of a build, which is updated as shards are built.
+#[derive(Debug)]
+pub struct Progress {
+    start: Instant,
+    /// How long it took to build every shard, once they're all built.
+    finished: Mutex<Option<Duration>>,
+    keys: AtomicUsize,
+    bytes: AtomicUsize,
+    shards: AtomicUsize,
+}
+
+impl Progress {
+    fn new() -> Progress {
+        Progress {
+            start: Instant::now(),
+            finished: Mutex::new(None),
+            keys: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+            shards: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of keys inserted into shards so far.
+    pub fn keys(&self) -> u64 {
+        self.keys.load(Ordering::Relaxed) as u64
+    }
+
+    /// The number of bytes of shards written so far.
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed) as u64
+    }
+
+    /// The number of shards that are done.
+    pub fn shards(&self) -> u64 {
+        self.shards.load(Ordering::Relaxed) as u64
+    }
+
+    /// Returns true once every shard has been built.
+    pub fn is_finished(&self) -> bool {
+        self.finished.lock().map(|d| d.is_some()).unwrap_or(true)
+    }
+
+    /// The time since the builder was created, or the time it took to build
+    /// every shard once they're all built.
+    pub fn elapsed(&self) -> Duration {
+        if let Ok(finished) = self.finished.lock() {
+            if let Some(elapsed) = *finished {
+                return elapsed;
+            }
+        }
+        self.start.elapsed()
+    }
+
+    fn finish(&self) {
+        if let Ok(mut finished) = self.finished.lock() {
+            *finished = Some(self.start.elapsed());
+        }
+    }
+
+    /// The average number of keys inserted per second.
+    pub fn keys_per_sec(&self) -> f64 {
+        let elapsed = self.elapsed();
+        let secs = elapsed.as_secs() as f64
+            + elapsed.subsec_nanos() as f64 / 1e9;
+        if secs == 0.0 { 0.0 } else { self.keys() as f64 / secs }
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} keys ({:.0} keys/sec), {:.1} MB written, {} shards",
+               self.keys(), self.keys_per_sec(),
+               self.bytes() as f64 / (1 << 20) as f64, self.shards())
+    }
+}
+
+/// A writer that adds the number of bytes written to a `Progress`.
+struct CountingWriter<'a, W> {
+    wtr: W,
+    progress: &'a Progress,
+}
+
+impl<'a, W: Write> Write for CountingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.wtr.write(buf)?;
+        self.progress.bytes.fetch_add(n, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+/// A builder for either a set or a map.
+enum Builder<W> {
+    Set(SetBuilder<W>, usize),
+    Map(MapBuilder<W>, usize),
+}
+
+impl<W: Write> Builder<W> {
+    fn new(wtr: W, is_map: bool) -> Result<Builder<W>> {
+        Ok(if is_map {
+            Builder::Map(MapBuilder::new(wtr)?, 0)
+        } else {
+            Builder::Set(SetBuilder::new(wtr)?, 0)
+        })
+    }
+
+    fn insert(&mut self, key: &[u8], value: u64) -> Result<()> {
+        match *self {
+            Builder::Set(ref mut b, ref mut len) => {
+                b.insert(key)?;
+                *len += 1;
+            }
+            Builder::Map(ref mut b, ref mut len) => {
+                b.insert(key, value)?;
+                *len += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of keys inserted so far.
+    fn len(&self) -> usize {
+        match *self {
+            Builder::Set(_, len) | Builder::Map(_, len) => len,
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Builder::Set(b, _) => b.finish()?,
+            Builder::Map(b, _) => b.finish()?,
+        }
+        Ok(())
+    }
+}
+
+/// The default number of threads searching shards.
+const DEFAULT_SEARCH_THREADS: usize = 4;
+
+/// The most keys a thread fetches from a shard at once.
+const BATCH: usize = 256;
+
+/// A set stored as several shards, which may overlap, whose searches run on
+/// every shard in parallel.
+pub struct ShardedSet {
+    map: ShardedMap,
+}
+
+impl ShardedSet {
+    /// Open the set stored in shards in the files at `paths`, which are
+    /// memory mapped, and search them with `threads` threads.
+    pub fn open<P: AsRef<Path>>(
+        paths: &[P],
+        threads: usize,
+    ) -> Result<ShardedSet> {
+        Ok(ShardedSet { map: ShardedMap::open(paths, threads)? })
+    }
+
+    /// The number of shards.
+    pub fn shards(&self) -> usize {
+        self.map.shards()
+    }
+
+    /// Returns true if any shard contains `key`.
+    pub fn contains<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.map.get(key).is_some()
+    }
+
+    /// Search every shard with `aut`. Call `into_keys` on the result to get
+    /// the matching keys, in order and without duplicates.
+    pub fn search<A>(&self, aut: A) -> SearchBuilder<A>
+    where A: Automaton + Send + Sync + 'static {
+        self.map.search(aut)
+    }
+
+    /// Search every shard for a range of keys, which is every key unless
+    /// bounds are set on the result.
+    pub fn range(&self) -> SearchBuilder<AlwaysMatch> {
+        self.map.range()
+    }
+}
+
+/// A map stored as several shards, which may overlap, whose searches run on
+/// every shard in parallel.
+///
+/// When more than one shard has the same key, its values are combined with a
+/// `Reducer`. By default, the value from the first shard with the key wins.
+pub struct ShardedMap {
+    maps: Arc<Vec<Map>>,
+    pool: ThreadPool,
+    reducer: Reducer,
+}
+
+impl ShardedMap {
+    /// Open the map stored in shards in the files at `paths`, which are
+    /// memory mapped, and search them with `threads` threads.
+    pub fn open<P: AsRef<Path>>(
+        paths: &[P],
+        threads: usize,
+    ) -> Result<ShardedMap> {
+        let mut maps = vec![];
+        for path in paths {
+            maps.push(open_map(path)?);
+        }
+        Ok(ShardedMap::from_maps(maps, threads))
+    }
+
+    /// Search `maps` as the shards of one map with `threads` threads.
+    pub fn from_maps(maps: Vec<Map>, threads: usize) -> ShardedMap {
+        let threads =
+            if threads == 0 { DEFAULT_SEARCH_THREADS } else { threads };
+        ShardedMap {
+            maps: Arc::new(maps),
+            pool: ThreadPool::new(threads),
+            reducer: Reducer::First,
+        }
+    }
+
+    /// Set how the values of a key in more than one shard are combined.
+    pub fn reducer(&mut self, reducer: Reducer) -> &mut ShardedMap {
+        self.reducer = reducer;
+        self
+    }
+
+    /// The number of shards.
+    pub fn shards(&self) -> usize {
+        self.maps.len()
+    }
+
+    /// Returns the value of `key`, combined from every shard that has it.
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<u64> {
+        let key = key.as_ref();
+        let mut values = vec![];
+        for (i, map) in self.maps.iter().enumerate() {
+            if let Some(value) = map.get(key) {
+                values.push(IndexedValue { index: i, value: value });
+            }
+        }
+        if values.is_empty() {
+            None
+        } else {
+            Some(self.reducer.reduce(&values))
+        }
+    }
+
+    /// Search every shard with `aut`. Call `into_stream` on the result to
+    /// get the matching keys and their values, in order and without
+    /// duplicates.
+    pub fn search<A>(&self, aut: A) -> SearchBuilder<A>
+    where A: Automaton + Send + Sync + 'static {
+        SearchBuilder {
+            maps: self.maps.clone(),
+            jobs: self.pool.send.clone(),
+            reducer: self.reducer,
+            aut: aut,
+            bounds: Bounds::new(),
+            limit: None,
+        }
+    }
+
+    /// Search every shard for a range of keys, which is every key unless
+    /// bounds are set on the result.
+    pub fn range(&self) -> SearchBuilder<AlwaysMatch> {
+        self.search(AlwaysMatch)
+    }
+}
+
+/// A search of every shard, which can be restricted to a range of keys and
+/// a number of results before it's run.
+pub struct SearchBuilder<A> {
+    maps: Arc<Vec<Map>>,
+    jobs: Sender<Job>,
+    reducer: Reducer,
+    aut: A,
+    bounds: Bounds,
+    limit: Option<usize>,
+}
+
+impl<A: Automaton + Send + Sync + 'static> SearchBuilder<A> {
+    /// Only find keys greater than or equal to `bound`.
+    pub fn ge<K: AsRef<[u8]>>(mut self, bound: K) -> SearchBuilder<A> {
+        self.bounds.ge(bound.as_ref());
+        self
+    }
+
+    /// Only find keys greater than `bound`.
+    pub fn gt<K: AsRef<[u8]>>(mut self, bound: K) -> SearchBuilder<A> {
+        self.bounds.gt(bound.as_ref());
+        self
+    }
+
+    /// Only find keys less than or equal to `bound`.
+    pub fn le<K: AsRef<[u8]>>(mut self, bound: K) -> SearchBuilder<A> {
+        self.bounds.le(bound.as_ref());
+        self
+    }
+
+    /// Only find keys less than `bound`.
+    pub fn lt<K: AsRef<[u8]>>(mut self, bound: K) -> SearchBuilder<A> {
+        self.bounds.lt(bound.as_ref());
+        self
+    }
+
+    /// Stop after finding `n` keys. Shards are searched a batch of keys at
+    /// a time, so this also keeps the threads from searching much further
+    /// than the last key found.
+    pub fn limit(mut self, n: usize) -> SearchBuilder<A> {
+        self.limit = Some(n);
+        self
+    }
+
+    /// Start the search, and return a stream of keys and their values.
+    pub fn into_stream(self) -> Stream {
+        let (send, recv) = mpsc::channel();
+        let query = Arc::new(Search {
+            aut: self.aut,
+            bounds: self.bounds,
+        });
+        let shards = self.maps.len();
+        let (maps, jobs) = (self.maps, self.jobs);
+        let mut stream = Stream {
+            fetch: Box::new(move |shard, after, n| {
+                let (maps, query) = (maps.clone(), query.clone());
+                let send = send.clone();
+                let job: Job = Box::new(move || {
+                    // Always send something back, so that the stream never
+                    // waits for a batch that isn't coming.
+                    let batch = panic::catch_unwind(AssertUnwindSafe(|| {
+                        query.fetch(&maps[shard], after.as_ref(), n)
+                    }));
+                    let _ = send.send((shard, batch.ok()));
+                });
+                let _ = jobs.send(job);
+            }),
+            results: recv,
+            reducer: self.reducer,
+            buffers: (0..shards).map(|_| Buffer::new()).collect(),
+            heap: BinaryHeap::new(),
+            started: false,
+            remaining: self.limit,
+            key: vec![],
+            values: vec![],
+        };
+        for shard in 0..shards {
+            stream.request(shard);
+        }
+        stream
+    }
+
+    /// Start the search, and return a stream of keys.
+    pub fn into_keys(self) -> Keys {
+        Keys(self.into_stream())
+    }
+}
+
+/// A search and the bounds it's restricted to, shared by every job fetching
+/// a batch of its results.
+struct Search<A> {
+    aut: A,
+    bounds: Bounds,
+}
+
+impl<A: Automaton> Search<A> {
+    /// Returns at most `n` results from `map` that come after `after`, or
+    /// from the start of the search if `after` is `None`. The batch is the
+    /// last one if there are no more results after it.
+    fn fetch(&self, map: &Map, after: Option<&Vec<u8>>, n: usize) -> Batch {
+        let after = after.map(|key| &**key);
+        let mut stream = self.bounds.search(map.as_fst(), &self.aut, after);
+        let mut batch = Batch { entries: VecDeque::new(), last: false };
+        while batch.entries.len() < n {
+            match stream.next() {
+                Some((key, out)) => {
+                    batch.entries.push_back((key.to_vec(), out.value()));
+                }
+                None => {
+                    batch.last = true;
+                    break;
+                }
+            }
+        }
+        batch
+    }
+}
+
+/// Some consecutive results of a search of one shard.
+struct Batch {
+    entries: VecDeque<(Vec<u8>, u64)>,
+    last: bool,
+}
+
+/// The results fetched from one shard that haven't been merged yet.
+struct Buffer {
+    entries: VecDeque<(Vec<u8>, u64)>,
+    /// The last key fetched, where the next batch starts.
+    after: Option<Vec<u8>>,
+    /// Whether a batch has been requested and hasn't arrived yet.
+    pending: bool,
+    /// Whether every result has been fetched.
+    done: bool,
+}
+
+impl Buffer {
+    fn new() -> Buffer {
+        Buffer {
+            entries: VecDeque::new(),
+            after: None,
+            pending: false,
+            done: false,
+        }
+    }
+}
+
+/// Starts a job that fetches a batch of at most `n` results from the shard
+/// with the given index, after the given key.
+type Fetch = Box<Fn(usize, Option<Vec<u8>>, usize) + Send>;
+
+/// The merged results of a search of every shard.
+///
+/// Each shard's results are fetched in batches by the thread pool, at most
+/// one batch per shard at a time, and the next batch is requested as soon as
+/// half of the last one has been merged. So there are never more than about
+/// one and a half batches per shard in memory, and dropping the stream stops
+/// the search after the batches being fetched.
+///
+/// If searching a shard panics, `next` returns an error, and the stream ends
+/// there rather than return incomplete results.
+pub struct Stream {
+    fetch: Fetch,
+    results: Receiver<(usize, Option<Batch>)>,
+    reducer: Reducer,
+    buffers: Vec<Buffer>,
+    /// The smallest unmerged key from each shard with one.
+    heap: BinaryHeap<Reverse<(Vec<u8>, usize)>>,
+    started: bool,
+    remaining: Option<usize>,
+    key: Vec<u8>,
+    values: Vec<IndexedValue>,
+}
+
+impl Stream {
+    /// Request the next batch from `shard`, unless one is already on its way
+    /// or there aren't any more.
+    fn request(&mut self, shard: usize) {
+        let buf = &mut self.buffers[shard];
+        if buf.pending || buf.done {
+            return;
+        }
+        buf.pending = true;
+        let n = match self.remaining {
+            None => BATCH,
+            // A few more than the limit, in case some are duplicates.
+            Some(remaining) => cmp::min(BATCH, remaining.saturating_add(1)),
+        };
+        (self.fetch)(shard, buf.after.clone(), n);
+    }
+
+    /// Wait until `shard` has an unmerged result or has no more, adding every
+    /// batch that arrives in the meantime to its shard's buffer.
+    fn wait(&mut self, shard: usize) -> Result<()> {
+        while self.buffers[shard].entries.is_empty()
+            && !self.buffers[shard].done
+        {
+            let (i, batch) = match self.results.recv() {
+                Ok(result) => result,
+                Err(_) => return Err(From::from("search threads stopped")),
+            };
+            let batch = match batch {
+                Some(batch) => batch,
+                None => {
+                    return Err(From::from(format!(
+                        "searching shard {} panicked", i)));
+                }
+            };
+            let buf = &mut self.buffers[i];
+            buf.pending = false;
+            buf.done = batch.last;
+            if let Some(&(ref key, _)) = batch.entries.back() {
+                buf.after = Some(key.clone());
+            }
+            buf.entries.extend(batch.entries);
+        }
+        Ok(())
+    }
+
+    /// Put the smallest unmerged key of `shard` on the heap, fetching more
+    /// results first if needed.
+    fn refill(&mut self, shard: usize) -> Result<()> {
+        if self.buffers[shard].entries.len() <= BATCH / 2 {
+            self.request(shard);
+        }
+        if let Err(err) = self.wait(shard) {
+            // A batch is missing, so end the stream here.
+            self.remaining = Some(0);
+            return Err(err);
+        }
+        if let Some(&(ref key, _)) = self.buffers[shard].entries.front() {
+            self.heap.push(Reverse((key.clone(), shard)));
+        }
+        Ok(())
+    }
+
+    /// Returns the next key and its value, or `None` once every key has been
+    /// returned.
+    pub fn next(&mut self) -> Result<Option<(&[u8], u64)>> {
+        if self.remaining == Some(0) {
+            return Ok(None);
+        }
+        if !self.started {
+            self.started = true;
+            for shard in 0..self.buffers.len() {
+                self.refill(shard)?;
+            }
+        }
+        let Reverse((key, shard)) = match self.heap.pop() {
+            None => return Ok(None),
+            Some(head) => head,
+        };
+        self.key = key;
+        self.values.clear();
+        let mut next = Some(shard);
+        while let Some(shard) = next {
+            let (_, value) = self.buffers[shard].entries.pop_front()
+                .expect("shard on the heap has a result");
+            self.values.push(IndexedValue { index: shard, value: value });
+            self.refill(shard)?;
+            next = match self.heap.peek() {
+                Some(&Reverse((ref key, shard))) if *key == self.key => {
+                    Some(shard)
+                }
+                _ => None,
+            };
+            if next.is_some() {
+                self.heap.pop();
+            }
+        }
+        if let Some(ref mut remaining) = self.remaining {
+            *remaining -= 1;
+        }
+        Ok(Some((&self.key, self.reducer.reduce(&self.values))))
+    }
+}
+
+/// The keys found by a search of every shard of a set.
+pub struct Keys(Stream);
+
+impl Keys {
+    /// Returns the next key, or `None` once every key has been returned.
+    pub fn next(&mut self) -> Result<Option<&[u8]>> {
+        Ok(self.0.next()?.map(|(key, _)| key))
+    }
+}
+
+/// A unit of work for a `ThreadPool`.
+type Job = Box<FnOnce() + Send>;
+
+/// A fixed number of threads that run jobs until the pool is dropped.
+struct ThreadPool {
+    send: Sender<Job>,
+}
+
+impl ThreadPool {
+    fn new(threads: usize) -> ThreadPool {
+        let (send, recv) = mpsc::channel::<Job>();
+        let recv = Arc::new(Mutex::new(recv));
+        for _ in 0..threads {
+            let recv = recv.clone();
+            thread::spawn(move || loop {
+                let job = match recv.lock() {
+                    Ok(recv) => recv.recv(),
+                    Err(_) => return,
+                };
+                match job {
+                    Ok(job) => job(),
+                    Err(_) => return,
+                }
+            });
+        }
+        ThreadPool { send: send }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fst::{Automaton, Map};
+
+    use super::ShardedMap;
+
+    fn map(keys: &[&str], value: u64) -> Map {
+        Map::from_iter(keys.iter().map(|k| (k, value))).unwrap()
+    }
+
+    /// Matches every key, but panics on the given byte.
+    struct PanicOn(u8);
+
+    impl Automaton for PanicOn {
+        type State = ();
+
+        fn start(&self) {}
+
+        fn is_match(&self, _: &()) -> bool {
+            true
+        }
+
+        fn accept(&self, _: &(), byte: u8) {
+            if byte == self.0 {
+                panic!("byte {}", byte);
+            }
+        }
+    }
+
+    #[test]
+    fn merged() {
+        let maps = vec![map(&["a", "c", "e"], 1), map(&["b", "c", "f"], 2)];
+        let sharded = ShardedMap::from_maps(maps, 2);
+        let mut stream = sharded.range().gt("a").into_stream();
+        let mut found = vec![];
+        while let Some((key, value)) = stream.next().unwrap() {
+            found.push((String::from_utf8(key.to_vec()).unwrap(), value));
+        }
+        let expected: Vec<(String, u64)> =
+            vec![("b", 2), ("c", 1), ("e", 1), ("f", 2)].into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn panicked() {
+        let maps = vec![map(&["a", "b"], 1), map(&["x", "y"], 2)];
+        let sharded = ShardedMap::from_maps(maps, 2);
+        let mut stream = sharded.search(PanicOn(b'y')).into_stream();
+        let err = stream.next().unwrap_err();
+        assert_eq!(err.to_string(), "searching shard 1 panicked");
+        assert!(stream.next().unwrap().is_none());
+    }
+}
@@ -0,0 +1,106 @@
+/*!
+Measurements of the structure of an FST.
+
+These are the kinds of numbers quoted by hand in the "Experiments" section of
+the blog post: how many states and transitions the FST has, how the
+transitions are distributed, how long the keys are and how many bytes each key
+costs.
+*/
+
+use fst::Streamer;
+use fst::raw::Fst;
+
+use states;
+
+/// Statistics about a single FST.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    /// The number of keys.
+    pub keys: u64,
+    /// The number of states reachable from the root.
+    pub states: u64,
+    /// The number of final states.
+    pub final_states: u64,
+    /// The total number of transitions.
+    pub transitions: u64,
+    /// `out_degrees[d]` is the number of states with exactly `d` transitions.
+    pub out_degrees: Vec<u64>,
+    /// The length, in bytes, of the longest key.
+    pub max_key_len: u64,
+    /// The sum of the lengths, in bytes, of all keys.
+    pub total_key_len: u64,
+    /// The size of the FST, in bytes.
+    pub size: u64,
+}
+
+impl Stats {
+    /// Compute statistics for the given FST.
+    ///
+    /// This visits every state once and streams every key once, so it takes
+    /// time proportional to the size of the FST plus the total length of its
+    /// keys.
+    pub fn new(fst: &Fst) -> Stats {
+        let mut stats = Stats {
+            keys: fst.len() as u64,
+            size: fst.size() as u64,
+            out_degrees: vec![0; 257],
+            ..Stats::default()
+        };
+        for addr in states(fst) {
+            let node = fst.node(addr);
+            stats.states += 1;
+            stats.transitions += node.len() as u64;
+            stats.out_degrees[node.len()] += 1;
+            if node.is_final() {
+                stats.final_states += 1;
+            }
+        }
+        while stats.out_degrees.last() == Some(&0) {
+            stats.out_degrees.pop();
+        }
+
+        let mut stream = fst.stream();
+        while let Some((key, _)) = stream.next() {
+            let len = key.len() as u64;
+            stats.total_key_len += len;
+            if len > stats.max_key_len {
+                stats.max_key_len = len;
+            }
+        }
+        stats
+    }
+
+    /// The fraction of states that are final.
+    pub fn final_ratio(&self) -> f64 {
+        ratio(self.final_states, self.states)
+    }
+
+    /// The average number of transitions out of each state.
+    pub fn avg_out_degree(&self) -> f64 {
+        ratio(self.transitions, self.states)
+    }
+
+    /// The average length of a key, in bytes.
+    pub fn avg_key_len(&self) -> f64 {
+        ratio(self.total_key_len, self.keys)
+    }
+
+    /// The average number of bytes in the FST used by each key.
+    pub fn bytes_per_key(&self) -> f64 {
+        ratio(self.size, self.keys)
+    }
+
+    /// The size of the FST relative to the size of its input, e.g., `0.294`
+    /// for the dictionary in the blog post.
+    pub fn compression_ratio(&self, input_size: u64) -> f64 {
+        ratio(self.size, input_size)
+    }
+}
+
+fn ratio(a: u64, b: u64) -> f64 {
+    if b == 0 {
+        0.0
+    } else {
+        a as f64 / b as f64
+    }
+}
@@ -0,0 +1,387 @@
+/*!
+Subsequence ("fzf style") fuzzy matching.
+
+A `Subsequence` automaton matches every key that contains the characters of
+the query in order, with anything in between. So `brsp` matches `bruce
+springsteen`, and so do a lot of other keys. With `word_starts`, every run of
+consecutive matched characters must begin at the start of a word, which
+filters out most accidental matches while still finding acronyms (`bs`) and
+abbreviations (`brsp`).
+
+Since so many keys match, results usually need to be ranked.
+`Subsequence::rank` finds where the query matches in each key and hands those
+positions to a `Scorer`, keeping only the best results. `DefaultScorer`
+prefers matches at the start of words, consecutive matches and short gaps,
+but any closure with the right signature works too.
+*/
+
+use std::cmp;
+use std::str;
+
+use fst::{Automaton, Streamer};
+
+use normalize::simple_fold;
+use topk::TopK;
+use utf8::{Decoded, Decoder};
+
+/// An automaton that matches keys containing the query as a subsequence.
+#[derive(Clone, Debug)]
+pub struct Subsequence {
+    query: Vec<char>,
+    word_starts: bool,
+    ignore_case: bool,
+}
+
+impl Subsequence {
+    /// Create an automaton matching every key that contains the characters
+    /// of `query` in order. Matching is case sensitive by default.
+    pub fn new(query: &str) -> Subsequence {
+        Subsequence {
+            query: query.chars().collect(),
+            word_starts: false,
+            ignore_case: false,
+        }
+    }
+
+    /// When enabled, every run of consecutive matched characters must start
+    /// at the beginning of a word. A word starts at the beginning of a key or
+    /// after any character that isn't alphanumeric.
+    pub fn word_starts(mut self, yes: bool) -> Subsequence {
+        self.word_starts = yes;
+        self
+    }
+
+    /// When enabled, characters are compared after simple case folding.
+    pub fn ignore_case(mut self, yes: bool) -> Subsequence {
+        self.ignore_case = yes;
+        self
+    }
+
+    /// Returns the byte offset in `key` of each matched query character, or
+    /// `None` if `key` doesn't match.
+    ///
+    /// When there's more than one way to match, this picks the one with the
+    /// most matches at word starts and consecutive matches, preferring
+    /// earlier matches among equals.
+    pub fn positions(&self, key: &[u8]) -> Option<Vec<usize>> {
+        str::from_utf8(key).ok().and_then(|key| self.align(key))
+    }
+
+    /// Score every key in `stream` that this automaton matches and return
+    /// the `limit` best as `(key, score)` pairs, in descending order of
+    /// score. Ties are broken by the lexicographic order of keys.
+    ///
+    /// The stream is typically `map.search(subsequence).into_stream()`. A
+    /// set can be searched the same way by opening it as a map, in which
+    /// case every value is zero.
+    pub fn rank<S, F>(
+        &self,
+        mut stream: S,
+        scorer: &F,
+        limit: usize,
+    ) -> Vec<(Vec<u8>, u64)>
+    where S: for<'a> Streamer<'a, Item=(&'a [u8], u64)>, F: Scorer {
+        let mut top = TopK::new(limit);
+        while let Some((key, value)) = stream.next() {
+            let text = match str::from_utf8(key) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+            if let Some(positions) = self.align(text) {
+                top.push(key, scorer.score(text, value, &positions));
+            }
+        }
+        top.into_sorted_vec()
+    }
+
+    fn fold(&self, c: char) -> char {
+        if self.ignore_case { simple_fold(c) } else { c }
+    }
+
+    fn matches(&self, i: usize, c: char) -> bool {
+        self.fold(self.query[i]) == c
+    }
+
+    /// Find the best alignment of the query in `key` with a dynamic program
+    /// over (query character, key character) pairs.
+    fn align(&self, key: &str) -> Option<Vec<usize>> {
+        let n = self.query.len();
+        if n == 0 {
+            return Some(vec![]);
+        }
+        let chars: Vec<(usize, char)> = key.char_indices().collect();
+        let m = chars.len();
+        // `best[i][j]` is the highest bonus of any alignment of the first
+        // `i + 1` query characters in which the last one matches `chars[j]`,
+        // and `from[i][j]` is where the previous query character matched.
+        let mut best: Vec<Vec<Option<u32>>> = vec![vec![None; m]; n];
+        let mut from: Vec<Vec<usize>> = vec![vec![0; m]; n];
+        // `far[i]` is the best of `best[i][..j - 1]`, i.e., the best place
+        // for query character `i` to match if character `i + 1` matches
+        // `chars[j]` without being adjacent to it.
+        let mut far: Vec<Option<(u32, usize)>> = vec![None; n];
+        for j in 0..m {
+            if j >= 2 {
+                for i in 0..n {
+                    if let Some(b) = best[i][j - 2] {
+                        if far[i].map_or(true, |(f, _)| b > f) {
+                            far[i] = Some((b, j - 2));
+                        }
+                    }
+                }
+            }
+            let c = self.fold(chars[j].1);
+            let start = j == 0 || !chars[j - 1].1.is_alphanumeric();
+            let bonus = if start { 2 } else { 0 };
+            let may_jump = start || !self.word_starts;
+            for i in 0..n {
+                if !self.matches(i, c) {
+                    continue;
+                }
+                if i == 0 {
+                    if may_jump {
+                        best[i][j] = Some(bonus);
+                    }
+                    continue;
+                }
+                let mut cur: Option<(u32, usize)> = None;
+                if j >= 1 {
+                    if let Some(b) = best[i - 1][j - 1] {
+                        cur = Some((b + bonus + 1, j - 1));
+                    }
+                }
+                if may_jump {
+                    if let Some((f, p)) = far[i - 1] {
+                        if cur.map_or(true, |(b, _)| f + bonus > b) {
+                            cur = Some((f + bonus, p));
+                        }
+                    }
+                }
+                if let Some((b, p)) = cur {
+                    best[i][j] = Some(b);
+                    from[i][j] = p;
+                }
+            }
+        }
+
+        let mut end: Option<(u32, usize)> = None;
+        for j in 0..m {
+            if let Some(b) = best[n - 1][j] {
+                if end.map_or(true, |(e, _)| b > e) {
+                    end = Some((b, j));
+                }
+            }
+        }
+        let mut j = match end {
+            None => return None,
+            Some((_, j)) => j,
+        };
+        let mut positions = vec![0; n];
+        for i in (0..n).rev() {
+            positions[i] = chars[j].0;
+            j = from[i][j];
+        }
+        Some(positions)
+    }
+}
+
+/// The state of a `Subsequence` automaton. `None` means the key isn't valid
+/// UTF-8.
+pub type State = Option<SubsequenceState>;
+
+/// The progress of a `Subsequence` automaton through a valid UTF-8 key.
+#[derive(Clone, Debug)]
+pub struct SubsequenceState {
+    /// The length of the longest prefix of the query that has matched.
+    matched: usize,
+    /// With `word_starts`, every `i` such that the first `i` characters of
+    /// the query can match with the last one on the previous character.
+    /// These are the only places a match may continue without a word start.
+    adjacent: Vec<usize>,
+    /// Whether the next character starts a word.
+    at_start: bool,
+    dec: Decoder,
+}
+
+impl Automaton for Subsequence {
+    type State = State;
+
+    fn start(&self) -> State {
+        Some(SubsequenceState {
+            matched: 0,
+            adjacent: vec![],
+            at_start: true,
+            dec: Decoder::default(),
+        })
+    }
+
+    fn is_match(&self, state: &State) -> bool {
+        match *state {
+            None => false,
+            Some(ref s) => {
+                s.dec.is_boundary() && s.matched == self.query.len()
+            }
+        }
+    }
+
+    fn can_match(&self, state: &State) -> bool {
+        state.is_some()
+    }
+
+    fn accept(&self, state: &State, byte: u8) -> State {
+        let s = match *state {
+            None => return None,
+            Some(ref s) => s,
+        };
+        let c = match s.dec.push(byte) {
+            Decoded::Invalid => return None,
+            Decoded::Incomplete(dec) => {
+                return Some(SubsequenceState { dec: dec, ..s.clone() });
+            }
+            Decoded::Char(c) => self.fold(c),
+        };
+        let mut next = SubsequenceState {
+            matched: s.matched,
+            adjacent: vec![],
+            at_start: !c.is_alphanumeric(),
+            dec: Decoder::default(),
+        };
+        if !self.word_starts {
+            if s.matched < self.query.len() && self.matches(s.matched, c) {
+                next.matched += 1;
+            }
+            return Some(next);
+        }
+        // Any prefix no longer than `matched` can be extended by `c`, as
+        // long as `c` starts a word or continues a run.
+        for i in 0..cmp::min(s.matched + 1, self.query.len()) {
+            if !self.matches(i, c) {
+                continue;
+            }
+            if s.at_start || s.adjacent.contains(&i) {
+                next.adjacent.push(i + 1);
+                next.matched = cmp::max(next.matched, i + 1);
+            }
+        }
+        Some(next)
+    }
+}
+
+/// Assigns a score to a key matched by a `Subsequence`. Higher is better.
+pub trait Scorer {
+    /// Score `key`, whose value in the map is `value`, given the byte offset
+    /// of each matched query character.
+    fn score(&self, key: &str, value: u64, positions: &[usize]) -> u64;
+}
+
+impl<F: Fn(&str, u64, &[usize]) -> u64> Scorer for F {
+    fn score(&self, key: &str, value: u64, positions: &[usize]) -> u64 {
+        self(key, value, positions)
+    }
+}
+
+/// A scorer in the spirit of fzf. Each matched character is worth 16
+/// points, plus 8 if it starts a word and 8 if it immediately follows the
+/// previous match. One point is taken off for every byte skipped before the
+/// last match. The value in the map is ignored.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultScorer;
+
+impl Scorer for DefaultScorer {
+    fn score(&self, key: &str, _value: u64, positions: &[usize]) -> u64 {
+        let mut score = 0u64;
+        let mut skipped = 0;
+        let mut prev_end = 0;
+        for (i, &p) in positions.iter().enumerate() {
+            score += 16;
+            let before = key[..p].chars().next_back();
+            if before.map_or(true, |c| !c.is_alphanumeric()) {
+                score += 8;
+            }
+            if i > 0 && p == prev_end {
+                score += 8;
+            }
+            skipped += p - prev_end;
+            prev_end = p + key[p..].chars().next().map_or(0, |c| c.len_utf8());
+        }
+        score.saturating_sub(skipped as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fst::{IntoStreamer, Map};
+
+    use matches;
+    use super::{DefaultScorer, Scorer, Subsequence};
+
+    fn positions(query: &str, key: &str) -> Option<Vec<usize>> {
+        Subsequence::new(query).positions(key.as_bytes())
+    }
+
+    #[test]
+    fn align() {
+        assert_eq!(positions("bs", "bruce springsteen"), Some(vec![0, 6]));
+        assert_eq!(positions("brsp", "bruce springsteen"),
+                   Some(vec![0, 1, 6, 7]));
+        assert_eq!(positions("abc", "xabc"), Some(vec![1, 2, 3]));
+        // Two word starts beat a consecutive match inside a word.
+        assert_eq!(positions("fb", "xfb foo bar"), Some(vec![4, 8]));
+        // Among equals, the earliest wins.
+        assert_eq!(positions("ab", "ab ab"), Some(vec![0, 1]));
+        // Positions are byte offsets.
+        assert_eq!(positions("éb", "aé b"), Some(vec![1, 4]));
+        assert_eq!(positions("", "abc"), Some(vec![]));
+        assert_eq!(positions("ba", "ab"), None);
+        assert_eq!(positions("a", ""), None);
+        assert_eq!(Subsequence::new("a").positions(b"a\xFF"), None);
+
+        let sub = Subsequence::new("BS").ignore_case(true);
+        assert_eq!(sub.positions(b"bruce Springsteen"), Some(vec![0, 6]));
+        let sub = Subsequence::new("ru").word_starts(true);
+        assert_eq!(sub.positions(b"bruce"), None);
+        assert_eq!(sub.positions(b"bruce r-us"), Some(vec![6, 8]));
+    }
+
+    #[test]
+    fn automaton_agrees_with_align() {
+        let keys = [
+            "", "a", "ab", "ba", "abab", "a b", "b-a-b", "aab", "ab ba",
+            "xaxb", "x ab", "xa b", "bb aa", "a_b", "é a", "ab\u{0}",
+        ];
+        let queries = ["", "a", "ab", "ba", "aa", "bab", "aba"];
+        for query in &queries {
+            for &word_starts in &[false, true] {
+                let sub = Subsequence::new(query).word_starts(word_starts);
+                for key in &keys {
+                    assert_eq!(matches(&sub, key.as_bytes()),
+                               sub.positions(key.as_bytes()).is_some(),
+                               "{:?} in {:?}, word starts: {}",
+                               query, key, word_starts);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scorer() {
+        let score = |key: &str| {
+            let positions = positions("bs", key).unwrap();
+            DefaultScorer.score(key, 0, &positions)
+        };
+        // Consecutive and word start matches beat scattered ones.
+        assert!(score("bs") > score("bar sun"));
+        assert!(score("bar sun") > score("abxxs"));
+        assert!(score("xbs") > score("xbxs"));
+        assert!(score("abxxs") > score("axbxxxs"));
+
+        let keys = ["axbxxs", "bar sun", "bs", "xbxxxxs"];
+        let map = Map::from_iter(keys.iter().map(|k| (k, 0))).unwrap();
+        let sub = Subsequence::new("bs");
+        let ranked = sub.rank(map.search(sub.clone()).into_stream(),
+                              &DefaultScorer, 3);
+        let ranked: Vec<&[u8]> =
+            ranked.iter().map(|&(ref key, _)| &key[..]).collect();
+        assert_eq!(ranked, [&b"bs"[..], b"bar sun", b"axbxxs"]);
+    }
+}
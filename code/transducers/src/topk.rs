@@ -0,0 +1,79 @@
+/*!
+Keeping only the best scoring keys from a stream.
+*/
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use fst::Streamer;
+
+/// Keeps the `k` best scoring keys seen so far.
+pub struct TopK {
+    k: usize,
+    // Wrapped in `Reverse` so that the worst result is at the top and can be
+    // evicted cheaply.
+    heap: BinaryHeap<Reverse<Candidate>>,
+}
+
+impl TopK {
+    pub fn new(k: usize) -> TopK {
+        TopK { k: k, heap: BinaryHeap::with_capacity(k + 1) }
+    }
+
+    pub fn extend<S>(&mut self, mut stream: S)
+    where S: for<'a> Streamer<'a, Item=(&'a [u8], u64)> {
+        while let Some((key, score)) = stream.next() {
+            self.push(key, score);
+        }
+    }
+
+    /// Offer a key with the given score. Keys must be pushed in lexicographic
+    /// order.
+    pub fn push(&mut self, key: &[u8], score: u64) {
+        if self.k == 0 {
+            return;
+        }
+        if self.heap.len() == self.k {
+            // Keys arrive in lexicographic order, so a key whose score is no
+            // better than the worst one we have can never beat it. This check
+            // avoids copying the key in the common case.
+            let worst = &(self.heap.peek().unwrap().0);
+            if score <= worst.score {
+                return;
+            }
+        }
+        let candidate = Candidate { score: score, key: key.to_vec() };
+        self.heap.push(Reverse(candidate));
+        if self.heap.len() > self.k {
+            self.heap.pop();
+        }
+    }
+
+    pub fn into_sorted_vec(self) -> Vec<(Vec<u8>, u64)> {
+        // Sorting `Reverse<Candidate>` ascending puts the best one first.
+        self.heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(c)| (c.key, c.score))
+            .collect()
+    }
+}
+
+/// A single result. Candidates with a higher score are greater. Among equal
+/// scores, the lexicographically smaller key is greater.
+#[derive(Eq, PartialEq)]
+struct Candidate {
+    score: u64,
+    key: Vec<u8>,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        self.score.cmp(&other.score).then_with(|| other.key.cmp(&self.key))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
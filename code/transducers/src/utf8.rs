@@ -0,0 +1,66 @@
+/*!
+Incremental UTF-8 decoding for automata that work on codepoints.
+
+FSTs feed automata one byte at a time, but some queries (edit distance, case
+insensitivity) are naturally defined over codepoints. `Decoder` is a small
+`Copy` value that can live inside an automaton's state and turns a sequence
+of bytes back into codepoints.
+*/
+
+/// The state of decoding a single codepoint.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Decoder {
+    partial: u32,
+    remaining: u8,
+}
+
+/// The result of giving a byte to a `Decoder`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Decoded {
+    /// The byte completed a codepoint. The decoder to use for the next byte
+    /// is `Decoder::default()`.
+    Char(char),
+    /// More bytes are needed. Use the given decoder for the next byte.
+    Incomplete(Decoder),
+    /// The bytes given so far aren't valid UTF-8.
+    Invalid,
+}
+
+impl Decoder {
+    /// Returns true if this decoder is between codepoints, i.e., every byte
+    /// given to it so far has been decoded.
+    pub fn is_boundary(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Feed the next byte to this decoder.
+    pub fn push(self, byte: u8) -> Decoded {
+        if self.remaining == 0 {
+            let (partial, remaining) = match byte {
+                0x00..=0x7F => return Decoded::Char(byte as char),
+                0xC2..=0xDF => (byte & 0x1F, 1),
+                0xE0..=0xEF => (byte & 0x0F, 2),
+                0xF0..=0xF4 => (byte & 0x07, 3),
+                _ => return Decoded::Invalid,
+            };
+            return Decoded::Incomplete(Decoder {
+                partial: partial as u32,
+                remaining: remaining,
+            });
+        }
+        if byte & 0xC0 != 0x80 {
+            return Decoded::Invalid;
+        }
+        let partial = (self.partial << 6) | (byte & 0x3F) as u32;
+        if self.remaining > 1 {
+            return Decoded::Incomplete(Decoder {
+                partial: partial,
+                remaining: self.remaining - 1,
+            });
+        }
+        match ::std::char::from_u32(partial) {
+            None => Decoded::Invalid,
+            Some(c) => Decoded::Char(c),
+        }
+    }
+}
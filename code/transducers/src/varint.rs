@@ -0,0 +1,76 @@
+/*!
+LEB128 varints, as used by the postings of an inverted index and the values
+of a key-value store.
+
+A varint stores an unsigned integer seven bits at a time, least significant
+first, with the high bit of each byte set on every byte but the last. Small
+numbers, which is most of them, take one byte.
+*/
+
+use Result;
+
+/// Append `n` to `buf`.
+pub fn write(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+/// Read a varint from the start of `buf`, and advance `buf` past it.
+pub fn read(buf: &mut &[u8]) -> Result<u64> {
+    let mut n = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = match buf.first() {
+            None => return Err(From::from("corrupt varint: truncated")),
+            Some(&byte) => byte,
+        };
+        *buf = &buf[1..];
+        // The tenth byte can only hold the highest bit of a u64.
+        if shift > 63 || (shift == 63 && byte > 1) {
+            return Err(From::from("corrupt varint: more than 64 bits"));
+        }
+        n |= ((byte & 0x7F) as u64) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, write};
+
+    #[test]
+    fn roundtrip() {
+        let mut buf = vec![];
+        let ns = [0, 1, 127, 128, 300, 1 << 35, u64::max_value() - 1,
+                  u64::max_value()];
+        for &n in &ns {
+            write(&mut buf, n);
+        }
+        assert_eq!(buf[..4], [0, 1, 127, 0x80]);
+        let mut rdr = &buf[..];
+        for &n in &ns {
+            assert_eq!(read(&mut rdr).unwrap(), n);
+        }
+        assert!(rdr.is_empty());
+    }
+
+    #[test]
+    fn corrupt() {
+        assert!(read(&mut &[][..]).is_err());
+        assert!(read(&mut &[0x80, 0x80][..]).is_err());
+        // Ten bytes, the last with more than one bit.
+        let mut big = vec![0xFF; 9];
+        big.push(0x02);
+        assert!(read(&mut &big[..]).is_err());
+        // Eleven bytes.
+        let mut long = vec![0x80; 10];
+        long.push(0);
+        assert!(read(&mut &long[..]).is_err());
+    }
+}